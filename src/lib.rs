@@ -0,0 +1,3 @@
+mod neoshell;
+
+pub use self::neoshell::*;
@@ -1,52 +1,323 @@
+//! Neoshell script runner : `neoshell <script> [args...]`, or interactive
+//! shell when no script is given.
+//!
+//! The script is read from the given file, or from the standard input if the
+//! file is `-`. As `#` starts a comment, scripts can begin with a
+//! `#!/usr/bin/env neoshell` line. The script receives its path in `$script`
+//! and its arguments in `$args`, as an array of strings.
+//!
+//! The exit code is the integer given to a top level `return`, or 0. Scripts
+//! which can't be read, parsed or compiled exit with 2, and failing scripts
+//! exit with the code of the failed program, 128 plus the signal for
+//! interrupted scripts, or 1.
+//!
+//! The interactive shell reads commands with line editing, and keeps their
+//! history in `~/.neoshell_history`. An input continues onto the next line
+//! while it is incomplete, and all the inputs share the same VM and root
+//! scope. Errors are shown and the shell carries on, until the end of the
+//! input (`Ctrl-D`).
 
-mod neoshell;
-
+use neoshell::builtins::*;
 use neoshell::parser::*;
+use neoshell::tools::*;
 use neoshell::vm::*;
 
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process;
+
 use failure::Error;
+use peg::error::ParseError;
+use peg::str::LineCol;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
 
 
-const SRC: &'static str = r#"
 
-puts "hello world";
-puts "this is from the script";
-puts "wooooohoooooo !!!";
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
-"#;
+    let code = match args.split_first() {
+        Some((script, args)) => run_script(script, args),
+        None => run_repl(),
+    };
 
-#[macro_use] extern crate failure;
+    process::exit(code);
+}
 
 
+/// Runs a script with the given arguments, and returns its exit code.
+fn run_script(script: &str, args: &[String]) -> i32 {
+    let src = match read_script(script) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {}", script, e);
+            return 2;
+        },
+    };
+    let ast = match ns_parser::file(&src) {
+        Ok(a) => a,
+        Err(e) => {
+            report(script, &src, vec![parse_diagnostic(&e)]);
+            return 2;
+        },
+    };
 
-struct PutsCommand { }
-impl RuntimeCommand for PutsCommand {
-    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<AstArgument, Error>
-    {
-        assert_eq!(cmd.arguments.len(), 1);
+    let mut vm = match new_vm() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("neoshell: {}", e);
+            return 2;
+        },
+    };
+    let mut scp = Scope::new();
+    let args = args.iter().map(|a| Value::Str(a.clone())).collect();
 
-        match &cmd.arguments[0] {
-            AstArgument::String(s) => println!("{}", s),
-            _ => panic!("invalid arg"),
+    scp.declare("script", Variable::Value(Value::Str(script.to_owned())));
+    scp.declare("args", Variable::Value(Value::Array(Type::Str, args)));
+
+    let prog = vm.compile(&mut scp, ast);
+    report(script, &src, vm.take_diagnostics());
+    let prog = match prog {
+        Ok(p) => p,
+        Err(e) => {
+            report(script, &src, vec![Diagnostic::new_error(e.to_string()).with_span(vm.take_error_span())]);
+            return 2;
+        },
+    };
+
+    let r = vm.run_script(&mut scp, &prog);
+    report(script, &src, vm.take_diagnostics());
+
+    match r {
+        Ok(v) => v.and_then(|v| v.get_integer()).map_or(0, |c| c as i32),
+        Err(e) => {
+            report(script, &src, vec![Diagnostic::new_error(e.to_string()).with_span(vm.take_error_span())]);
+            exit_code_of(&e)
+        },
+    }
+}
+
+/// Runs the interactive shell until the end of the input, and returns its
+/// exit code.
+fn run_repl() -> i32 {
+    let mut vm = match new_vm() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("neoshell: {}", e);
+            return 2;
+        },
+    };
+    let mut rl = match DefaultEditor::new() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("neoshell: {}", e);
+            return 2;
+        },
+    };
+    let history = env::var_os("HOME").map(|h| PathBuf::from(h).join(".neoshell_history"));
+    let mut scp = Scope::new();
+
+    if let Some(h) = &history {
+        let _ = rl.load_history(h);
+    }
+
+    loop {
+        let src = match read_input(&mut rl) {
+            Ok(Some(s)) => s,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("neoshell: {}", e);
+                break;
+            },
+        };
+
+        let _ = rl.add_history_entry(src.as_str());
+        if let Some(h) = &history {
+            let _ = rl.save_history(h);
         }
 
-        Ok(AstArgument::None)
+        if let Err(e) = run_input(&mut vm, &mut scp, &src) {
+            report("neoshell", &src, vec![e]);
+        }
     }
+
+    0
 }
 
+/// Reads an input of the interactive shell, line by line until it can be
+/// parsed. Returns `None` at the end of the input. An interrupted input is
+/// dropped, and a new one is read.
+fn read_input(rl: &mut DefaultEditor) -> Result<Option<String>, ReadlineError> {
+    let mut src = String::new();
 
-fn main() {
-    let ast = ns_parser::file(SRC);
-    let mut vm = VM::new();
-    vm.get_registery_mut().register(&"puts".to_owned(), PutsCommand{});
-    let mut scp = Scope::new();
+    loop {
+        let prompt = match src.is_empty() {
+            true => "ns> ",
+            false => "... ",
+        };
 
-    match ast {
-        Ok(v) => {
-            for c in v {
-                vm.execute_command(&mut scp, &c).expect("oof");
-            }
+        match rl.readline(prompt) {
+            Ok(l) => {
+                if !src.is_empty() {
+                    src.push('\n');
+                }
+                src.push_str(&l);
+            },
+            Err(ReadlineError::Interrupted) => {
+                src.clear();
+                continue;
+            },
+            Err(ReadlineError::Eof) if src.is_empty() => return Ok(None),
+            Err(ReadlineError::Eof) => return Ok(Some(src)),
+            Err(e) => return Err(e),
         }
-        Err(e) => println!("{}", e)
+
+        if src.trim().is_empty() {
+            src.clear();
+        } else if !is_incomplete(&src) {
+            return Ok(Some(src));
+        }
+    }
+}
+
+/// Checks if an input needs more lines to be parsed : the parsing failed at
+/// its end, as a brace or string is still open, or the last `;` is missing.
+fn is_incomplete(src: &str) -> bool {
+    match ns_parser::file(src) {
+        Ok(_) => false,
+        Err(e) => e.location.offset >= src.len(),
+    }
+}
+
+/// Compiles and runs an input of the interactive shell. Returns the error
+/// to report if it fails.
+fn run_input(vm: &mut VM, scp: &mut Scope, src: &str) -> Result<(), Diagnostic> {
+    let ast = ns_parser::file(src).map_err(|e| parse_diagnostic(&e))?;
+
+    let prog = vm.compile(scp, ast);
+    report("neoshell", src, vm.take_diagnostics());
+    let prog = prog.map_err(|e| Diagnostic::new_error(e.to_string()).with_span(vm.take_error_span()))?;
+
+    // A signal received while reading the input isn't meant for this one.
+    vm.get_signals().take();
+
+    let r = vm.run(scp, &prog);
+    report("neoshell", src, vm.take_diagnostics());
+
+    r.map(|_| ()).map_err(|e| Diagnostic::new_error(e.to_string()).with_span(vm.take_error_span()))
+}
+
+/// Reads a script from a file, or from the standard input.
+fn read_script(script: &str) -> io::Result<String> {
+    match script {
+        "-" => {
+            let mut src = String::new();
+            io::stdin().read_to_string(&mut src)?;
+            Ok(src)
+        },
+        _ => fs::read_to_string(script),
+    }
+}
+
+/// Creates a VM with the builtin commands, forwarding the signals received
+/// to the programs it runs.
+fn new_vm() -> Result<VM, Error> {
+    let mut vm = VM::new();
+
+    register_builtins(vm.get_registery_mut())?;
+    vm.get_signals().install()?;
+
+    Ok(vm)
+}
+
+/// Writes diagnostics to the error output, located in the script.
+fn report(script: &str, src: &str, diags: Vec<Diagnostic>) {
+    for d in diags {
+        eprintln!("{}: {}", script, d.format(src));
+    }
+}
+
+/// Converts a parsing error into a diagnostic.
+fn parse_diagnostic(e: &ParseError<LineCol>) -> Diagnostic {
+    Diagnostic::new_error(format!("expected {}", e.expected)).with_span(Some(AstSpan {
+        start: e.location.offset,
+        end: e.location.offset,
+    }))
+}
+
+/// Gets the exit code of a failed script.
+fn exit_code_of(e: &Error) -> i32 {
+    match (e.downcast_ref::<ExternalError>(), e.downcast_ref::<VMError>()) {
+        (Some(ExternalError::Failed { code, .. }), _) => *code,
+        (_, Some(VMError::Interrupted { signal })) => 128 + signal,
+        _ => 1,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Runs the given script from a temporary file, and returns its exit code.
+    fn exit_code(src: &str, args: &[&str]) -> i32 {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let n = COUNT.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("neoshell-script-{}-{}", process::id(), n));
+        fs::write(&path, src).unwrap();
+
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let code = run_script(&path.to_string_lossy(), &args);
+        let _ = fs::remove_file(&path);
+        code
+    }
+
+    #[test]
+    fn scripts_exit_with_their_returned_code() {
+        assert_eq!(exit_code("#!/usr/bin/env neoshell\nvalue 1;", &[]), 0);
+        assert_eq!(exit_code("return 3;", &[]), 3);
+        assert_eq!(exit_code("return !{ stream $args |> count };", &["a", "b"]), 2);
+    }
+
+    #[test]
+    fn invalid_scripts_exit_with_2() {
+        assert_eq!(run_script("/neoshell-missing-script", &[]), 2);
+        assert_eq!(exit_code("let a {", &[]), 2);
+        assert_eq!(exit_code("set undeclared 1;", &[]), 2);
+    }
+
+    #[test]
+    fn failing_scripts_exit_with_the_code_of_their_error() {
+        assert_eq!(exit_code("sh \"-c\" \"exit 4\";", &[]), 4);
+        assert_eq!(exit_code("kill 42;", &[]), 1);
+        assert_eq!(exit_code_of(&VMError::new_interrupted(libc::SIGINT)), 130);
+    }
+
+    #[test]
+    fn unfinished_inputs_are_incomplete() {
+        assert!(is_incomplete("let a {"));
+        assert!(is_incomplete("puts \"a"));
+        assert!(is_incomplete("let a 1"));
+        assert!(!is_incomplete("let a 1;"));
+        assert!(!is_incomplete("let a 1 };"));
+    }
+
+    #[test]
+    fn inputs_share_their_scope() {
+        let mut vm = VM::new();
+        let mut scp = Scope::new();
+        register_builtins(vm.get_registery_mut()).unwrap();
+
+        assert!(run_input(&mut vm, &mut scp, "let a 1;").is_ok());
+        assert!(run_input(&mut vm, &mut scp, "set undeclared 2;").is_err());
+        assert!(run_input(&mut vm, &mut scp, "set a 2;").is_ok());
+        assert_eq!(scp.get_variable(&"a".to_owned()).map(|v| v.to_value().to_string()).as_deref(), Some("2"));
     }
 }
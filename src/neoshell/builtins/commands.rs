@@ -0,0 +1,108 @@
+#![deny(missing_docs)]
+//! Builtin commands handling instantiated commands.
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Command instantiation command : `command <target> [args...];`.
+///
+/// `target` can be the name of a registered runtime command, a block, or an
+/// already instantiated command. The given arguments are bound to the
+/// resulting command, and will be passed before the arguments given at
+/// invocation (`$cmd args...;`). Literals and words are bound as-is, and the
+/// other arguments are evaluated, their values being given to the command
+/// when it binds its arguments. Switches can only be given at invocation.
+pub struct InstantiateCommand { }
+
+
+
+impl RuntimeCommand for InstantiateCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let target = match args.get_ast("target") {
+            Some(t) => t,
+            None => return Err(CommandError::new_invalid_arguments("command", "Expected a command to instantiate.")),
+        };
+
+        let mut inst = match target {
+            AstArgument::Name(AstName::Name(n)) => {
+                if !vm.get_registery().has_command(n) {
+                    return Err(CommandError::new_invalid_arguments("command", &format!("Command '{}' not found.", n)));
+                }
+
+                InstancedCommand::new(CommandTarget::Registered(n.clone()))
+            },
+            AstArgument::Block(AstBlock::Inferred(c)) => {
+                InstancedCommand::new(CommandTarget::Closure(Closure::new(c.clone(), scp)))
+            },
+            _ => match vm.evaluate(scp, target)? {
+                Value::Closure(c) => InstancedCommand::new(CommandTarget::Closure(c)),
+                Value::Command(c) => InstancedCommand {
+                    target: c.target.clone(),
+                    arguments: c.arguments.clone(),
+                },
+                v => return Err(ValueError::new_type_mismatch(&Type::Command, v.get_type())),
+            },
+        };
+
+        for a in args.get_list("args").map(|v| v.as_slice()).unwrap_or(&[]) {
+            let a = bind_argument(vm, scp, a)?;
+            inst.arguments.push(a);
+        }
+
+        Ok(Value::Command(Rc::new(inst)))
+    }
+}
+
+
+
+/// Binds an argument to an instantiated command. Literals and words are kept
+/// as-is, and the other arguments are evaluated.
+fn bind_argument(vm: &VM, scp: &mut Scope, arg: &AstArgument) -> Result<InstanceArgument, Error> {
+    match arg {
+        AstArgument::Name(AstName::Name(_)) | AstArgument::None | AstArgument::Integer(_) |
+        AstArgument::Float(_) | AstArgument::String(_) | AstArgument::Descriptor(_) => Ok(InstanceArgument::Ast(arg.clone())),
+        _ => Ok(InstanceArgument::Value(vm.evaluate(scp, arg)?)),
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::neoshell::builtins::tests::run;
+
+    #[test]
+    fn bound_values_are_given_to_registered_commands() {
+        let v = run("let x 1; let c !{ command value !{ value $x } }; set x 2; $c;").unwrap();
+        assert_eq!(v.to_string(), "1");
+    }
+
+    #[test]
+    fn bound_values_are_given_to_defined_commands() {
+        let v = run("def-cmd 'add <a i4> <b i4>; { value $a; }; let c !{ command add !{ value 1 } }; $c 2;").unwrap();
+        assert_eq!(v.to_string(), "1");
+    }
+
+    #[test]
+    fn bound_values_are_given_first_to_blocks() {
+        let v = run("let x 1; let c !{ command &{ value $args; } $x 2 }; let d !{ command $c 3 }; $d 4;").unwrap();
+        assert_eq!(v.to_string(), "[1, 2, 3, 4]");
+    }
+
+    #[test]
+    fn bound_values_are_not_visible_as_variables() {
+        assert!(run("let c !{ command value !{ value 1 } }; $c; value $#0;").is_err());
+    }
+
+    #[test]
+    fn switches_are_given_at_invocation() {
+        assert!(run("command puts \"a\" /sep \"-\";").is_err());
+    }
+}
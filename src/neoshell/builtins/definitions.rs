@@ -0,0 +1,464 @@
+#![deny(missing_docs)]
+//! Builtin commands defining commands from scripts.
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::tools::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Runtime command definition command : `def-cmd <descriptor> <body>;`.
+///
+/// The body is compiled when the command is defined. At each invocation, the
+/// arguments are bound to variables named after the descriptor in a new
+/// sub-scope of the scope the command was defined in, and the body is executed
+/// in it. The command returns the value given to `return`, or the result of
+/// the last command of its body.
+pub struct DefineCommand { }
+
+/// Macro definition command : `def-macro <descriptor> <body>;`.
+///
+/// The body is compiled when the macro is defined, and executed each time the
+/// macro is expanded, with its arguments bound like for `def-cmd`. Arguments
+/// of type `ast` are bound without being evaluated. The value returned by the
+/// body is turned into the commands replacing the macro invocation :
+///
+///  - a quoted block (`&{ ... }`) gives its commands, in which the variables
+///    of the macro are replaced by their values ;
+///  - an AST value built with the `ast::` commands gives its command, or a
+///    `value` command returning its argument ;
+///  - an array gives the commands of all its items ;
+///  - void gives no command, and any other value gives a `value` command
+///    returning it.
+pub struct DefineMacroCommand { }
+
+/// Compile time command definition command : `def-ct-cmd <descriptor> <body>;`.
+///
+/// The body is made of compile time commands, executed each time the defined
+/// command is. The arguments aren't evaluated : the variables of the body
+/// named after the descriptor are replaced by the given arguments before the
+/// body is executed.
+pub struct DefineCompileTimeCommand { }
+
+/// Alias command : `alias <name> <target>;`. Registers `name` as another name
+/// for the command `target`. Invocations of runtime and macro commands are
+/// renamed when expanding macros, and compile time commands are forwarded.
+pub struct AliasCommand { }
+
+/// Return command : `return [value];`. Stops the execution of the current
+/// command or closure, making it return the given value.
+pub struct ReturnCommand { }
+
+/// Runtime command defined by a script.
+pub struct ScriptCommand {
+    descriptor: AstDescriptor,
+    body: Rc<Vec<AstCommand>>,
+    scope: Scope,
+}
+
+/// Macro command defined by a script.
+pub struct ScriptMacro {
+    body: Rc<Vec<AstCommand>>,
+    scope: Scope,
+}
+
+/// Compile time command defined by a script.
+pub struct ScriptCompileTimeCommand {
+    body: Vec<AstCommand>,
+    scope: Scope,
+}
+
+/// Command registered under another name.
+pub struct AliasedCommand {
+    target: String,
+}
+
+
+
+impl CompileTimeCommand for DefineCommand {
+    fn execute(&mut self, vm: &mut VM, scp: &mut Scope, cmd: &AstCommand) -> Option<Error> {
+        let (desc, body) = match compile_definition(vm, scp, "def-cmd", cmd) {
+            Ok(d) => d,
+            Err(e) => return Some(e),
+        };
+        let cmd = ScriptCommand {
+            descriptor: desc.clone(),
+            body: Rc::new(body),
+            scope: scp.clone(),
+        };
+
+        vm.get_registery_mut().register_with(desc, Box::new(cmd)).err()
+    }
+}
+
+impl CompileTimeCommand for DefineMacroCommand {
+    fn execute(&mut self, vm: &mut VM, scp: &mut Scope, cmd: &AstCommand) -> Option<Error> {
+        let (desc, body) = match compile_definition(vm, scp, "def-macro", cmd) {
+            Ok(d) => d,
+            Err(e) => return Some(e),
+        };
+        let cmd = ScriptMacro {
+            body: Rc::new(body),
+            scope: scp.clone(),
+        };
+
+        vm.get_registery_mut().register_macro_with(desc, Box::new(cmd)).err()
+    }
+}
+
+impl CompileTimeCommand for DefineCompileTimeCommand {
+    fn execute(&mut self, vm: &mut VM, scp: &mut Scope, cmd: &AstCommand) -> Option<Error> {
+        let (desc, body) = match get_definition(vm, scp, "def-ct-cmd", cmd) {
+            Ok(d) => d,
+            Err(e) => return Some(e),
+        };
+        let cmd = ScriptCompileTimeCommand {
+            body,
+            scope: scp.clone(),
+        };
+
+        vm.get_registery_mut().register_ct_with(desc, Box::new(cmd)).err()
+    }
+}
+
+impl CompileTimeCommand for AliasCommand {
+    fn execute(&mut self, vm: &mut VM, scp: &mut Scope, cmd: &AstCommand) -> Option<Error> {
+        let args = match vm.bind_arguments(scp, cmd) {
+            Ok(a) => a,
+            Err(e) => return Some(e),
+        };
+        let name = |n: &str| match args.get_ast(n) {
+            Some(AstArgument::String(n)) | Some(AstArgument::Name(AstName::Name(n))) => Some(n.clone()),
+            _ => None,
+        };
+        let (name, target) = match (name("name"), name("target")) {
+            (Some(n), Some(t)) => (n, t),
+            _ => return Some(CommandError::new_invalid_arguments("alias", "Expected a name and a target.")),
+        };
+
+        let reg = vm.get_registery_mut();
+        let alias = || AliasedCommand {
+            target: target.clone(),
+        };
+
+        if !reg.has_ct_command(&target) && !reg.has_macro(&target) && !reg.has_command(&target) {
+            return Some(RegisteryError::new_not_found(&target));
+        }
+        if reg.has_ct_command(&target) {
+            reg.register_ct(&name, alias());
+        }
+        if reg.has_macro(&target) || reg.has_command(&target) {
+            reg.register_macro(&name, alias());
+        }
+
+        None
+    }
+}
+
+impl RuntimeCommand for ReturnCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let val = vm.bind_arguments(scp, cmd)?.evaluate(vm, scp, "value")?;
+
+        vm.set_return(val);
+        Ok(Value::Void)
+    }
+}
+
+impl RuntimeCommand for ScriptCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let mut sub = bind_arguments(vm, scp, &self.scope, cmd, true)?;
+
+        if let Some(t) = input_type(&self.descriptor) {
+            sub.declare("in", Variable::Value(Value::Stream(t, ValueStream::from_values(Vec::new()))));
+        }
+
+        let r = vm.execute_block(&mut sub, &self.body)?;
+        Ok(vm.take_return().unwrap_or(r))
+    }
+
+    fn execute_piped(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand, input: ValueStream) -> Result<Value, Error> {
+        let t = match input_type(&self.descriptor) {
+            Some(t) => t,
+            None => return Err(CommandError::new_not_a_consumer(&self.descriptor.name)),
+        };
+
+        let mut sub = bind_arguments(vm, scp, &self.scope, cmd, true)?;
+        sub.declare("in", Variable::Value(Value::Stream(t, input)));
+
+        let r = vm.execute_block(&mut sub, &self.body)?;
+        Ok(vm.take_return().unwrap_or(r))
+    }
+}
+
+impl MacroCommand for ScriptMacro {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Vec<AstCommand>, Error> {
+        let mut sub = bind_arguments(vm, scp, &self.scope, cmd, true)?;
+        let r = vm.execute_block(&mut sub, &self.body)?;
+        let r = vm.take_return().unwrap_or(r);
+
+        into_commands(&sub, r)
+    }
+}
+
+
+impl CompileTimeCommand for ScriptCompileTimeCommand {
+    fn execute(&mut self, vm: &mut VM, scp: &mut Scope, cmd: &AstCommand) -> Option<Error> {
+        let sub = match bind_arguments(vm, scp, &self.scope, cmd, false) {
+            Ok(s) => s,
+            Err(e) => return Some(e),
+        };
+
+        for c in self.body.iter() {
+            let mut c = match substitute_command(&sub, c) {
+                Ok(c) => c,
+                Err(e) => return Some(e),
+            };
+            c.time = AstTime::CompileTime;
+
+            if let Some(e) = vm.execute_ct_command(scp, &c) {
+                return Some(e);
+            }
+        }
+
+        None
+    }
+}
+
+impl CompileTimeCommand for AliasedCommand {
+    fn execute(&mut self, vm: &mut VM, scp: &mut Scope, cmd: &AstCommand) -> Option<Error> {
+        let mut c = cmd.clone();
+        c.name = AstName::Name(self.target.clone());
+
+        vm.execute_ct_command(scp, &c)
+    }
+}
+
+impl MacroCommand for AliasedCommand {
+    fn execute(&self, _vm: &VM, _scp: &mut Scope, cmd: &AstCommand) -> Result<Vec<AstCommand>, Error> {
+        let mut c = cmd.clone();
+        c.time = AstTime::Runtime;
+        c.name = AstName::Name(self.target.clone());
+
+        Ok(vec![c])
+    }
+}
+
+
+
+/// Gets the descriptor, with its choices merged, and the body of a command
+/// definition.
+fn get_definition(vm: &VM, scp: &Scope, name: &str, cmd: &AstCommand) -> Result<(AstDescriptor, Vec<AstCommand>), Error> {
+    let args = vm.bind_arguments(scp, cmd)?;
+
+    match (args.get_ast("desc"), args.get_ast("body")) {
+        (Some(AstArgument::Descriptor(d)), Some(AstArgument::Block(b))) => match b {
+            AstBlock::Evaluated(c) | AstBlock::Argument(c) | AstBlock::Inferred(c) => Ok((merge_choices(d)?, c.clone())),
+        },
+        _ => Err(CommandError::new_invalid_arguments(name, "Expected a descriptor and a block.")),
+    }
+}
+
+/// Gets the descriptor and the compiled body of a command definition. The
+/// body is compiled in a sub-scope declaring the arguments of the descriptor.
+fn compile_definition(vm: &mut VM, scp: &Scope, name: &str, cmd: &AstCommand) -> Result<(AstDescriptor, Vec<AstCommand>), Error> {
+    let (desc, body) = get_definition(vm, scp, name, cmd)?;
+    let mut check = Scope::extends(scp);
+
+    for d in desc.arguments.iter().flat_map(descriptor_arguments) {
+        check.declare_with(&d.name, Variable::Value(Value::Void), Some(&Type::Generic), false)?;
+    }
+    if let Some(t) = input_type(&desc) {
+        check.declare_type("in", Type::Stream(Box::new(t)));
+    }
+
+    Ok((desc, vm.compile(&mut check, body)?))
+}
+
+/// Binds the arguments of an invocation of a script command to variables,
+/// in a new sub-scope of the scope the command was defined in. Unevaluated
+/// arguments are bound as pieces of AST.
+fn bind_arguments(vm: &VM, scp: &mut Scope, parent: &Scope, cmd: &AstCommand, evaluate: bool) -> Result<Scope, Error> {
+    let sub = Scope::extends(parent);
+    let args = vm.bind_arguments(scp, cmd)?;
+
+    for name in args.names() {
+        let val = match evaluate {
+            true => args.evaluate(vm, scp, name)?,
+            false => args.quote(name)?,
+        };
+        sub.declare(name, Variable::from_value(val));
+    }
+
+    Ok(sub)
+}
+
+/// Turns the value returned by a macro into commands.
+fn into_commands(sub: &Scope, val: Value) -> Result<Vec<AstCommand>, Error> {
+    let value_command = |arg: AstArgument| {
+        let mut c = AstCommand::new(AstTime::Runtime, AstName::Name("value".to_owned()));
+        c.arguments.push(arg);
+        c
+    };
+
+    match val {
+        Value::Void => Ok(Vec::new()),
+        Value::Closure(c) => c.body.iter().map(|c| substitute_command(sub, c)).collect(),
+        Value::Ast(a) => match a.as_ref() {
+            AstValue::Command(c) => Ok(vec![c.clone()]),
+            AstValue::Argument(a) => Ok(vec![value_command(a.clone())]),
+        },
+        Value::Array(_, v) => {
+            let mut r = Vec::new();
+            for v in v {
+                r.extend(into_commands(sub, v)?);
+            }
+            Ok(r)
+        },
+        v => Ok(vec![value_command(v.to_ast()?)]),
+    }
+}
+
+/// Replaces the variables declared in the given scope by their values.
+fn substitute_command(sub: &Scope, cmd: &AstCommand) -> Result<AstCommand, Error> {
+    let mut r = cmd.clone();
+
+    r.arguments = cmd.arguments.iter().map(|a| substitute_argument(sub, a)).collect::<Result<_, _>>()?;
+    if let Some(p) = &cmd.pipe {
+        r.pipe = Some(Box::new(substitute_command(sub, p)?));
+    }
+
+    Ok(r)
+}
+
+/// Replaces the variables declared in the given scope by their values.
+fn substitute_argument(sub: &Scope, arg: &AstArgument) -> Result<AstArgument, Error> {
+    let all = |v: &Vec<AstArgument>| -> Result<Vec<AstArgument>, Error> {
+        v.iter().map(|a| substitute_argument(sub, a)).collect()
+    };
+    let block = |v: &Vec<AstCommand>| -> Result<Vec<AstCommand>, Error> {
+        v.iter().map(|c| substitute_command(sub, c)).collect()
+    };
+
+    Ok(match arg {
+        AstArgument::Name(AstName::Variable(n)) if sub.is_declared_locally(n) => {
+            sub.get_variable(n).unwrap().to_value().to_ast()?
+        },
+        AstArgument::Switch(AstSwitch::Option(n, v)) => AstArgument::Switch(AstSwitch::Option(n.clone(), Box::new(substitute_argument(sub, v)?))),
+        AstArgument::Switch(AstSwitch::Choice(n, v)) => AstArgument::Switch(AstSwitch::Choice(n.clone(), Box::new(substitute_argument(sub, v)?))),
+        AstArgument::Switch(AstSwitch::List(n, v)) => AstArgument::Switch(AstSwitch::List(n.clone(), all(v)?)),
+        AstArgument::Switch(AstSwitch::MultiChoice(n, v)) => AstArgument::Switch(AstSwitch::MultiChoice(n.clone(), all(v)?)),
+        AstArgument::Block(AstBlock::Evaluated(c)) => AstArgument::Block(AstBlock::Evaluated(block(c)?)),
+        AstArgument::Block(AstBlock::Argument(c)) => AstArgument::Block(AstBlock::Argument(block(c)?)),
+        AstArgument::Block(AstBlock::Inferred(c)) => AstArgument::Block(AstBlock::Inferred(block(c)?)),
+        _ => arg.clone(),
+    })
+}
+
+/// Gets the type of the items of the stream consumed by a command, available
+/// to its body as `$in`.
+fn input_type(desc: &AstDescriptor) -> Option<Type> {
+    desc.input.as_ref().map(|t| Type::from_name(t).unwrap_or(Type::Generic))
+}
+
+/// Gets the argument descriptors of a descriptor item.
+fn descriptor_arguments(item: &AstDescriptorItem) -> Vec<&AstArgumentDescriptor> {
+    match item {
+        AstDescriptorItem::Static(_) => Vec::new(),
+        AstDescriptorItem::Mandatory(d) => vec![d],
+        AstDescriptorItem::Optional(v) => v.iter().collect(),
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neoshell::builtins::tests::run;
+
+    fn value(src: &str) -> String {
+        run(src).unwrap().to_string()
+    }
+
+    #[test]
+    fn defined_commands_bind_their_arguments() {
+        assert_eq!(value("def-cmd 'sub <a i4> <b i4>; { value $b; }; sub 1 2;"), "2");
+        assert_eq!(value("def-cmd 'greet [name = str \"you\"]; { value $name; }; greet;"), "you");
+        assert_eq!(value("def-cmd 'greet [name = str \"you\"]; { value $name; }; greet name = \"me\";"), "me");
+    }
+
+    #[test]
+    fn defined_commands_return_early() {
+        assert_eq!(value("def-cmd 'f; { return 1; value 2; }; f;"), "1");
+    }
+
+    #[test]
+    fn defined_commands_are_overloaded_by_type() {
+        let src = "def-cmd 'kind <v i4>; { value \"int\"; }; def-cmd 'kind <v str>; { value \"str\"; };";
+        assert_eq!(value(&format!("{} kind 1;", src)), "int");
+        assert_eq!(value(&format!("{} kind \"a\";", src)), "str");
+    }
+
+    #[test]
+    fn defined_consumers_read_their_input() {
+        assert_eq!(value("def-cmd 'total |>i8; { stream $in |> count; }; range 1 5 |> total;"), "4");
+    }
+
+    #[test]
+    fn defined_commands_are_compiled_when_defined() {
+        assert!(run("def-cmd 'f <a i4>; { value $a; set b 1; };").is_err());
+    }
+
+    #[test]
+    fn macros_expand_quoted_blocks_with_their_arguments() {
+        assert_eq!(value("def-macro 'twice <v i4>; { value &{ value $v; value $v; }; }; twice 3;"), "3");
+        assert_eq!(value("def-macro 'declare <n i4>; { value &{ let x $n; }; }; declare 4; value $x;"), "4");
+    }
+
+    #[test]
+    fn macros_expand_built_commands() {
+        let src = "def-macro 'm; { ast::command new \"value\" 5; }; m;";
+        assert_eq!(value(src), "5");
+        let src = "def-macro 'm; { ast::integer-literal new 6; }; m;";
+        assert_eq!(value(src), "6");
+    }
+
+    #[test]
+    fn macros_expand_arrays_and_void() {
+        let v = Value::Array(Type::I4, vec![Value::I4(1), Value::I4(2)]);
+        let cmds = into_commands(&Scope::new(), v).unwrap();
+        assert_eq!(cmds.len(), 2);
+        assert!(cmds.iter().all(|c| c.name == AstName::Name("value".to_owned())));
+
+        assert!(into_commands(&Scope::new(), Value::Void).unwrap().is_empty());
+        assert_eq!(value("def-macro 'm; { return; }; value 8; m;"), "8");
+        assert_eq!(value("def-macro 'm; { value 9; }; m;"), "9");
+    }
+
+    #[test]
+    fn aliases_accept_words_and_strings() {
+        assert_eq!(run("alias v value; v 1;").unwrap().to_string(), "1");
+        assert_eq!(run("alias \"v\" \"value\"; v 1;").unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn aliases_of_unknown_commands_are_rejected() {
+        assert!(run("alias v unknown-command;").is_err());
+    }
+
+    #[test]
+    fn compile_time_commands_substitute_their_arguments() {
+        let v = run("def-ct-cmd 'alias-value <n str>; { alias $n value; }; alias-value v; v 2;").unwrap();
+        assert_eq!(v.to_string(), "2");
+    }
+
+    #[test]
+    fn definitions_are_described() {
+        assert!(run("def-ct-cmd { };").is_err());
+        assert!(run("alias v;").is_err());
+    }
+}
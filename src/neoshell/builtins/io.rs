@@ -0,0 +1,76 @@
+#![deny(missing_docs)]
+//! Builtin commands writing to the output of the shell.
+
+use std::io::Write;
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Print command : `puts <values...> [sep = <str>] [/no-newline];`. Writes the
+/// values separated by `sep` (a space by default) to the output, followed by
+/// a line feed unless `/no-newline` is given. Values of any type are written
+/// as text, and arrays and streams give their items.
+pub struct PutsCommand { }
+
+
+
+impl RuntimeCommand for PutsCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let values = args.evaluate(vm, scp, "values")?;
+        let sep = args.evaluate(vm, scp, "sep")?;
+
+        let mut items = Vec::new();
+        for v in values.get_array().unwrap() {
+            match v.clone().into_stream() {
+                Some((_, s)) => {
+                    for v in s {
+                        items.push(v?.to_string());
+                    }
+                },
+                None => items.push(v.to_string()),
+            }
+        }
+        let sep = sep.get_string().unwrap();
+
+        match args.evaluate(vm, scp, "no-newline")?.get_bool() {
+            Some(true) => write!(vm.stdout(), "{}", items.join(sep))?,
+            _ => writeln!(vm.stdout(), "{}", items.join(sep))?,
+        }
+
+        Ok(Value::Void)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::neoshell::builtins::tests::output;
+
+    #[test]
+    fn values_of_any_type_are_written() {
+        assert_eq!(output("let d 3; puts \"a\" $d 1.5 true stdout = $out;").unwrap(), "a 3 1.5 true\n");
+    }
+
+    #[test]
+    fn evaluated_values_are_written() {
+        let src = "let n i8 8; let d 3; puts !{ value $n } !{ value $d } stdout = $out;";
+        assert_eq!(output(src).unwrap(), "8 3\n");
+    }
+
+    #[test]
+    fn arrays_and_streams_give_their_items() {
+        assert_eq!(output("let r !{ range 1 3 }; puts $r \"x\" sep = \",\" /no-newline stdout = $out;").unwrap(), "1,2,x");
+    }
+
+    #[test]
+    fn repeated_switches_keep_their_last_value() {
+        let src = "puts \"a\" \"b\" sep = \"-\" sep = \"+\" /no-newline /no-newline stdout = $out;";
+        assert_eq!(output(src).unwrap(), "a+b");
+    }
+}
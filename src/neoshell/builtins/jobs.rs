@@ -0,0 +1,140 @@
+#![deny(missing_docs)]
+//! Builtin commands managing the jobs running in the background.
+
+use std::io::Write;
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Wait command : `wait [job];`. Waits for the end of the given job, and
+/// returns its exit codes, like a pipeline run in the foreground. Without a
+/// job (or with `0`), waits for all the jobs and returns the array of their
+/// exit codes.
+pub struct WaitCommand { }
+
+/// Jobs command : `jobs;`. Lists the jobs not waited yet, with their state,
+/// and returns the array of their ids.
+pub struct JobsCommand { }
+
+/// Kill command : `kill <job> [signal -> <name>];`. Sends a signal to the
+/// processes of a job, `term` by default.
+pub struct KillCommand { }
+
+
+
+impl RuntimeCommand for WaitCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let id = args.evaluate(vm, scp, "job")?.get_integer().unwrap_or(0) as i32;
+
+        if id != 0 {
+            let job = vm.get_jobs().remove(id)?;
+
+            return Ok(status_value(job.wait()?));
+        }
+
+        let jobs = vm.get_jobs().remove_all();
+        let mut values = Vec::with_capacity(jobs.len());
+        let mut error = None;
+
+        for j in jobs {
+            match j.wait() {
+                Ok(c) => values.push(status_value(c)),
+                Err(e) => {
+                    error.get_or_insert(e);
+                },
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(Value::Array(Type::Generic, values)),
+        }
+    }
+}
+
+impl RuntimeCommand for JobsCommand {
+    fn execute(&self, vm: &VM, _scp: &mut Scope, _cmd: &AstCommand) -> Result<Value, Error> {
+        let mut out = vm.stdout();
+        let mut ids = Vec::new();
+
+        for j in vm.get_jobs().iter_mut() {
+            let state = match j.poll()? {
+                JobState::Running => "running".to_owned(),
+                JobState::Done(c) => format!("done ({})", status_value(c)),
+            };
+
+            writeln!(out, "[{}] {} : {}", j.get_id(), state, j.get_command())?;
+            ids.push(Value::I4(j.get_id()));
+        }
+
+        Ok(Value::Array(Type::I4, ids))
+    }
+}
+
+impl RuntimeCommand for KillCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let id = args.evaluate(vm, scp, "job")?.get_integer().unwrap_or(0) as i32;
+        let sig = match args.get_word("signal").map(|s| s.as_str()) {
+            Some("int") => libc::SIGINT,
+            Some("hup") => libc::SIGHUP,
+            Some("quit") => libc::SIGQUIT,
+            Some("kill") => libc::SIGKILL,
+            Some("stop") => libc::SIGSTOP,
+            Some("cont") => libc::SIGCONT,
+            Some("usr1") => libc::SIGUSR1,
+            Some("usr2") => libc::SIGUSR2,
+            Some("term") | None => libc::SIGTERM,
+            Some(s) => return Err(CommandError::new_invalid_arguments("kill", &format!("Unknown signal '{}'.", s))),
+        };
+
+        vm.get_jobs().get_mut(id)?.signal(sig)?;
+        Ok(Value::Void)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::neoshell::builtins::tests::{output, run};
+
+    #[test]
+    fn background_pipelines_return_their_job() {
+        assert_eq!(run("let j !{ true /background }; wait $j;").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn background_pipelines_are_made_of_programs() {
+        let e = run("range 1 3 |> cat /background;").err().unwrap();
+        assert!(e.to_string().contains("can't read the output of a command"), "{}", e);
+
+        let e = run("cat /background |> count;").err().unwrap();
+        assert!(e.to_string().contains("can't be piped into 'count'"), "{}", e);
+    }
+
+    #[test]
+    fn waiting_without_job_waits_for_all_of_them() {
+        assert_eq!(run("true /background; false /background /allow-failure; wait;").unwrap().to_string(), "[0, 1]");
+        assert!(run("let j !{ false /background }; wait $j;").is_err());
+        assert!(run("wait 42;").is_err());
+    }
+
+    #[test]
+    fn jobs_are_listed_with_their_state() {
+        let src = "let j !{ sleep 5 /background /allow-failure }; jobs stdout = $out; kill $j; wait $j;";
+        assert_eq!(output(src).unwrap(), "[1] running : sleep 5 /background /allow-failure\n");
+    }
+
+    #[test]
+    fn killed_jobs_exit_with_the_signal() {
+        let src = "let j !{ sleep 5 /background /allow-failure }; kill $j signal -> int; wait $j;";
+        assert_eq!(run(src).unwrap().to_string(), (128 + libc::SIGINT).to_string());
+        assert!(run("kill 1 signal -> nope;").err().unwrap().to_string().contains("Invalid value 'nope'"));
+    }
+}
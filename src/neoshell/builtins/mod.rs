@@ -0,0 +1,111 @@
+
+pub mod commands;
+pub mod definitions;
+pub mod io;
+pub mod jobs;
+pub mod paths;
+pub mod streams;
+pub mod syntax;
+pub mod variables;
+
+pub use self::commands::*;
+pub use self::definitions::*;
+pub use self::io::*;
+pub use self::jobs::*;
+pub use self::paths::*;
+pub use self::streams::*;
+pub use self::syntax::*;
+pub use self::variables::*;
+
+
+use failure::Error;
+
+use crate::neoshell::vm::*;
+
+
+/// Registers all the builtin commands.
+pub fn register_builtins(reg: &mut CommandRegistery) -> Result<(), Error> {
+    reg.register_described("'let <name str> <value generic>;", DeclareCommand::new_let())?;
+    reg.register_described("'let <name str> <type str> <value generic>;", DeclareCommand::new_let())?;
+    reg.register_described("'const <name str> <value generic>;", DeclareCommand::new_const())?;
+    reg.register_described("'const <name str> <type str> <value generic>;", DeclareCommand::new_const())?;
+    reg.register_described("'set <name str> <value generic>;", SetCommand{})?;
+    reg.register_described("'export <name str> [value generic];", ExportCommand{})?;
+    reg.register_described("'command <target generic> [args... generic];", InstantiateCommand{})?;
+
+    reg.register_ct_described("'def-cmd <desc descriptor> <body block>;", DefineCommand{})?;
+    reg.register_ct_described("'def-macro <desc descriptor> <body block>;", DefineMacroCommand{})?;
+    reg.register_ct_described("'def-ct-cmd <desc descriptor> <body block>;", DefineCompileTimeCommand{})?;
+    reg.register_ct_described("'alias <name str> <target str>;", AliasCommand{})?;
+    reg.register_described("'return [value generic];", ReturnCommand{})?;
+    reg.register_described("'value <v generic>;", ValueCommand{})?;
+    reg.register_described("'puts <values... generic> [sep = str \" \"] [/no-newline];", PutsCommand{})?;
+
+    reg.register_described("'range i8|> <from i8> <to i8>;", RangeCommand{})?;
+    reg.register_described("'read str|> <path str>;", ReadCommand{})?;
+    reg.register_described("'stream generic|> <values generic>;", StreamCommand{})?;
+    reg.register_described("'take |>generic generic|> <n i8>;", TakeCommand{})?;
+    reg.register_described("'collect |>generic;", CollectCommand{})?;
+    reg.register_described("'count |>generic;", CountCommand{})?;
+    reg.register_described("'each |>generic <body block>;", EachCommand{})?;
+
+    reg.register_described("'path <path str>;", PathCommand{})?;
+    reg.register_described("'glob path|> <pattern str> [/allow-empty];", GlobCommand{})?;
+
+    reg.register_described("'wait [job i4 0];", WaitCommand{})?;
+    reg.register_described("'jobs;", JobsCommand{})?;
+    reg.register_described("'kill <job i4> [signal -> term,int,hup,quit,kill,stop,cont,usr1,usr2 term];", KillCommand{})?;
+
+    reg.register_described("'ast::integer-literal 'new <v i4>;", AstLiteralCommand::new_integer())?;
+    reg.register_described("'ast::float-literal 'new <v f4>;", AstLiteralCommand::new_float())?;
+    reg.register_described("'ast::string-literal 'new <v str>;", AstLiteralCommand::new_string())?;
+    reg.register_described("'ast::word 'new <name str>;", AstNameCommand::new_word())?;
+    reg.register_described("'ast::variable 'new <name str>;", AstNameCommand::new_variable())?;
+    reg.register_described("'ast::command 'new <name str> [args... generic];", AstCallCommand{})?;
+    reg.register_described("'ast::block 'new [commands... ast];", AstBlockCommand{})?;
+
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::neoshell::parser::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Compiles and runs the given script with the builtin commands, and
+    /// returns the value of its last command.
+    pub fn run(src: &str) -> Result<Value, Error> {
+        run_in(&mut Scope::new(), src)
+    }
+
+    /// Runs the given script like `run`, in the given scope.
+    pub fn run_in(scp: &mut Scope, src: &str) -> Result<Value, Error> {
+        let mut vm = VM::new();
+        register_builtins(vm.get_registery_mut())?;
+
+        let prog = vm.compile(scp, ns_parser::file(src)?)?;
+        vm.run(scp, &prog)
+    }
+
+    /// Runs the given script like `run`, and returns what it wrote into the
+    /// file named by `$out`.
+    pub fn output(src: &str) -> Result<String, Error> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let n = COUNT.fetch_add(1, Ordering::SeqCst);
+        let out = env::temp_dir().join(format!("neoshell-output-{}-{}", process::id(), n));
+        let mut scp = Scope::new();
+        scp.declare("out", Variable::Value(Value::Str(out.to_string_lossy().into_owned())));
+
+        let r = run_in(&mut scp, src).map(|_| fs::read_to_string(&out).unwrap_or_default());
+        let _ = fs::remove_file(&out);
+        r
+    }
+}
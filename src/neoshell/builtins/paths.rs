@@ -0,0 +1,46 @@
+#![deny(missing_docs)]
+//! Builtin commands building paths and expanding glob patterns.
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Path command : `path <path>;`. Converts a string into a path, expanding a
+/// leading `~` into the home directory. The `~` placeholder itself is never
+/// expanded, only the `~` at the start of the string.
+pub struct PathCommand { }
+
+/// Glob generator : `glob <pattern> [/allow-empty] |> ...;`. Generates the
+/// paths of the files matching the pattern, in the order described by `Glob`,
+/// after expanding a leading `~` as `path` does. A pattern matching no file is
+/// an error, unless `/allow-empty` is given.
+pub struct GlobCommand { }
+
+
+
+impl RuntimeCommand for PathCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let path = args.evaluate(vm, scp, "path")?;
+        let home = scp.get_environment_variable(&"HOME".to_owned());
+
+        Ok(Value::Path(expand_home(path.get_string().unwrap(), home)?))
+    }
+}
+
+impl RuntimeCommand for GlobCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let pattern = args.evaluate(vm, scp, "pattern")?;
+        let allow_empty = args.evaluate(vm, scp, "allow-empty")?.get_bool().unwrap_or(false);
+        let home = scp.get_environment_variable(&"HOME".to_owned());
+
+        let pattern = expand_home(pattern.get_string().unwrap(), home)?;
+        let glob = Glob::new(&pattern.to_string_lossy(), allow_empty).interrupted_by(&vm.get_signals());
+
+        Ok(Value::Stream(Type::Path, ValueStream::new(glob)))
+    }
+}
@@ -0,0 +1,184 @@
+#![deny(missing_docs)]
+//! Builtin commands generating and consuming streams.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Range generator : `range <from> <to> |> ...;`. Generates the integers from
+/// `from` to `to`, excluded.
+pub struct RangeCommand { }
+
+/// File reading generator : `read <path> |> ...;`. Generates the lines of the
+/// file, read only when pulled.
+pub struct ReadCommand { }
+
+/// Stream generator : `stream <values> |> ...;`. Generates the items of an
+/// array or of a stream, such as the `$in` stream of a script command.
+pub struct StreamCommand { }
+
+/// Take consumer : `... |> take <n> |> ...;`. Generates the first `n` items of
+/// its input, and stops pulling its input afterward.
+pub struct TakeCommand { }
+
+/// Collect consumer : `... |> collect;`. Returns the items of its input as an
+/// array.
+pub struct CollectCommand { }
+
+/// Count consumer : `... |> count;`. Returns the number of items of its input.
+pub struct CountCommand { }
+
+/// Each consumer : `... |> each <body>;`. Calls the block with each item of its
+/// input, given in `$args`.
+pub struct EachCommand { }
+
+
+
+impl RuntimeCommand for RangeCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let from = args.evaluate(vm, scp, "from")?.get_integer().unwrap_or(0);
+        let to = args.evaluate(vm, scp, "to")?.get_integer().unwrap_or(0);
+
+        Ok(Value::Stream(Type::I8, ValueStream::new((from..to).map(|i| Ok(Value::I8(i))))))
+    }
+}
+
+impl RuntimeCommand for ReadCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let path = match args.evaluate(vm, scp, "path")? {
+            Value::Str(s) => s,
+            v => return Err(ValueError::new_type_mismatch(&Type::Str, v.get_type())),
+        };
+
+        let lines = BufReader::new(File::open(&path)?).lines()
+            .map(|l| l.map(Value::Str).map_err(Error::from));
+
+        Ok(Value::Stream(Type::Str, ValueStream::new(lines)))
+    }
+}
+
+impl RuntimeCommand for StreamCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let values = args.evaluate(vm, scp, "values")?;
+        let ty = values.get_type();
+
+        match values.into_stream() {
+            Some((t, s)) => Ok(Value::Stream(t, s)),
+            None => Err(ValueError::new_type_mismatch(&Type::Stream(Box::new(Type::Generic)), ty)),
+        }
+    }
+}
+
+impl RuntimeCommand for TakeCommand {
+    fn execute(&self, _: &VM, _: &mut Scope, _: &AstCommand) -> Result<Value, Error> {
+        Err(CommandError::new_invalid_arguments("take", "Expected a stream piped into the command."))
+    }
+
+    fn execute_piped(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand, input: ValueStream) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let n = args.evaluate(vm, scp, "n")?.get_integer().unwrap_or(0);
+
+        Ok(Value::Stream(Type::Generic, ValueStream::new(input.take(n.max(0) as usize))))
+    }
+}
+
+impl RuntimeCommand for CollectCommand {
+    fn execute(&self, _: &VM, _: &mut Scope, _: &AstCommand) -> Result<Value, Error> {
+        Ok(Value::Array(Type::Generic, Vec::new()))
+    }
+
+    fn execute_piped(&self, _: &VM, _: &mut Scope, _: &AstCommand, input: ValueStream) -> Result<Value, Error> {
+        Ok(Value::Array(Type::Generic, input.collect::<Result<Vec<Value>, Error>>()?))
+    }
+}
+
+impl RuntimeCommand for CountCommand {
+    fn execute(&self, _: &VM, _: &mut Scope, _: &AstCommand) -> Result<Value, Error> {
+        Ok(Value::I8(0))
+    }
+
+    fn execute_piped(&self, _: &VM, _: &mut Scope, _: &AstCommand, input: ValueStream) -> Result<Value, Error> {
+        let mut n = 0;
+
+        for v in input {
+            v?;
+            n += 1;
+        }
+
+        Ok(Value::I8(n))
+    }
+}
+
+impl RuntimeCommand for EachCommand {
+    fn execute(&self, _: &VM, _: &mut Scope, _: &AstCommand) -> Result<Value, Error> {
+        Ok(Value::Void)
+    }
+
+    fn execute_piped(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand, input: ValueStream) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let body = args.evaluate(vm, scp, "body")?;
+        let body = match body.get_closure() {
+            Some(c) => c,
+            None => return Err(ValueError::new_type_mismatch(&Type::Block, body.get_type())),
+        };
+
+        for v in input {
+            vm.call(body, vec![v?])?;
+        }
+
+        Ok(Value::Void)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::neoshell::builtins::tests::{output, run};
+    use crate::neoshell::vm::*;
+
+    fn value(src: &str) -> String {
+        run(src).unwrap().to_string()
+    }
+
+    #[test]
+    fn generated_items_flow_through_consumers() {
+        assert_eq!(value("range 1 4 |> collect;"), "[1, 2, 3]");
+        assert_eq!(value("range 1 10 |> take 2 |> collect;"), "[1, 2]");
+        assert_eq!(value("range 0 5 |> count;"), "5");
+    }
+
+    #[test]
+    fn consumers_stop_pulling_their_input() {
+        assert_eq!(value("range 0 2000000000 |> take 3 |> count;"), "3");
+    }
+
+    #[test]
+    fn each_calls_its_body_with_every_item() {
+        let src = "range 1 4 |> each &{ puts $args /no-newline stdout = $out /append; };";
+        assert_eq!(output(src).unwrap(), "123");
+    }
+
+    #[test]
+    fn arrays_are_streamed() {
+        assert_eq!(value("let r !{ range 1 3 |> collect }; stream $r |> count;"), "2");
+        assert!(run("stream 1 |> count;").is_err());
+    }
+
+    #[test]
+    fn consumers_need_a_generator() {
+        assert!(run("def-cmd 'one; { value 1; }; one |> count;").is_err());
+
+        let e = run("def-cmd 'one generic|>; { value 1; }; one |> count;").err().unwrap();
+        assert!(matches!(e.downcast_ref::<VMError>(), Some(VMError::NotAGenerator { .. })));
+    }
+}
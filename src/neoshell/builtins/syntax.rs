@@ -0,0 +1,161 @@
+#![deny(missing_docs)]
+//! Builtin commands building pieces of AST, mainly used by macros.
+
+use std::rc::Rc;
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Value command : `value <v>;`. Returns the given value.
+pub struct ValueCommand { }
+
+/// Literal building commands : `ast::integer-literal new <v>;`,
+/// `ast::float-literal new <v>;` and `ast::string-literal new <v>;`.
+pub struct AstLiteralCommand {
+    name: &'static str,
+    ty: Type,
+}
+
+/// Name building commands : `ast::word new <name>;` and
+/// `ast::variable new <name>;`.
+pub struct AstNameCommand {
+    name: &'static str,
+    variable: bool,
+}
+
+/// Command building command : `ast::command new <name> [args...];`. The
+/// arguments are converted to AST literals, pieces of AST being used as-is.
+pub struct AstCallCommand { }
+
+/// Block building command : `ast::block new [commands...];`. Builds an
+/// evaluated block from pieces of AST built with `ast::command`.
+pub struct AstBlockCommand { }
+
+
+
+impl AstLiteralCommand {
+
+    /// Creates the `ast::integer-literal` command.
+    pub fn new_integer() -> AstLiteralCommand {
+        AstLiteralCommand {
+            name: "ast::integer-literal",
+            ty: Type::I4,
+        }
+    }
+    /// Creates the `ast::float-literal` command.
+    pub fn new_float() -> AstLiteralCommand {
+        AstLiteralCommand {
+            name: "ast::float-literal",
+            ty: Type::F4,
+        }
+    }
+    /// Creates the `ast::string-literal` command.
+    pub fn new_string() -> AstLiteralCommand {
+        AstLiteralCommand {
+            name: "ast::string-literal",
+            ty: Type::Str,
+        }
+    }
+
+}
+
+impl AstNameCommand {
+
+    /// Creates the `ast::word` command.
+    pub fn new_word() -> AstNameCommand {
+        AstNameCommand {
+            name: "ast::word",
+            variable: false,
+        }
+    }
+    /// Creates the `ast::variable` command.
+    pub fn new_variable() -> AstNameCommand {
+        AstNameCommand {
+            name: "ast::variable",
+            variable: true,
+        }
+    }
+
+}
+
+
+
+impl RuntimeCommand for ValueCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        vm.bind_arguments(scp, cmd)?.evaluate(vm, scp, "v")
+    }
+}
+
+impl RuntimeCommand for AstLiteralCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let v = match cmd.arguments.as_slice() {
+            [_, v] => vm.evaluate(scp, v)?.cast(&self.ty)?,
+            _ => return Err(CommandError::new_invalid_arguments(self.name, "Expected a value.")),
+        };
+
+        Ok(Value::Ast(Rc::new(AstValue::Argument(v.to_ast()?))))
+    }
+}
+
+impl RuntimeCommand for AstNameCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let n = match cmd.arguments.as_slice() {
+            [_, n] => get_string(vm, scp, n)?,
+            _ => return Err(CommandError::new_invalid_arguments(self.name, "Expected a name.")),
+        };
+        let n = match self.variable {
+            true => AstName::Variable(n),
+            false => AstName::Name(n),
+        };
+
+        Ok(Value::Ast(Rc::new(AstValue::Argument(AstArgument::Name(n)))))
+    }
+}
+
+impl RuntimeCommand for AstCallCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let (n, args) = match cmd.arguments.as_slice() {
+            [_, n, args @ ..] => (get_string(vm, scp, n)?, args),
+            _ => return Err(CommandError::new_invalid_arguments("ast::command", "Expected a command name.")),
+        };
+
+        let mut c = AstCommand::new(AstTime::Runtime, AstName::Name(n));
+        for a in args {
+            c.arguments.push(vm.evaluate(scp, a)?.to_ast()?);
+        }
+
+        Ok(Value::Ast(Rc::new(AstValue::Command(c))))
+    }
+}
+
+impl RuntimeCommand for AstBlockCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let mut cmds = Vec::new();
+
+        for a in cmd.arguments.iter().skip(1) {
+            match vm.evaluate(scp, a)? {
+                Value::Ast(a) => match a.as_ref() {
+                    AstValue::Command(c) => cmds.push(c.clone()),
+                    AstValue::Argument(_) => return Err(CommandError::new_invalid_arguments("ast::block", "Expected commands.")),
+                },
+                v => return Err(ValueError::new_type_mismatch(&Type::Ast, v.get_type())),
+            }
+        }
+
+        Ok(Value::Ast(Rc::new(AstValue::Argument(AstArgument::Block(AstBlock::Evaluated(cmds))))))
+    }
+}
+
+
+
+/// Evaluates an argument into a string.
+fn get_string(vm: &VM, scp: &mut Scope, arg: &AstArgument) -> Result<String, Error> {
+    match vm.evaluate(scp, arg)? {
+        Value::Str(s) => Ok(s),
+        v => Err(ValueError::new_type_mismatch(&Type::Str, v.get_type())),
+    }
+}
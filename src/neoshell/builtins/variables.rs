@@ -0,0 +1,154 @@
+#![deny(missing_docs)]
+//! Builtin commands declaring and assigning variables.
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::tools::*;
+use crate::neoshell::vm::*;
+
+
+
+/// Variable declaration command : `let <name> [type] <value>;` or
+/// `const <name> [type] <value>;`. When the type is omitted, it is inferred
+/// from the value. Each command has an overload with the type and one
+/// without.
+pub struct DeclareCommand {
+    name: &'static str,
+    constant: bool,
+}
+
+/// Variable assignment command : `set <name> <value>;`.
+pub struct SetCommand { }
+
+/// Variable exportation command : `export <name> [value];`. With a value, the
+/// variable is declared in the current scope before being exported, otherwise
+/// the visible variable is exported.
+pub struct ExportCommand { }
+
+
+
+impl DeclareCommand {
+
+    /// Creates the `let` command, declaring mutable variables.
+    pub fn new_let() -> DeclareCommand {
+        DeclareCommand {
+            name: "let",
+            constant: false,
+        }
+    }
+    /// Creates the `const` command, declaring constant variables.
+    pub fn new_const() -> DeclareCommand {
+        DeclareCommand {
+            name: "const",
+            constant: true,
+        }
+    }
+
+}
+
+impl RuntimeCommand for DeclareCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let var = get_variable_name(self.name, &args)?;
+        let ty = match args.get_word("type") {
+            Some(t) => match Type::from_name(t) {
+                Some(t) => Some(t),
+                None => return Err(CommandError::new_invalid_arguments(self.name, &format!("Unknown type '{}'.", t))),
+            },
+            None if args.is_omitted("type") => None,
+            None => return Err(CommandError::new_invalid_arguments(self.name, "Expected a type name.")),
+        };
+        let val = args.evaluate(vm, scp, "value")?;
+
+        scp.declare_with(var, Variable::from_value(val), ty.as_ref(), self.constant)?;
+        Ok(Value::Void)
+    }
+}
+
+impl RuntimeCommand for SetCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let var = get_variable_name("set", &args)?;
+        let val = args.evaluate(vm, scp, "value")?;
+
+        scp.assign(var, Variable::from_value(val))?;
+        Ok(Value::Void)
+    }
+}
+
+
+impl RuntimeCommand for ExportCommand {
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        let args = vm.bind_arguments(scp, cmd)?;
+        let var = get_variable_name("export", &args)?;
+
+        if !args.is_omitted("value") {
+            let val = args.evaluate(vm, scp, "value")?;
+            scp.declare_with(var, Variable::from_value(val), None, false)?;
+        }
+
+        scp.export(var)?;
+        Ok(Value::Void)
+    }
+}
+
+
+
+/// Gets the name of the variable declared or assigned by a command.
+fn get_variable_name<'a>(cmd: &str, args: &'a BoundArguments) -> Result<&'a String, Error> {
+    match args.get_word("name") {
+        Some(n) => Ok(n),
+        None => Err(CommandError::new_invalid_arguments(cmd, "Expected a variable name.")),
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::neoshell::builtins::tests::{output, run};
+
+    fn value(src: &str) -> String {
+        run(src).unwrap().to_string()
+    }
+
+    #[test]
+    fn declarations_infer_or_cast_the_type() {
+        assert_eq!(value("let a 1; value $a;"), "1");
+        assert_eq!(value("let a u1 7; value $a;"), "7");
+        assert!(run("let a u1 300;").is_err());
+        assert!(run("let a nothing 1;").is_err());
+    }
+
+    #[test]
+    fn assignments_change_visible_variables() {
+        assert_eq!(value("let a 1; set a 2; value $a;"), "2");
+        assert_eq!(value("let a 1; let u !{ set a 3; }; value $a;"), "3");
+        assert_eq!(value("let a 1; let u !{ let a 4; }; value $a;"), "1");
+        assert!(run("let a 1; set a \"s\";").is_err());
+    }
+
+    #[test]
+    fn constants_and_undeclared_variables_cannot_be_assigned() {
+        assert_eq!(value("const c 5; value $c;"), "5");
+        assert!(run("const c 5; set c 6;").is_err());
+        assert!(run("const c 5; const c 6;").is_err());
+        assert!(run("set b 1;").is_err());
+    }
+
+    #[test]
+    fn exported_variables_are_given_to_programs() {
+        let src = "export NEOSHELL_VALUE \"a\"; sh \"-c\" \"echo $NEOSHELL_VALUE\" stdout = $out;";
+        assert_eq!(output(src).unwrap(), "a\n");
+
+        let src = "let NEOSHELL_VALUE \"b\"; sh \"-c\" \"echo x$NEOSHELL_VALUE\" stdout = $out;";
+        assert_eq!(output(src).unwrap(), "x\n");
+    }
+
+    #[test]
+    fn environments_are_overridden_for_one_program() {
+        let src = "sh \"-c\" \"echo $NEOSHELL_VALUE\" env = { NEOSHELL_VALUE \"c\"; } stdout = $out; sh \"-c\" \"echo x$NEOSHELL_VALUE\" stdout = $out /append;";
+        assert_eq!(output(src).unwrap(), "c\nx\n");
+    }
+}
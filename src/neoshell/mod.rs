@@ -1,4 +1,5 @@
 
+pub mod builtins;
 pub mod parser;
 pub mod tools;
 pub mod vm;
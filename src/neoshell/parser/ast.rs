@@ -1,8 +1,12 @@
 #![deny(missing_docs)]
+//! Abstract syntax tree of Neoshell scripts.
 
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 
 /// AST Command.
+#[derive(Clone)]
 pub struct AstCommand {
     /// Execution time of the command.
     pub time: AstTime,
@@ -10,10 +14,26 @@ pub struct AstCommand {
     pub name: AstName,
     /// Argument of the command.
     pub arguments: Vec<AstArgument>,
+    /// Command consuming the stream generated by this command (`|>`).
+    pub pipe: Option<Box<AstCommand>>,
+    /// Redirections of the standard streams of the command, separated from
+    /// its arguments at compile time.
+    pub redirections: Vec<AstSwitch>,
+    /// Location of the command in the source, if it was parsed.
+    pub span: Option<AstSpan>,
+}
+
+/// Location of a piece of code, as byte offsets in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AstSpan {
+    /// Offset of the first byte.
+    pub start: usize,
+    /// Offset following the last byte.
+    pub end: usize,
 }
 
 /// Command execution time.
-#[derive(PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AstTime {
     /// Command executed at compile time.
     CompileTime,
@@ -24,7 +44,7 @@ pub enum AstTime {
 }
 
 /// Argument for a command.
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum AstArgument {
     /// No arguments.
     None,
@@ -40,29 +60,40 @@ pub enum AstArgument {
     Switch(AstSwitch),
     /// A command block.
     Block(AstBlock),
+    /// A command descriptor.
+    Descriptor(AstDescriptor),
 }
 
 /// A command switch.
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum AstSwitch {
     /// An enabling switch.
     On(String),
     /// A disabling switch.
     Off(String),
     /// An option switch, associated with a value
-    Option(String, Box<AstArgument>)
+    Option(String, Box<AstArgument>),
+    /// A list switch, associated with a list of values.
+    List(String, Vec<AstArgument>),
+    /// A choice switch, associated with the chosen value.
+    Choice(String, Box<AstArgument>),
+    /// A multi-choice switch, associated with the chosen values.
+    MultiChoice(String, Vec<AstArgument>),
 }
 
 /// A command block.
+#[derive(Clone)]
 pub enum AstBlock {
     /// An evaluated block, whos result is passed to the command.
     Evaluated(Vec<AstCommand>),
     /// A static block, passed directly to the command as an argument.
     Argument(Vec<AstCommand>),
+    /// A block whose usage is inferred from the command receiving it.
+    Inferred(Vec<AstCommand>),
 }
 
 /// A name in the AST.
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum AstName {
     /// Placeholder name, used by macros.
     Placeholder,
@@ -70,20 +101,95 @@ pub enum AstName {
     Name(String),
     /// A variable.
     Variable(String),
+    /// An evaluated block giving the command to invoke : `!{...}`.
+    Block(AstBlock),
+}
+
+/// A command descriptor : `'name |>type type|> arguments... ;`.
+#[derive(Clone, PartialEq)]
+pub struct AstDescriptor {
+    /// Name of the described command.
+    pub name: String,
+    /// Type of the input stream, if the command accepts one.
+    pub input: Option<String>,
+    /// Type of the output stream, if the command generates one.
+    pub output: Option<String>,
+    /// Descriptions of the arguments.
+    pub arguments: Vec<AstDescriptorItem>,
+}
+
+/// An item of a command descriptor.
+#[derive(Clone, PartialEq)]
+pub enum AstDescriptorItem {
+    /// A static word : `'word`.
+    Static(String),
+    /// A mandatory argument : `<argument>`.
+    Mandatory(AstArgumentDescriptor),
+    /// A group of optional arguments : `[argument; argument...]`.
+    Optional(Vec<AstArgumentDescriptor>),
+}
+
+/// Description of an argument.
+#[derive(Clone, PartialEq)]
+pub struct AstArgumentDescriptor {
+    /// Name of the argument.
+    pub name: String,
+    /// Kind of the argument.
+    pub kind: AstArgumentKind,
+}
+
+/// Kind of an argument, with its type and default value.
+#[derive(Clone, PartialEq)]
+pub enum AstArgumentKind {
+    /// Positional argument : `name type [default]`.
+    Positional(String, Option<AstArgument>),
+    /// Positional list argument : `name... type [default]`.
+    PositionalList(String, Option<AstArgument>),
+    /// Flag argument : `/name [default enabled]`.
+    Flag(Option<(AstArgument, AstArgument)>),
+    /// Option argument : `name = type [default]`.
+    Option(String, Option<AstArgument>),
+    /// List argument : `name[] = type [default]`.
+    List(String, Option<AstArgument>),
+    /// Choice argument : `name -> v0,v1,...,vN [default]`.
+    Choice(Vec<AstArgument>, Option<AstArgument>),
+    /// Named choice argument : `name -> n0=v0,n1=v1,...,nN=vN [default]`.
+    NamedChoice(Vec<(String, AstArgument)>, Option<AstArgument>),
+    /// Multi-choice argument : `name => v0,v1,...,vN [default]`.
+    MultiChoice(Vec<AstArgument>, Option<AstArgument>),
+    /// Named multi-choice argument : `name => n0=v0,n1=v1,...,nN=vN [default]`.
+    NamedMultiChoice(Vec<(String, AstArgument)>, Option<AstArgument>),
 }
 
 
 
 
+impl AstSpan {
+
+    /// Gets the line and the column, both starting at 1, of the start of the
+    /// span in the given source.
+    pub fn locate(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.start.min(src.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+
+        (line, col)
+    }
+
+}
+
 impl AstCommand {
 
     /// Creates a new command without any arguments.
     ///
     pub fn new(time: AstTime, name: AstName) -> AstCommand {
         AstCommand {
-            time: time,
-            name: name,
+            time,
+            name,
             arguments: Vec::new(),
+            pipe: None,
+            redirections: Vec::new(),
+            span: None,
         }
     }
     /// Creates a new command from the given one, using other arguments.
@@ -92,7 +198,10 @@ impl AstCommand {
         AstCommand {
             time: cmd.time,
             name: cmd.name,
-            arguments: arguments,
+            arguments,
+            pipe: cmd.pipe,
+            redirections: cmd.redirections,
+            span: cmd.span,
         }
     }
 
@@ -115,6 +224,18 @@ impl AstCommand {
         for a in self.arguments.iter() {
             a.dump(format!("{}    ", i));
         }
+
+        if !self.redirections.is_empty() {
+            println!("{}  Redirections :", &i);
+            for r in self.redirections.iter() {
+                r.dump(format!("{}    ", i));
+            }
+        }
+
+        if let Some(p) = &self.pipe {
+            println!("{}  Pipe :", &i);
+            p.dump(format!("{}    ", i));
+        }
     }
 
 }
@@ -132,6 +253,7 @@ impl AstArgument {
             AstArgument::String(v)  => println!("{}String( {} )", i, v),
             AstArgument::Switch(v)  => v.dump(i),
             AstArgument::Block(v)   => v.dump(i),
+            AstArgument::Descriptor(v) => println!("{}Descriptor( {} )", i, v),
         }
     }
 
@@ -148,7 +270,23 @@ impl AstSwitch {
             AstSwitch::Option(n, v) => {
                 println!("{}OptionSwitch( {} )", i, n);
                 v.dump(format!("{}  ", i));
-            }
+            },
+            AstSwitch::List(n, v) => {
+                println!("{}ListSwitch( {} )", i, n);
+                for a in v {
+                    a.dump(format!("{}  ", i));
+                }
+            },
+            AstSwitch::Choice(n, v) => {
+                println!("{}ChoiceSwitch( {} )", i, n);
+                v.dump(format!("{}  ", i));
+            },
+            AstSwitch::MultiChoice(n, v) => {
+                println!("{}MultiChoiceSwitch( {} )", i, n);
+                for a in v {
+                    a.dump(format!("{}  ", i));
+                }
+            },
         }
     }
 
@@ -171,6 +309,12 @@ impl AstBlock {
                 for c in v {
                     c.dump(format!("{}  ", i));
                 }
+            },
+            AstBlock::Inferred(v) => {
+                println!("{}InferredBlock", i);
+                for c in v {
+                    c.dump(format!("{}  ", i));
+                }
             }
         }
     }
@@ -178,9 +322,9 @@ impl AstBlock {
 }
 
 impl PartialEq for AstBlock {
-	fn eq(&self, other: &Self) -> bool {
+	fn eq(&self, _other: &Self) -> bool {
 		// blocks cannot be compared.
-		return false;
+		false
 	}
 }
 
@@ -199,7 +343,143 @@ impl AstName {
             AstName::Placeholder => println!("{}PlaceholderName", i),
             AstName::Name(v)     => println!("{}Name( {} )", i, v),
             AstName::Variable(v) => println!("{}Variable( {} )", i, v),
+            AstName::Block(_)    => println!("{}Block", i),
+        }
+    }
+
+}
+
+impl fmt::Display for AstTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AstTime::CompileTime => write!(f, "compile time"),
+            AstTime::Macro => write!(f, "macro"),
+            AstTime::Runtime => write!(f, "runtime"),
         }
     }
+}
+
+impl fmt::Display for AstCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.time {
+            AstTime::CompileTime => write!(f, "!{}", self.name)?,
+            AstTime::Macro       => write!(f, "{}!", self.name)?,
+            AstTime::Runtime     => write!(f, "{}", self.name)?,
+        }
+
+        for a in self.arguments.iter() {
+            write!(f, " {}", a)?;
+        }
+        for r in self.redirections.iter() {
+            write!(f, " {}", r)?;
+        }
+
+        match &self.pipe {
+            Some(p) => write!(f, " |> {}", p),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for AstName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AstName::Placeholder => write!(f, "~"),
+            AstName::Name(v)     => write!(f, "{}", v),
+            AstName::Variable(v) => write!(f, "${}", v),
+            AstName::Block(_)    => write!(f, "!{{ ... }}"),
+        }
+    }
+}
+
+impl fmt::Display for AstArgument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AstArgument::None       => Ok(()),
+            AstArgument::Name(v)    => write!(f, "{}", v),
+            AstArgument::Integer(v) => write!(f, "{}", v),
+            AstArgument::Float(v)   => write!(f, "{:?}", v),
+            AstArgument::String(v)  => write!(f, "{:?}", v),
+            AstArgument::Switch(v)  => write!(f, "{}", v),
+            AstArgument::Block(v)   => match v {
+                AstBlock::Evaluated(_) => write!(f, "!{{ ... }}"),
+                AstBlock::Argument(_)  => write!(f, "&{{ ... }}"),
+                AstBlock::Inferred(_)  => write!(f, "{{ ... }}"),
+            },
+            AstArgument::Descriptor(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl fmt::Display for AstDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}", self.name)?;
+
+        if let Some(t) = &self.input {
+            write!(f, " |>{}", t)?;
+        }
+        if let Some(t) = &self.output {
+            write!(f, " {}|>", t)?;
+        }
+
+        for a in self.arguments.iter() {
+            match a {
+                AstDescriptorItem::Static(w)    => write!(f, " '{}", w)?,
+                AstDescriptorItem::Mandatory(d) => write!(f, " <{}>", d)?,
+                AstDescriptorItem::Optional(v)  => {
+                    let v: Vec<String> = v.iter().map(|d| d.to_string()).collect();
+                    write!(f, " [{}]", v.join("; "))?
+                },
+            }
+        }
+
+        write!(f, ";")
+    }
+}
+
+impl fmt::Display for AstSwitch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AstSwitch::On(n)              => write!(f, "/{}", n),
+            AstSwitch::Off(n)             => write!(f, "-{}", n),
+            AstSwitch::Option(n, v)       => write!(f, "{} = {}", n, v),
+            AstSwitch::List(n, v)         => write!(f, "{}[] = {}", n, join(v)),
+            AstSwitch::Choice(n, v)       => write!(f, "{} -> {}", n, v),
+            AstSwitch::MultiChoice(n, v)  => write!(f, "{} => {}", n, join(v)),
+        }
+    }
+}
+
+impl fmt::Display for AstArgumentDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let def = |d: &Option<AstArgument>| match d {
+            Some(v) => format!(" {}", v),
+            None => String::new(),
+        };
+        let pairs = |v: &Vec<(String, AstArgument)>| {
+            let v: Vec<String> = v.iter().map(|(n, v)| format!("{}={}", n, v)).collect();
+            v.join(",")
+        };
+
+        match &self.kind {
+            AstArgumentKind::Positional(t, d)       => write!(f, "{} {}{}", self.name, t, def(d)),
+            AstArgumentKind::PositionalList(t, d)   => write!(f, "{}... {}{}", self.name, t, def(d)),
+            AstArgumentKind::Flag(None)             => write!(f, "/{}", self.name),
+            AstArgumentKind::Flag(Some((d, e)))     => write!(f, "/{} {} {}", self.name, d, e),
+            AstArgumentKind::Option(t, d)           => write!(f, "{} = {}{}", self.name, t, def(d)),
+            AstArgumentKind::List(t, d)             => write!(f, "{}[] = {}{}", self.name, t, def(d)),
+            AstArgumentKind::Choice(v, d)           => write!(f, "{} -> {}{}", self.name, join(v), def(d)),
+            AstArgumentKind::NamedChoice(v, d)      => write!(f, "{} -> {}{}", self.name, pairs(v), def(d)),
+            AstArgumentKind::MultiChoice(v, d)      => write!(f, "{} => {}{}", self.name, join(v), def(d)),
+            AstArgumentKind::NamedMultiChoice(v, d) => write!(f, "{} => {}{}", self.name, pairs(v), def(d)),
+        }
+    }
+}
+
+
 
+/// Joins a list of arguments with commas.
+fn join(v: &[AstArgument]) -> String {
+    let v: Vec<String> = v.iter().map(|a| a.to_string()).collect();
+    v.join(",")
 }
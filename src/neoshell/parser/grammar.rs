@@ -1,4 +1,6 @@
 
+use std::convert::TryFrom;
+
 use super::ast::*;
 
 
@@ -6,27 +8,156 @@ peg::parser!{
     pub grammar ns_parser() for str {
 
 
-		rule pipe_input_description()
-			= "|>" typename()
-		rule pipe_output_description()
-			= typename() "|>"
+		// file
+		pub rule file() -> Vec<AstCommand>
+			= _ c:(c:pipeline() _ ";" _ { c })* eof() { c }
+
+		// descriptor
+		pub rule descriptor() -> AstDescriptor
+			= _ d:desc_command() _ eof() { d }
+
+
+
+		// command/pipeline
+		rule pipeline() -> AstCommand
+			= c:command() ++ (_ "|>" _) { chain_pipeline(c) }
+
+		// command
+		rule command() -> AstCommand
+			= s:position!() c:command_body() e:position!() { with_span(c, s, e) }
+		rule command_body() -> AstCommand
+			= "!" n:path() a:arguments() { new_command(AstTime::CompileTime, AstName::Name(n), a) }
+			/ n:path() "!" a:arguments() { new_command(AstTime::Macro, AstName::Name(n), a) }
+			/ n:command_name() a:arguments() { new_command(AstTime::Runtime, n, a) }
+
+		// command/name
+		rule command_name() -> AstName
+			= "!" c:block_content() { AstName::Block(AstBlock::Evaluated(c)) }
+			/ name()
+
+		// command/arguments
+		rule arguments() -> Vec<AstArgument>
+			= a:(_ a:argument() { a })* { a }
+
+
+
+		// argument
+		rule argument() -> AstArgument
+			= s:switch() { AstArgument::Switch(s) }
+			/ value()
+
+		// argument/value
+		rule value() -> AstArgument
+			= b:block() { AstArgument::Block(b) }
+			/ s:string() { AstArgument::String(s) }
+			/ c:character() { AstArgument::Integer(c as i32) }
+			/ d:desc_command() { AstArgument::Descriptor(d) }
+			/ "--" !identifier_continue() { AstArgument::Name(AstName::Name("--".to_owned())) }
+			/ number()
+			/ n:name() { AstArgument::Name(n) }
+
+		// argument/switch
+		rule switch() -> AstSwitch
+			= "/" n:identifier() { AstSwitch::On(n) }
+			/ n:identifier() "[]" _ "=" _ v:value() ++ comma_sep() { AstSwitch::List(n, v) }
+			/ n:identifier() _ "=>" _ v:value() ++ comma_sep() { AstSwitch::MultiChoice(n, v) }
+			/ n:identifier() _ "->" _ v:value() { AstSwitch::Choice(n, Box::new(v)) }
+			/ n:identifier() _ "=" _ v:value() { AstSwitch::Option(n, Box::new(v)) }
+
+
+
+		// block
+		rule block() -> AstBlock
+			= "!" c:block_content() { AstBlock::Evaluated(c) }
+			/ "&" c:block_content() { AstBlock::Argument(c) }
+			/ c:block_content() { AstBlock::Inferred(c) }
+
+		// block/content
+		rule block_content() -> Vec<AstCommand>
+			= "{" _ c:pipeline() _ "}" { vec![c] }
+			/ "{" _ c:(c:pipeline() _ ";" _ { c })* "}" { c }
+
+
+
+		// name
+		rule name() -> AstName
+			= "~" { AstName::Placeholder }
+			/ "$" n:identifier() { AstName::Variable(n) }
+			/ n:path() { AstName::Name(n) }
+
+		// name/path
+		rule path() -> String
+			= p:$(identifier() ++ "::") { p.to_owned() }
+
+
+
+		// literal/string
+		rule string() -> String
+			= "\"{" s:$(block_string_content()) "}\"" { s.to_owned() }
+			/ "\"" s:string_char()* "\"" { s.into_iter().collect() }
+
+		rule block_string_content()
+			= ("\"{" block_string_content() "}\"" / !"}\"" [_])*
+
+		rule string_char() -> char
+			= "\\" c:escape() { c }
+			/ !['"' | '\\'] c:$([_]) { first_char(c) }
+
+		rule escape() -> char
+			= "n" { '\n' }
+			/ "r" { '\r' }
+			/ "t" { '\t' }
+			/ "0" { '\0' }
+			/ "\\" { '\\' }
+			/ "\"" { '"' }
+			/ "'" { '\'' }
+			/ "u{" h:$(['0'..='9' | 'a'..='f' | 'A'..='F']+) "}" {?
+				u32::from_str_radix(h, 16).ok().and_then(std::char::from_u32).ok_or("unicode escape")
+			}
+
+		// literal/character
+		rule character() -> char
+			= "'" c:(!['\'' | '\\'] c:$([_]) { first_char(c) } / "\\" c:escape() { c }) "'" { c }
+
+
+
+		// literal/number
+		rule number() -> AstArgument
+			= f:float() { AstArgument::Float(f) }
+			/ i:integer() { AstArgument::Integer(i) }
+
+		// literal/number/float
+		rule float() -> f32
+			= s:$("-"? digits() "." digits()?) {? s.replace("_", "").parse().or(Err("float")) }
+
+		// literal/number/integer
+		rule integer() -> i32
+			= s:$("-"?) "0" b:$(['a'..='z' | 'A'..='Z']) d:$(['0'..='9' | 'a'..='z' | 'A'..='Z'] ['0'..='9' | 'a'..='z' | 'A'..='Z' | '_']*) {?
+				parse_based_integer(s, first_char(b), d).ok_or("integer")
+			}
+			/ s:$("-"? digits()) {? s.replace("_", "").parse().or(Err("integer")) }
+
+		rule digits()
+			= digit() (digit() / "_")*
 
 
 
 		// descriptor/command
-		rule desc_command()
-			= "'" _ identifier() _ desc_pipe_input()? _ desc_pipe_output()? _ desc_arg()* _ ";"
+		rule desc_command() -> AstDescriptor
+			= "'" _ n:path() _ i:desc_pipe_input()? _ o:desc_pipe_output()? _ a:desc_arg()* _ ";" {
+				AstDescriptor { name: n, input: i, output: o, arguments: a }
+			}
 
 		// descriptor/command/pipe-input
-		rule desc_pipe_input()
-			= "|>" typename()
+		rule desc_pipe_input() -> String
+			= "|>" t:typename() { t }
 
 		// descriptor/command/pipe-output
-		rule desc_pipe_output()
-			= typename() "|>"
+		rule desc_pipe_output() -> String
+			= t:typename() "|>" { t }
 
 		// descriptor/command/argument
-		rule desc_arg()
+		rule desc_arg() -> AstDescriptorItem
 			= desc_arg_static()
 			/ desc_mendatory_arg()
 			/ desc_optional_arg()
@@ -34,96 +165,118 @@ peg::parser!{
 
 
 		// descriptor/command/static-argument
-		rule desc_arg_static()
-			= "'" identifier() _
+		rule desc_arg_static() -> AstDescriptorItem
+			= "'" i:identifier() _ { AstDescriptorItem::Static(i) }
 		// descriptor/command/mandatory-argument
-		rule desc_mendatory_arg()
-			= "<" _ desc_arg_without_def() _ ">" _
+		rule desc_mendatory_arg() -> AstDescriptorItem
+			= "<" _ a:desc_arg_without_def() _ ">" _ { AstDescriptorItem::Mandatory(a) }
 		// descriptor/command/optional-argument
-		rule desc_optional_arg()
-			= "[" _ desc_arg_with_def() ** semi_sep() _ "]" _
+		rule desc_optional_arg() -> AstDescriptorItem
+			= "[" _ a:(desc_arg_with_def() / desc_arg_without_def()) ** semi_sep() _ "]" _ { AstDescriptorItem::Optional(a) }
 
 
 
-		rule desc_arg_with_def()
+		rule desc_arg_with_def() -> AstArgumentDescriptor
 			= desc_arg_pos_with_def()
 			/ desc_arg_pos_list_with_def()
 			/ desc_arg_flag_with_def()
 			/ desc_arg_opt_with_def()
 			/ desc_arg_list_with_def()
-			/ desc_arg_choice_with_def()
 			/ desc_arg_nchoice_with_def()
-			/ desc_arg_mchoice_with_def()
+			/ desc_arg_choice_with_def()
 			/ desc_arg_nmchoice_with_def()
+			/ desc_arg_mchoice_with_def()
 
-		rule desc_arg_without_def()
+		rule desc_arg_without_def() -> AstArgumentDescriptor
 			= desc_arg_pos_without_def()
 			/ desc_arg_pos_list_without_def()
 			/ desc_arg_flag_without_def()
-			/ desc_arg_opt_with_def()
+			/ desc_arg_opt_without_def()
 			/ desc_arg_list_without_def()
-			/ desc_arg_choice_without_def()
 			/ desc_arg_nchoice_without_def()
-			/ desc_arg_mchoice_without_def()
+			/ desc_arg_choice_without_def()
 			/ desc_arg_nmchoice_without_def()
+			/ desc_arg_mchoice_without_def()
 
 
 
 		// descriptor/argument/positional
-		rule desc_arg_pos_with_def()
-			= desc_arg_pos_without_def() _ some_value()
-		rule desc_arg_pos_without_def()
-			= identifier() _ typename()
+		rule desc_arg_pos_with_def() -> AstArgumentDescriptor
+			= n:identifier() _ t:typename() _ d:some_value() { arg_desc(n, AstArgumentKind::Positional(t, Some(d))) }
+		rule desc_arg_pos_without_def() -> AstArgumentDescriptor
+			= n:identifier() _ t:typename() { arg_desc(n, AstArgumentKind::Positional(t, None)) }
 
 		// descriptor/argument/positional-list
-		rule desc_arg_pos_list_with_def()
-			= desc_arg_pos_list_without_def() _ some_value()
-		rule desc_arg_pos_list_without_def()
-			= identifier() "..." _ typename()
+		rule desc_arg_pos_list_with_def() -> AstArgumentDescriptor
+			= n:identifier() "..." _ t:typename() _ d:some_value() { arg_desc(n, AstArgumentKind::PositionalList(t, Some(d))) }
+		rule desc_arg_pos_list_without_def() -> AstArgumentDescriptor
+			= n:identifier() "..." _ t:typename() { arg_desc(n, AstArgumentKind::PositionalList(t, None)) }
 
 		// descriptor/argument/flag
-		rule desc_arg_flag_with_def()
-			= desc_arg_flag_without_def() _ some_value() _ some_value()
-		rule desc_arg_flag_without_def()
-			= "/" identifier()
+		rule desc_arg_flag_with_def() -> AstArgumentDescriptor
+			= "/" n:identifier() _ d:some_value() _ e:some_value() { arg_desc(n, AstArgumentKind::Flag(Some((d, e)))) }
+		rule desc_arg_flag_without_def() -> AstArgumentDescriptor
+			= "/" n:identifier() { arg_desc(n, AstArgumentKind::Flag(None)) }
 
 		// descriptor/argument/option
-		rule desc_arg_opt_with_def()
-			= desc_arg_opt_without_def() _ some_value()
-		rule desc_arg_opt_without_def()
-			= identifier() _ "=" _ typename()
+		rule desc_arg_opt_with_def() -> AstArgumentDescriptor
+			= n:identifier() _ "=" _ t:typename() _ d:some_value() { arg_desc(n, AstArgumentKind::Option(t, Some(d))) }
+		rule desc_arg_opt_without_def() -> AstArgumentDescriptor
+			= n:identifier() _ "=" _ t:typename() { arg_desc(n, AstArgumentKind::Option(t, None)) }
 
 		// descriptor/argument/list
-		rule desc_arg_list_with_def()
-			= desc_arg_list_without_def() _ some_value()
-		rule desc_arg_list_without_def()
-			= identifier() "[]" _ "=" _ typneame()
+		rule desc_arg_list_with_def() -> AstArgumentDescriptor
+			= n:identifier() "[]" _ "=" _ t:typename() _ d:some_value() { arg_desc(n, AstArgumentKind::List(t, Some(d))) }
+		rule desc_arg_list_without_def() -> AstArgumentDescriptor
+			= n:identifier() "[]" _ "=" _ t:typename() { arg_desc(n, AstArgumentKind::List(t, None)) }
 
 		// descriptor/argument/choice
-		rule desc_arg_choice_with_def()
-			= desc_arg_choice_without_def() _ some_value()
-		rule desc_arg_choice_without_def()
-			= identifier() _ "->" _ some_value() ** comma_sep()
+		rule desc_arg_choice_with_def() -> AstArgumentDescriptor
+			= n:identifier() _ "->" _ v:some_value() ++ comma_sep() _ d:some_value() { arg_desc(n, AstArgumentKind::Choice(v, Some(d))) }
+		rule desc_arg_choice_without_def() -> AstArgumentDescriptor
+			= n:identifier() _ "->" _ v:some_value() ++ comma_sep() { arg_desc(n, AstArgumentKind::Choice(v, None)) }
 
 		// descriptor/argument/named-choice
-		rule desc_arg_nchoice_with_def()
-			= desc_arg_nchoice_without_def() _ some_value()
-		rule desc_arg_nchoice_without_def()
-			= identifier() _ "->" _ some_name_pair_value() ** comma_sep()
+		rule desc_arg_nchoice_with_def() -> AstArgumentDescriptor
+			= n:identifier() _ "->" _ v:some_name_pair_value() ++ comma_sep() _ d:some_value() { arg_desc(n, AstArgumentKind::NamedChoice(v, Some(d))) }
+		rule desc_arg_nchoice_without_def() -> AstArgumentDescriptor
+			= n:identifier() _ "->" _ v:some_name_pair_value() ++ comma_sep() { arg_desc(n, AstArgumentKind::NamedChoice(v, None)) }
 
 		// descriptor/argument/multi-choice
-		rule desc_arg_mchoice_with_def()
-			= desc_arg_mchoice_without_def() _ some_value()
-		rule desc_arg_mchoice_without_def()
-			= identifier() _ "=>" _ some_value() ** comma_sep()
+		rule desc_arg_mchoice_with_def() -> AstArgumentDescriptor
+			= n:identifier() _ "=>" _ v:some_value() ++ comma_sep() _ d:some_value() { arg_desc(n, AstArgumentKind::MultiChoice(v, Some(d))) }
+		rule desc_arg_mchoice_without_def() -> AstArgumentDescriptor
+			= n:identifier() _ "=>" _ v:some_value() ++ comma_sep() { arg_desc(n, AstArgumentKind::MultiChoice(v, None)) }
 
 		// descriptor/argument/named-multi-choice
-		rule desc_arg_nmchoice_with_def()
-			= desc_arg_nmchoice_without_def() _ some_value()
-		rule desc_arg_nmchoice_without_def()
-			= identifier() _ "=>" _ some_name_pair_value() ** comma_sep()
+		rule desc_arg_nmchoice_with_def() -> AstArgumentDescriptor
+			= n:identifier() _ "=>" _ v:some_name_pair_value() ++ comma_sep() _ d:some_value() { arg_desc(n, AstArgumentKind::NamedMultiChoice(v, Some(d))) }
+		rule desc_arg_nmchoice_without_def() -> AstArgumentDescriptor
+			= n:identifier() _ "=>" _ v:some_name_pair_value() ++ comma_sep() { arg_desc(n, AstArgumentKind::NamedMultiChoice(v, None)) }
+
+
+
+		// descriptor/value
+		rule some_value() -> AstArgument
+			= s:string() { AstArgument::String(s) }
+			/ c:character() { AstArgument::Integer(c as i32) }
+			/ number()
+			/ n:path() { AstArgument::Name(AstName::Name(n)) }
+		rule some_name_pair_value() -> (String, AstArgument)
+			= n:identifier() "=" v:some_value() { (n, v) }
 
+		// descriptor/type
+		rule typename() -> String
+			= t:$("[" _ typename() _ "]" / "<" _ typename() _ ">" / identifier()) { t.to_owned() }
 
+		rule comma_sep()
+			= _ "," _
+		rule semi_sep()
+			= _ ";" _
+
+
+        rule identifier() -> String
+            = i:$(identifier_start() identifier_continue()*) { i.to_owned() }
         rule identifier_start()
             = alpha() / "_"
         rule identifier_continue()
@@ -145,7 +298,7 @@ peg::parser!{
         rule space()
             = " " / "\n" / "\t" / "\r"
         rule comment()
-            = "#" (!eol() [_])* eol()
+            = "#" (!eol() [_])* (eol() / eof())
 
 
         rule eol()
@@ -157,9 +310,55 @@ peg::parser!{
 
 
 
-fn extract_name(n: AstName) -> String {
-    match n {
-        AstName::Name(v) => v,
-        _                => unreachable!(),
+fn first_char(s: &str) -> char {
+    s.chars().next().unwrap()
+}
+
+fn new_command(time: AstTime, name: AstName, arguments: Vec<AstArgument>) -> AstCommand {
+    AstCommand::extends(AstCommand::new(time, name), arguments)
+}
+
+fn with_span(mut cmd: AstCommand, start: usize, end: usize) -> AstCommand {
+    cmd.span = Some(AstSpan {
+        start,
+        end,
+    });
+    cmd
+}
+
+fn chain_pipeline(cmds: Vec<AstCommand>) -> AstCommand {
+    let mut iter = cmds.into_iter().rev();
+    let mut last = iter.next().unwrap();
+
+    for mut c in iter {
+        c.pipe = Some(Box::new(last));
+        last = c;
+    }
+
+    last
+}
+
+fn arg_desc(name: String, kind: AstArgumentKind) -> AstArgumentDescriptor {
+    AstArgumentDescriptor {
+        name,
+        kind,
+    }
+}
+
+/// Parses an integer whose base is given by the index of a letter in the
+/// alphabet (`b` for binary, `p` for hexadecimal...).
+fn parse_based_integer(sign: &str, base: char, digits: &str) -> Option<i32> {
+    let base = base.to_ascii_lowercase() as u32 - 'a' as u32 + 1;
+
+    if base < 2 {
+        return None;
+    }
+
+    let v = i64::from_str_radix(&digits.replace("_", ""), base).ok()?;
+
+    if sign == "-" {
+        i32::try_from(-v).ok()
+    } else {
+        i32::try_from(v).ok()
     }
 }
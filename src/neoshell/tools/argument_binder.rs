@@ -0,0 +1,501 @@
+#![deny(missing_docs)]
+//! Binding of command arguments to the arguments of a descriptor.
+
+use std::fmt;
+use std::rc::Rc;
+
+use failure::*;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+use super::descriptor_matcher::*;
+use super::diagnostics::*;
+use super::switch_usage::*;
+
+
+/// Structure binding the arguments of a command to the arguments described by
+/// a descriptor.
+///
+/// The arguments are first matched against the descriptor (arity, types,
+/// static words and optional groups), then each described argument gets the
+/// given value, or its default value when omitted.
+///
+/// Repeated flags, options and choices take their last occurrence, which the
+/// `TypeChecker` reports as warnings (see `repetition_warning`). Repeated
+/// lists and multi-choices are concatenated.
+pub struct ArgumentBinder<'d> {
+    desc: &'d AstDescriptor,
+}
+
+/// Arguments bound by an `ArgumentBinder`, in the order of the descriptor.
+///
+/// The arguments of an instantiated command bound to values are given as
+/// placeholders (`~`), the values being kept aside for each argument in the
+/// order of its placeholders.
+pub struct BoundArguments {
+    values: Vec<(String, BoundArgument)>,
+    given: Vec<(String, Vec<Value>)>,
+}
+
+/// Argument bound to an argument descriptor.
+#[derive(Clone)]
+pub struct BoundArgument {
+    /// Described type of the values, if any.
+    pub ty: Option<Type>,
+    /// Bound values.
+    pub value: BoundValue,
+}
+
+/// Values bound to an argument descriptor.
+#[derive(Clone)]
+pub enum BoundValue {
+    /// Positional, flag, option or choice argument.
+    Single(AstArgument),
+    /// Positional list, list or multi-choice argument.
+    List(Vec<AstArgument>),
+    /// Omitted argument without default value.
+    Omitted,
+}
+
+/// Errors related to bound arguments.
+#[derive(Debug)]
+pub enum BindError {
+    /// The argument isn't described by the descriptor.
+    UnknownArgument {
+        /// Name of the argument.
+        name: String
+    },
+}
+
+
+
+impl BindError {
+    /// Creates a new `UnknownArgument` error.
+    pub fn new_unknown_argument(name: &str) -> Error {
+        Error::from(BindError::UnknownArgument {
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindError::UnknownArgument { name } => write!(f, "Argument '{}' isn't described.", name),
+        }
+    }
+}
+
+impl Fail for BindError {}
+
+
+
+impl<'d> ArgumentBinder<'d> {
+
+    /// Creates a new binder for the given descriptor.
+    pub fn new(desc: &'d AstDescriptor) -> ArgumentBinder<'d> {
+        ArgumentBinder {
+            desc,
+        }
+    }
+
+
+
+    /// Binds the given arguments. The scope is used to get the types of the
+    /// variables.
+    pub fn bind(&self, scp: &Scope, args: &[AstArgument]) -> Result<BoundArguments, Error> {
+        self.bind_values(scp, args, &[])
+    }
+    /// Binds the given arguments, some of them being placeholders for the
+    /// given values, associated with their index in the arguments. Used to
+    /// invoke instantiated commands.
+    pub fn bind_values(&self, scp: &Scope, args: &[AstArgument], values: &[(usize, Value)]) -> Result<BoundArguments, Error> {
+        let m = DescriptorMatcher::new(self.desc).matches(scp, args)?;
+        let mut bound = Vec::with_capacity(m.arguments.len());
+        let mut given = Vec::new();
+
+        for (d, matched) in m.arguments {
+            if let MatchedArgument::Positionals(v) = &matched {
+                let v: Vec<Value> = v.iter()
+                    .filter_map(|a| args.iter().position(|x| std::ptr::eq(x, *a)))
+                    .filter_map(|i| values.iter().find(|(j, _)| *j == i).map(|(_, v)| v.clone()))
+                    .collect();
+
+                if !v.is_empty() {
+                    given.push((d.name.clone(), v));
+                }
+            }
+
+            bound.push((d.name.clone(), bind_argument(d, matched)));
+        }
+
+        Ok(BoundArguments {
+            values: bound,
+            given,
+        })
+    }
+
+}
+
+
+
+impl BoundArguments {
+
+    /// Gets the given bound argument.
+    pub fn get(&self, name: &str) -> Option<&BoundArgument> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, b)| b)
+    }
+    /// Gets the AST of the given single-valued argument.
+    pub fn get_ast(&self, name: &str) -> Option<&AstArgument> {
+        match self.get(name).map(|b| &b.value) {
+            Some(BoundValue::Single(a)) => Some(a),
+            _ => None,
+        }
+    }
+    /// Gets the ASTs of the given multi-valued argument.
+    pub fn get_list(&self, name: &str) -> Option<&Vec<AstArgument>> {
+        match self.get(name).map(|b| &b.value) {
+            Some(BoundValue::List(v)) => Some(v),
+            _ => None,
+        }
+    }
+    /// Gets the word given to the given argument, if the argument is a word.
+    pub fn get_word(&self, name: &str) -> Option<&String> {
+        match self.get_ast(name) {
+            Some(AstArgument::Name(AstName::Name(w))) => Some(w),
+            _ => None,
+        }
+    }
+    /// Checks if the given argument is omitted and has no default value.
+    pub fn is_omitted(&self, name: &str) -> bool {
+        matches!(self.get(name).map(|b| &b.value), Some(BoundValue::Omitted) | None)
+    }
+    /// Iterates over the names of the bound arguments, in the order of the
+    /// descriptor.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.values.iter().map(|(n, _)| n)
+    }
+
+
+    /// Evaluates the given argument, converting it to its described type.
+    /// Arguments of type `ast` are not evaluated, inferred blocks given for
+    /// arguments of type `block` are closures, and multi-valued arguments are
+    /// evaluated into arrays. Omitted arguments are void, and placeholders
+    /// give the values bound by instantiated commands.
+    ///
+    /// The arrays and streams given by variables or blocks to multi-valued
+    /// arguments of a plain type (such as `str` or `path`) give their items
+    /// to the argument.
+    pub fn evaluate(&self, vm: &VM, scp: &mut Scope, name: &str) -> Result<Value, Error> {
+        let b = match self.get(name) {
+            Some(b) => b,
+            None => return Err(BindError::new_unknown_argument(name)),
+        };
+
+        let mut given = self.given.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+            .unwrap_or(&[])
+            .iter();
+
+        let mut one = |scp: &mut Scope, a: &AstArgument| -> Result<Value, Error> {
+            match (&b.ty, a) {
+                (t, AstArgument::Name(AstName::Placeholder)) => match (given.next(), t) {
+                    (Some(v), Some(t)) => v.cast(t),
+                    (Some(v), None) => Ok(v.clone()),
+                    (None, _) => vm.evaluate(scp, a),
+                },
+                (Some(Type::Ast), _) => Ok(quote(a)),
+                (Some(Type::Block), AstArgument::Block(AstBlock::Inferred(c))) => Ok(Value::Closure(Closure::new(c.clone(), scp))),
+                (Some(t), _) => vm.evaluate(scp, a)?.cast(t),
+                (None, _) => vm.evaluate(scp, a),
+            }
+        };
+
+        match &b.value {
+            BoundValue::Single(a) => one(scp, a),
+            BoundValue::List(v) => {
+                let mut values = Vec::with_capacity(v.len());
+                for a in v {
+                    match &b.ty {
+                        Some(t) if is_spliced(t) && is_evaluated(a) => match vm.evaluate(scp, a)? {
+                            v @ Value::Array(..) | v @ Value::Stream(..) => for v in v.into_stream().unwrap().1 {
+                                values.push(v?.cast(t)?);
+                            },
+                            v => values.push(v.cast(t)?),
+                        },
+                        _ => values.push(one(scp, a)?),
+                    }
+                }
+
+                Ok(Value::Array(b.ty.clone().unwrap_or(Type::Generic), values))
+            },
+            BoundValue::Omitted => Ok(Value::Void),
+        }
+    }
+    /// Gets the given argument as pieces of AST, without evaluating it.
+    pub fn quote(&self, name: &str) -> Result<Value, Error> {
+        match self.get(name).map(|b| &b.value) {
+            Some(BoundValue::Single(a)) => Ok(quote(a)),
+            Some(BoundValue::List(v)) => Ok(Value::Array(Type::Ast, v.iter().map(quote).collect())),
+            Some(BoundValue::Omitted) => Ok(Value::Void),
+            None => Err(BindError::new_unknown_argument(name)),
+        }
+    }
+
+}
+
+
+
+/// Binds the arguments given for an argument descriptor.
+fn bind_argument(d: &AstArgumentDescriptor, given: MatchedArgument) -> BoundArgument {
+    let ty = |t: &String| Type::from_name(t);
+    let single = |a: &AstArgument| BoundValue::Single(a.clone());
+    let list = |a: &Option<AstArgument>| BoundValue::List(a.iter().cloned().collect());
+
+    match (&d.kind, given) {
+        (AstArgumentKind::Positional(t, _), MatchedArgument::Positionals(v)) => BoundArgument {
+            ty: ty(t),
+            value: single(v[0]),
+        },
+        (AstArgumentKind::Positional(t, def), _) | (AstArgumentKind::Option(t, def), MatchedArgument::Omitted) => BoundArgument {
+            ty: ty(t),
+            value: match def {
+                Some(a) => single(a),
+                None => type_default(t),
+            },
+        },
+
+        (AstArgumentKind::PositionalList(t, _), MatchedArgument::Positionals(v)) => BoundArgument {
+            ty: ty(t),
+            value: BoundValue::List(v.into_iter().cloned().collect()),
+        },
+        (AstArgumentKind::PositionalList(t, def), _) | (AstArgumentKind::List(t, def), MatchedArgument::Omitted) => BoundArgument {
+            ty: ty(t),
+            value: list(def),
+        },
+
+        (AstArgumentKind::Flag(values), given) => {
+            let enabled = match given {
+                MatchedArgument::Switches(v) => matches!(v.last(), Some(AstSwitch::On(_))),
+                _ => false,
+            };
+
+            BoundArgument {
+                ty: None,
+                value: match values {
+                    Some((d, e)) => single(if enabled { e } else { d }),
+                    None => single(&AstArgument::Name(AstName::Name(enabled.to_string()))),
+                },
+            }
+        },
+
+        (AstArgumentKind::Option(t, _), MatchedArgument::Switches(v)) => BoundArgument {
+            ty: ty(t),
+            value: match v.last() {
+                Some(AstSwitch::Option(_, a)) => single(a),
+                _ => BoundValue::Omitted,
+            },
+        },
+
+        (AstArgumentKind::List(t, _), MatchedArgument::Switches(v)) => BoundArgument {
+            ty: ty(t),
+            value: BoundValue::List(switch_values(&v)),
+        },
+
+        (AstArgumentKind::Choice(_, _), MatchedArgument::Switches(v)) => BoundArgument {
+            ty: None,
+            value: match switch_values(&v).pop() {
+                Some(a) => BoundValue::Single(a),
+                None => BoundValue::Omitted,
+            },
+        },
+        (AstArgumentKind::Choice(_, def), _) => BoundArgument {
+            ty: None,
+            value: match def {
+                Some(a) => single(a),
+                None => BoundValue::Omitted,
+            },
+        },
+
+        (AstArgumentKind::NamedChoice(pairs, def), MatchedArgument::Switches(v)) => {
+            let c = ChoiceOptionSwitch::new_described(pairs, def);
+            BoundArgument {
+                ty: None,
+                value: match switch_values(&v).pop() {
+                    Some(a) => BoundValue::Single(named_value(&c, &d.name, &a)),
+                    None => BoundValue::Omitted,
+                },
+            }
+        },
+        (AstArgumentKind::NamedChoice(pairs, def), _) => BoundArgument {
+            ty: None,
+            value: match ChoiceOptionSwitch::new_described(pairs, def).initial_value() {
+                Some(a) => single(a),
+                None => BoundValue::Omitted,
+            },
+        },
+
+        (AstArgumentKind::MultiChoice(_, _), MatchedArgument::Switches(v)) => BoundArgument {
+            ty: None,
+            value: BoundValue::List(switch_values(&v)),
+        },
+        (AstArgumentKind::MultiChoice(_, def), _) => BoundArgument {
+            ty: None,
+            value: list(def),
+        },
+
+        (AstArgumentKind::NamedMultiChoice(pairs, def), MatchedArgument::Switches(v)) => {
+            let c = ChoiceOptionSwitch::new_described(pairs, def);
+            BoundArgument {
+                ty: None,
+                value: BoundValue::List(switch_values(&v).iter().map(|a| named_value(&c, &d.name, a)).collect()),
+            }
+        },
+        (AstArgumentKind::NamedMultiChoice(pairs, def), _) => BoundArgument {
+            ty: None,
+            value: list(ChoiceOptionSwitch::new_described(pairs, def).initial_value()),
+        },
+
+        (AstArgumentKind::List(t, _), MatchedArgument::Positionals(_)) | (AstArgumentKind::Option(t, _), MatchedArgument::Positionals(_)) => BoundArgument {
+            ty: ty(t),
+            value: BoundValue::Omitted,
+        },
+    }
+}
+
+/// Gets the warning about an argument which should be given only once, given
+/// `count` times, if it is repeated.
+pub fn repetition_warning(desc: &AstDescriptor, d: &AstArgumentDescriptor, count: usize) -> Option<Diagnostic> {
+    if count < 2 {
+        return None;
+    }
+
+    let what = match &d.kind {
+        AstArgumentKind::Flag(_) => "Flag",
+        AstArgumentKind::Option(_, _) => "Option",
+        AstArgumentKind::Choice(_, _) | AstArgumentKind::NamedChoice(_, _) => "Choice",
+        _ => return None,
+    };
+
+    Some(Diagnostic::new_warning(format!(
+        "{} '{}' of command '{}' is given {} times, only the last occurrence is used.",
+        what, d.name, desc.name, count
+    )))
+}
+
+/// Gets the values given by switches, in order.
+fn switch_values(switches: &[&AstSwitch]) -> Vec<AstArgument> {
+    let mut r = Vec::new();
+
+    for s in switches {
+        match s {
+            AstSwitch::Option(_, v) | AstSwitch::Choice(_, v) => r.push(v.as_ref().clone()),
+            AstSwitch::List(_, v) | AstSwitch::MultiChoice(_, v) => r.extend(v.iter().cloned()),
+            AstSwitch::On(_) | AstSwitch::Off(_) => {},
+        }
+    }
+
+    r
+}
+
+/// Gets the value named by the given word in a named choice. Arguments only
+/// known at runtime are kept as-is.
+fn named_value(c: &ChoiceOptionSwitch<Option<AstArgument>>, name: &str, arg: &AstArgument) -> AstArgument {
+    match c.choose(name, arg) {
+        Ok(Some(a)) => a.clone(),
+        _ => arg.clone(),
+    }
+}
+
+/// Gets the default value of a type as an AST literal, if the type has one.
+fn type_default(t: &str) -> BoundValue {
+    let value = Type::from_name(t)
+        .and_then(|t| Value::default_of(&t))
+        .and_then(|v| v.to_ast().ok());
+
+    match value {
+        Some(a) => BoundValue::Single(a),
+        None => BoundValue::Omitted,
+    }
+}
+
+/// Checks if an argument is only known once evaluated.
+fn is_evaluated(a: &AstArgument) -> bool {
+    matches!(a, AstArgument::Name(AstName::Variable(_)) | AstArgument::Block(AstBlock::Evaluated(_)) | AstArgument::Block(AstBlock::Inferred(_)))
+}
+
+/// Wraps an argument into a value.
+fn quote(a: &AstArgument) -> Value {
+    Value::Ast(Rc::new(AstValue::Argument(a.clone())))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(src: &str) -> AstDescriptor {
+        ns_parser::descriptor(src).unwrap()
+    }
+
+    fn bind(desc: &str, src: &str) -> BoundArguments {
+        let args = ns_parser::file(src).unwrap().remove(0).arguments;
+        ArgumentBinder::new(&descriptor(desc)).bind(&Scope::new(), &args).ok().unwrap()
+    }
+
+    #[test]
+    fn omitted_arguments_take_their_defaults() {
+        let b = bind("'f [n = i4 3] [/v] [m = i4];", "f;");
+
+        assert!(b.get_ast("n") == Some(&AstArgument::Integer(3)));
+        assert_eq!(b.get_word("v").map(|w| w.as_str()), Some("false"));
+        assert!(b.get_ast("m") == Some(&AstArgument::Integer(0)));
+    }
+
+    #[test]
+    fn given_arguments_are_bound_in_descriptor_order() {
+        let b = bind("'f <a str> [/v] [n = i4];", "f n = 2 x /v;");
+
+        assert_eq!(b.names().map(|n| n.as_str()).collect::<Vec<_>>(), ["a", "v", "n"]);
+        assert_eq!(b.get_word("a").map(|w| w.as_str()), Some("x"));
+        assert_eq!(b.get_word("v").map(|w| w.as_str()), Some("true"));
+        assert!(b.get_ast("n") == Some(&AstArgument::Integer(2)));
+    }
+
+    #[test]
+    fn repeated_options_take_their_last_occurrence() {
+        let b = bind("'f [n = i4];", "f n = 1 n = 2;");
+
+        assert!(b.get_ast("n") == Some(&AstArgument::Integer(2)));
+    }
+
+    #[test]
+    fn repeated_lists_are_concatenated() {
+        let b = bind("'f [x[] = i4];", "f x[] = 1, 2 x[] = 3;");
+
+        assert!(b.get_list("x") == Some(&vec![AstArgument::Integer(1), AstArgument::Integer(2), AstArgument::Integer(3)]));
+    }
+
+    #[test]
+    fn named_choices_bind_their_values() {
+        let b = bind("'f [c -> low=1, high=2];", "f c -> high;");
+
+        assert!(b.get_ast("c") == Some(&AstArgument::Integer(2)));
+    }
+
+    #[test]
+    fn only_repeated_single_switches_are_warned() {
+        let desc = descriptor("'f [n = i4] [x[] = i4];");
+        let args: Vec<&AstArgumentDescriptor> = desc.arguments.iter().flat_map(|i| match i {
+            AstDescriptorItem::Optional(v) => v.iter().collect(),
+            _ => Vec::new(),
+        }).collect();
+
+        assert!(repetition_warning(&desc, args[0], 1).is_none());
+        assert_eq!(repetition_warning(&desc, args[0], 2).map(|d| d.severity), Some(Severity::Warning));
+        assert!(repetition_warning(&desc, args[1], 2).is_none());
+    }
+}
@@ -1,4 +1,5 @@
 #![deny(missing_docs)]
+//! Collection of command arguments.
 
 use std::collections::HashMap;
 
@@ -34,13 +35,14 @@ impl<'a> ArgumentCollector<'a> {
         for arg in iter {
             match &arg {
                 AstArgument::Switch(s) => {
-                    let mut name: &String;
-
-                    match &s {
-                        AstSwitch::On(n) => name = n,
-                        AstSwitch::Off(n) => name = n,
-                        AstSwitch::Option(n, _) => name = n,
-                    }
+                    let name = match s {
+                        AstSwitch::On(n) => n,
+                        AstSwitch::Off(n) => n,
+                        AstSwitch::Option(n, _) => n,
+                        AstSwitch::List(n, _) => n,
+                        AstSwitch::Choice(n, _) => n,
+                        AstSwitch::MultiChoice(n, _) => n,
+                    };
 
                     self.switches.entry(name.clone())
                         .and_modify(|v| v.push(s))
@@ -52,3 +54,9 @@ impl<'a> ArgumentCollector<'a> {
     }
 
 }
+
+impl<'a> Default for ArgumentCollector<'a> {
+    fn default() -> ArgumentCollector<'a> {
+        ArgumentCollector::new()
+    }
+}
@@ -0,0 +1,319 @@
+#![deny(missing_docs)]
+//! Merging of choice descriptors declared in several chunks.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use failure::*;
+
+use crate::neoshell::parser::*;
+
+
+/// Errors related to the merging of chunked choices.
+#[derive(Debug)]
+pub enum MergeError {
+    /// Two arguments which can't be merged have the same name.
+    DuplicateArgument {
+        /// Name of the argument.
+        name: String,
+    },
+    /// Two arguments of different kinds have the same name.
+    KindMismatch {
+        /// Name of the argument.
+        name: String,
+        /// Kind of the first description.
+        first: &'static str,
+        /// Kind of the conflicting description.
+        second: &'static str,
+    },
+    /// A value is given by several chunks of a choice.
+    DuplicateValue {
+        /// Name of the choice.
+        name: String,
+        /// Duplicated value, or name of the duplicated named value.
+        value: String,
+    },
+    /// A value of a chunk doesn't have the type of the other values.
+    TypeMismatch {
+        /// Name of the choice.
+        name: String,
+        /// Mismatching value.
+        value: String,
+        /// Kind of literal of the other values.
+        expected: &'static str,
+    },
+    /// Several chunks of a choice give different default values.
+    DuplicateDefault {
+        /// Name of the choice.
+        name: String,
+    },
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::DuplicateArgument { name } => write!(f, "Argument '{}' is described more than once.", name),
+            MergeError::KindMismatch { name, first, second } => write!(f, "Argument '{}' is described both as {} and as {}.", name, first, second),
+            MergeError::DuplicateValue { value, name } => write!(f, "Value '{}' is given more than once to the choice '{}'.", value, name),
+            MergeError::TypeMismatch { value, name, expected } => write!(f, "Value '{}' of the choice '{}' must be {} like its other values.", value, name, expected),
+            MergeError::DuplicateDefault { name } => write!(f, "Choice '{}' is given more than one default value.", name),
+        }
+    }
+}
+
+impl Fail for MergeError {}
+
+
+
+/// Merges the choice, named choice and multi-choice arguments repeated in a
+/// descriptor : `<c -> a, b> <c -> d>` is the same as `<c -> a, b, d>`.
+///
+/// The merged argument takes the place of the first chunk, and optional groups
+/// left empty are removed. Chunks of different kinds, chunks giving the same
+/// value twice or values of different types are errors.
+pub fn merge_choices(desc: &AstDescriptor) -> Result<AstDescriptor, Error> {
+    let mut merged: HashMap<String, AstArgumentKind> = HashMap::new();
+    let mut seen: HashMap<String, &AstArgumentKind> = HashMap::new();
+
+    for d in desc.arguments.iter().flat_map(item_arguments) {
+        match (seen.get(&d.name), merged.remove(&d.name)) {
+            (None, _) => {
+                seen.insert(d.name.clone(), &d.kind);
+            },
+            (Some(first), m) => {
+                let first = m.unwrap_or_else(|| (*first).clone());
+                merged.insert(d.name.clone(), merge_kinds(&d.name, first, &d.kind)?);
+            },
+        }
+    }
+
+    if merged.is_empty() {
+        return Ok(desc.clone());
+    }
+
+    let mut placed = Vec::new();
+    let mut place = |d: &AstArgumentDescriptor| -> Option<AstArgumentDescriptor> {
+        match merged.get(&d.name) {
+            None => Some(d.clone()),
+            Some(_) if placed.contains(&d.name) => None,
+            Some(k) => {
+                placed.push(d.name.clone());
+                Some(AstArgumentDescriptor {
+                    name: d.name.clone(),
+                    kind: k.clone(),
+                })
+            },
+        }
+    };
+
+    let mut arguments = Vec::with_capacity(desc.arguments.len());
+
+    for item in desc.arguments.iter() {
+        match item {
+            AstDescriptorItem::Static(w) => arguments.push(AstDescriptorItem::Static(w.clone())),
+            AstDescriptorItem::Mandatory(d) => {
+                if let Some(d) = place(d) {
+                    arguments.push(AstDescriptorItem::Mandatory(d));
+                }
+            },
+            AstDescriptorItem::Optional(v) => {
+                let v: Vec<AstArgumentDescriptor> = v.iter().filter_map(&mut place).collect();
+
+                if !v.is_empty() {
+                    arguments.push(AstDescriptorItem::Optional(v));
+                }
+            },
+        }
+    }
+
+    Ok(AstDescriptor {
+        name: desc.name.clone(),
+        input: desc.input.clone(),
+        output: desc.output.clone(),
+        arguments,
+    })
+}
+
+
+
+/// Merges a chunk into the previously merged chunks of an argument.
+fn merge_kinds(name: &str, first: AstArgumentKind, chunk: &AstArgumentKind) -> Result<AstArgumentKind, Error> {
+    use self::AstArgumentKind::*;
+
+    match (first, chunk) {
+        (Choice(a, da), Choice(b, db)) => Ok(Choice(merge_values(name, a, b)?, merge_default(name, da, db)?)),
+        (MultiChoice(a, da), MultiChoice(b, db)) => Ok(MultiChoice(merge_values(name, a, b)?, merge_default(name, da, db)?)),
+        (NamedChoice(a, da), NamedChoice(b, db)) => Ok(NamedChoice(merge_pairs(name, a, b)?, merge_default(name, da, db)?)),
+        (NamedMultiChoice(a, da), NamedMultiChoice(b, db)) => Ok(NamedMultiChoice(merge_pairs(name, a, b)?, merge_default(name, da, db)?)),
+        (first, chunk) if kind_name(&first) == kind_name(chunk) => Err(Error::from(MergeError::DuplicateArgument {
+            name: name.to_owned(),
+        })),
+        (first, chunk) => Err(Error::from(MergeError::KindMismatch {
+            name: name.to_owned(),
+            first: kind_name(&first),
+            second: kind_name(chunk),
+        })),
+    }
+}
+
+/// Appends the values of a chunk, checking for duplicates and literal types.
+/// The values of the first chunk are checked too.
+fn merge_values(name: &str, first: Vec<AstArgument>, chunk: &[AstArgument]) -> Result<Vec<AstArgument>, Error> {
+    let expected = first.first().map(literal_name);
+    let mut values = Vec::with_capacity(first.len() + chunk.len());
+
+    for v in first.iter().chain(chunk) {
+        if values.contains(v) {
+            return Err(Error::from(MergeError::DuplicateValue {
+                name: name.to_owned(),
+                value: v.to_string(),
+            }));
+        }
+
+        if let Some(expected) = expected {
+            if literal_name(v) != expected {
+                return Err(Error::from(MergeError::TypeMismatch {
+                    name: name.to_owned(),
+                    value: v.to_string(),
+                    expected,
+                }));
+            }
+        }
+
+        values.push(v.clone());
+    }
+
+    Ok(values)
+}
+
+/// Appends the named values of a chunk, checking for duplicated names and
+/// literal types. The values of the first chunk are checked too.
+fn merge_pairs(name: &str, first: Vec<(String, AstArgument)>, chunk: &[(String, AstArgument)]) -> Result<Vec<(String, AstArgument)>, Error> {
+    let expected = first.first().map(|(_, v)| literal_name(v));
+    let mut pairs = Vec::with_capacity(first.len() + chunk.len());
+
+    for (n, v) in first.iter().chain(chunk) {
+        if pairs.iter().any(|(m, _)| m == n) {
+            return Err(Error::from(MergeError::DuplicateValue {
+                name: name.to_owned(),
+                value: n.clone(),
+            }));
+        }
+
+        if let Some(expected) = expected {
+            if literal_name(v) != expected {
+                return Err(Error::from(MergeError::TypeMismatch {
+                    name: name.to_owned(),
+                    value: format!("{}={}", n, v),
+                    expected,
+                }));
+            }
+        }
+
+        pairs.push((n.clone(), v.clone()));
+    }
+
+    Ok(pairs)
+}
+
+/// Keeps the default value given by one of the chunks.
+fn merge_default(name: &str, first: Option<AstArgument>, chunk: &Option<AstArgument>) -> Result<Option<AstArgument>, Error> {
+    match (first, chunk) {
+        (Some(a), Some(b)) if a != *b => Err(Error::from(MergeError::DuplicateDefault {
+            name: name.to_owned(),
+        })),
+        (Some(a), _) => Ok(Some(a)),
+        (None, b) => Ok(b.clone()),
+    }
+}
+
+/// Gets the argument descriptors of a descriptor item.
+fn item_arguments(item: &AstDescriptorItem) -> Vec<&AstArgumentDescriptor> {
+    match item {
+        AstDescriptorItem::Static(_) => Vec::new(),
+        AstDescriptorItem::Mandatory(d) => vec![d],
+        AstDescriptorItem::Optional(v) => v.iter().collect(),
+    }
+}
+
+/// Gets the name of a kind of argument, for error messages.
+fn kind_name(kind: &AstArgumentKind) -> &'static str {
+    match kind {
+        AstArgumentKind::Positional(_, _) => "a positional argument",
+        AstArgumentKind::PositionalList(_, _) => "a positional list",
+        AstArgumentKind::Flag(_) => "a flag",
+        AstArgumentKind::Option(_, _) => "an option",
+        AstArgumentKind::List(_, _) => "a list",
+        AstArgumentKind::Choice(_, _) => "a choice",
+        AstArgumentKind::NamedChoice(_, _) => "a named choice",
+        AstArgumentKind::MultiChoice(_, _) => "a multi-choice",
+        AstArgumentKind::NamedMultiChoice(_, _) => "a named multi-choice",
+    }
+}
+
+/// Gets the name of the kind of literal of a choice value.
+fn literal_name(v: &AstArgument) -> &'static str {
+    match v {
+        AstArgument::Integer(_) => "an integer",
+        AstArgument::Float(_) => "a float",
+        AstArgument::String(_) => "a string",
+        AstArgument::Name(_) => "a word",
+        _ => "a value",
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(src: &str) -> AstDescriptor {
+        ns_parser::descriptor(src).unwrap()
+    }
+
+    fn merge(src: &str) -> Result<String, Error> {
+        merge_choices(&descriptor(src)).map(|d| d.to_string())
+    }
+
+    fn merge_error(src: &str) -> MergeError {
+        match merge(src).err().unwrap().downcast::<MergeError>() {
+            Ok(e) => e,
+            Err(e) => panic!("Unexpected error : {}", e),
+        }
+    }
+
+    #[test]
+    fn chunks_merge_into_the_first_one() {
+        assert_eq!(merge("'f <c -> a, b> <x str> [c -> d];").ok().unwrap(), descriptor("'f <c -> a, b, d> <x str>;").to_string());
+        assert_eq!(merge("'f [m => a] [m => b];").ok().unwrap(), descriptor("'f [m => a, b];").to_string());
+        assert_eq!(merge("'f [c -> low=1] [c -> high=2];").ok().unwrap(), descriptor("'f [c -> low=1, high=2];").to_string());
+    }
+
+    #[test]
+    fn descriptors_without_chunks_are_kept() {
+        assert_eq!(merge("'f <c -> a, b> [/v];").ok().unwrap(), descriptor("'f <c -> a, b> [/v];").to_string());
+    }
+
+    #[test]
+    fn duplicate_values_are_errors() {
+        assert!(matches!(merge_error("'f [c -> a, b] [c -> b];"), MergeError::DuplicateValue { value, .. } if value == "b"));
+        assert!(matches!(merge_error("'f [c -> low=1] [c -> low=2];"), MergeError::DuplicateValue { value, .. } if value == "low"));
+    }
+
+    #[test]
+    fn first_chunks_are_checked() {
+        assert!(matches!(merge_error("'f [c -> a, a] [c -> b];"), MergeError::DuplicateValue { value, .. } if value == "a"));
+        assert!(matches!(merge_error("'f [c -> a, 1] [c -> b];"), MergeError::TypeMismatch { value, .. } if value == "1"));
+        assert!(matches!(merge_error("'f [c -> x=1, x=2] [c -> y=3];"), MergeError::DuplicateValue { value, .. } if value == "x"));
+        assert!(matches!(merge_error("'f [c -> x=1, y=a] [c -> z=3];"), MergeError::TypeMismatch { .. }));
+    }
+
+    #[test]
+    fn mismatching_chunks_are_errors() {
+        assert!(matches!(merge_error("'f [c -> a] [c => b];"), MergeError::KindMismatch { .. }));
+        assert!(matches!(merge_error("'f [c -> a] [c -> 1];"), MergeError::TypeMismatch { .. }));
+        assert!(matches!(merge_error("'f [n = i4] [n = i4];"), MergeError::DuplicateArgument { .. }));
+        assert!(matches!(merge_error("'f [c -> a, b a] [c -> d b];"), MergeError::DuplicateDefault { .. }));
+    }
+}
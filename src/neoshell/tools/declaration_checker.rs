@@ -0,0 +1,228 @@
+#![deny(missing_docs)]
+//! Compile time checking of variable declarations.
+
+use std::collections::HashMap;
+
+use failure::Error;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+
+/// Commands declaring the variable named by their first argument.
+pub const DECLARATORS: [&str; 3] = ["let", "const", "export"];
+
+
+/// Checks at compile time that every used variable is declared beforehand.
+///
+/// Variables are declared by the declaring commands (see `DECLARATORS`) whose
+/// first argument is the name of the variable. A declaration is visible until
+/// the end of the block it is made in. The variables assigned by `set` have to
+/// be declared and not constant, and constants can't be redeclared in the
+/// block declaring them.
+pub struct DeclarationChecker {
+    scopes: Vec<HashMap<String, bool>>,
+    error_span: Option<AstSpan>,
+}
+
+
+impl DeclarationChecker {
+
+    /// Creates a new checker.
+    pub fn new() -> DeclarationChecker {
+        DeclarationChecker {
+            scopes: Vec::new(),
+            error_span: None,
+        }
+    }
+
+
+    /// Checks the given commands. Variables already declared in the given
+    /// scope are considered declared.
+    pub fn check(&mut self, scp: &Scope, cmds: &[AstCommand]) -> Result<(), Error> {
+        self.scopes.clear();
+        self.error_span = None;
+        self.check_block(scp, cmds)
+    }
+    /// Gets the location of the innermost command using an undeclared
+    /// variable, once `check` failed.
+    pub fn get_error_span(&self) -> Option<AstSpan> {
+        self.error_span
+    }
+
+
+    fn check_block(&mut self, scp: &Scope, cmds: &[AstCommand]) -> Result<(), Error> {
+        self.scopes.push(HashMap::new());
+
+        let r = cmds.iter().try_for_each(|c| self.check_command(scp, c));
+
+        self.scopes.pop();
+        r
+    }
+
+    /// Checks the body of a closure, which receives its arguments in `$args`.
+    fn check_closure(&mut self, scp: &Scope, cmds: &[AstCommand]) -> Result<(), Error> {
+        let mut args = HashMap::new();
+        args.insert("args".to_owned(), false);
+
+        self.scopes.push(args);
+        let r = self.check_block(scp, cmds);
+
+        self.scopes.pop();
+        r
+    }
+
+    fn check_command(&mut self, scp: &Scope, cmd: &AstCommand) -> Result<(), Error> {
+        let r = self.check_command_parts(scp, cmd);
+
+        if r.is_err() && self.error_span.is_none() {
+            self.error_span = cmd.span;
+        }
+        r
+    }
+
+    fn check_command_parts(&mut self, scp: &Scope, cmd: &AstCommand) -> Result<(), Error> {
+        if let AstName::Block(b) = &cmd.name {
+            self.check_block_argument(scp, b)?;
+        }
+        self.check_name(scp, &cmd.name)?;
+
+        for a in cmd.arguments.iter() {
+            self.check_argument(scp, a)?;
+        }
+
+        if let Some(p) = &cmd.pipe {
+            self.check_command(scp, p)?;
+        }
+
+        let (n, v) = match (&cmd.name, cmd.arguments.first()) {
+            (AstName::Name(n), Some(AstArgument::Name(AstName::Name(v)))) => (n, v),
+            _ => return Ok(()),
+        };
+
+        if n == "set" {
+            self.check_assignment(scp, v)?;
+        }
+        // `export` without value only exports the visible variable.
+        if is_declarator(n) && cmd.arguments.len() > 1 {
+            self.check_redeclaration(scp, v)?;
+            self.scopes.last_mut().unwrap().insert(v.clone(), n == "const");
+        }
+
+        Ok(())
+    }
+
+    /// Checks that an assigned variable is declared and isn't a constant.
+    fn check_assignment(&self, scp: &Scope, v: &String) -> Result<(), Error> {
+        let constant = match self.scopes.iter().rev().find_map(|s| s.get(v)) {
+            Some(c) => *c,
+            None => match scp.is_constant(v) {
+                Some(c) => c,
+                None => return Err(ScopeError::new_undeclared_variable(v)),
+            },
+        };
+
+        match constant {
+            true => Err(ScopeError::new_constant_assignment(v)),
+            false => Ok(()),
+        }
+    }
+
+    /// Checks that a declared variable isn't a constant of the same block.
+    /// The outermost block is the given scope.
+    fn check_redeclaration(&self, scp: &Scope, v: &String) -> Result<(), Error> {
+        let constant = match self.scopes.last().and_then(|s| s.get(v)) {
+            Some(c) => *c,
+            None => self.scopes.len() == 1 && scp.is_declared_locally(v) && scp.is_constant(v) == Some(true),
+        };
+
+        match constant {
+            true => Err(ScopeError::new_constant_redeclaration(v)),
+            false => Ok(()),
+        }
+    }
+
+    fn check_argument(&mut self, scp: &Scope, arg: &AstArgument) -> Result<(), Error> {
+        match arg {
+            AstArgument::Name(n) => self.check_name(scp, n),
+            AstArgument::Switch(AstSwitch::Option(_, v)) => self.check_argument(scp, v),
+            AstArgument::Switch(AstSwitch::Choice(_, v)) => self.check_argument(scp, v),
+            AstArgument::Switch(AstSwitch::List(_, v)) => v.iter().try_for_each(|a| self.check_argument(scp, a)),
+            AstArgument::Switch(AstSwitch::MultiChoice(_, v)) => v.iter().try_for_each(|a| self.check_argument(scp, a)),
+            AstArgument::Block(b) => self.check_block_argument(scp, b),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_block_argument(&mut self, scp: &Scope, b: &AstBlock) -> Result<(), Error> {
+        match b {
+            AstBlock::Evaluated(c) | AstBlock::Inferred(c) => self.check_block(scp, c),
+            AstBlock::Argument(c) => self.check_closure(scp, c),
+        }
+    }
+
+    fn check_name(&self, scp: &Scope, name: &AstName) -> Result<(), Error> {
+        if let AstName::Variable(n) = name {
+            let declared = self.scopes.iter().any(|s| s.contains_key(n)) || scp.is_declared(n);
+
+            if !declared {
+                return Err(ScopeError::new_undeclared_variable(n));
+            }
+        }
+
+        Ok(())
+    }
+
+}
+
+impl Default for DeclarationChecker {
+    fn default() -> DeclarationChecker {
+        DeclarationChecker::new()
+    }
+}
+
+
+
+/// Checks if the given command declares the variable named by its first
+/// argument.
+pub fn is_declarator(name: &str) -> bool {
+    DECLARATORS.contains(&name)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(src: &str) -> Result<(), Error> {
+        let cmds = ns_parser::file(src).unwrap();
+        DeclarationChecker::new().check(&Scope::with_environment(HashMap::new()), &cmds)
+    }
+
+    #[test]
+    fn variables_are_declared_until_the_end_of_their_block() {
+        assert!(check("let x 1; puts $x;").is_ok());
+        assert!(check("puts $x;").is_err());
+        assert!(check("let y !{ let x 1; value $x; }; puts $x;").is_err());
+        assert!(check("each &{ puts $args; };").is_ok());
+    }
+
+    #[test]
+    fn assigned_variables_are_declared_and_mutable() {
+        assert!(check("let x 1; set x 2;").is_ok());
+        assert!(check("let x 1; let y !{ set x 2; };").is_ok());
+        assert!(check("set x 2;").is_err());
+        assert!(check("const x 1; set x 2;").is_err());
+        assert!(check("const x 1; let y !{ let x 2; set x 3; };").is_ok());
+    }
+
+    #[test]
+    fn constants_are_not_redeclared_in_their_block() {
+        assert!(check("const x 1; let x 2;").is_err());
+        assert!(check("const x 1; export x 2;").is_err());
+        assert!(check("const x 1; export x;").is_ok());
+        assert!(check("let x 1; let x 2;").is_ok());
+        assert!(check("const x 1; let y !{ const x 2; };").is_ok());
+    }
+}
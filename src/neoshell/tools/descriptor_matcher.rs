@@ -0,0 +1,649 @@
+#![deny(missing_docs)]
+//! Static matching of command arguments against descriptors.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use failure::*;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+use super::argument_collector::*;
+use super::switch_usage::*;
+
+
+/// Structure matching the arguments of a command against a descriptor.
+///
+/// Matching is done on the AST : literals are checked against the described
+/// types, variables are checked through their declared types, and blocks
+/// whose result is unknown are accepted by any type.
+pub struct DescriptorMatcher<'d> {
+    desc: &'d AstDescriptor,
+}
+
+/// Precision of a match. A match is more precise than another when it omits
+/// less non-positional arguments, then when more arguments have exactly the
+/// described type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Precision {
+    /// Number of omitted non-positional arguments.
+    pub omitted: usize,
+    /// Number of arguments whose type is exactly the described one.
+    pub exact: usize,
+}
+
+/// Compatibility of an argument with a type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compatibility {
+    /// The argument has exactly the given type.
+    Exact,
+    /// The argument can be converted to the given type, or its type is only
+    /// known at runtime.
+    Compatible,
+    /// The argument can't be used as the given type.
+    Incompatible,
+}
+
+/// Arguments given for an argument descriptor.
+pub enum MatchedArgument<'a> {
+    /// Positional arguments, given for a positional or a positional list
+    /// descriptor.
+    Positionals(Vec<&'a AstArgument>),
+    /// Switches, given for a non-positional descriptor.
+    Switches(Vec<&'a AstSwitch>),
+    /// The argument is omitted.
+    Omitted,
+}
+
+/// Successful match of arguments against a descriptor.
+pub struct DescriptorMatch<'d, 'a> {
+    /// Precision of the match.
+    pub precision: Precision,
+    /// Arguments given for each argument descriptor, in the descriptor's
+    /// order.
+    pub arguments: Vec<(&'d AstArgumentDescriptor, MatchedArgument<'a>)>,
+}
+
+/// Errors related to descriptor matching.
+#[derive(Debug)]
+pub enum MatchError {
+    /// The number of positional arguments doesn't match.
+    Arity {
+        /// Expected number of arguments.
+        expected: String,
+        /// Number of given arguments.
+        found: usize,
+    },
+    /// A static word is missing.
+    StaticWord {
+        /// Expected word.
+        word: String
+    },
+    /// An argument doesn't have the described type.
+    Type {
+        /// Name of the argument.
+        name: String,
+        /// Described type.
+        expected: String,
+        /// Given argument.
+        found: String,
+    },
+    /// The descriptor uses an unknown type.
+    UnknownType {
+        /// Name of the type.
+        ty: String
+    },
+    /// A switch isn't described by the descriptor.
+    UnknownSwitch {
+        /// Name of the switch.
+        name: String
+    },
+    /// A switch doesn't have the described syntax.
+    SwitchSyntax {
+        /// Name of the switch.
+        name: String,
+        /// Expected syntax.
+        syntax: String,
+    },
+    /// A mandatory non-positional argument is missing.
+    MissingArgument {
+        /// Name of the argument.
+        name: String
+    },
+    /// Only a part of an optional group is given.
+    IncompleteGroup {
+        /// Names of the arguments of the group.
+        group: String
+    },
+}
+
+
+
+
+
+impl Precision {
+    /// Precision of implementations without descriptor, lower than any other
+    /// precision.
+    pub fn lowest() -> Precision {
+        Precision {
+            omitted: usize::MAX,
+            exact: 0,
+        }
+    }
+}
+
+impl Ord for Precision {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.omitted.cmp(&self.omitted)
+            .then(self.exact.cmp(&other.exact))
+    }
+}
+
+impl PartialOrd for Precision {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+
+impl Compatibility {
+
+    /// Gets the compatibility of an argument with a type. The scope is used to
+    /// get the types of the variables.
+    pub fn of(scp: &Scope, arg: &AstArgument, ty: &Type) -> Compatibility {
+        use Compatibility::*;
+
+        if *ty == Type::Generic {
+            return Compatible;
+        }
+        if *ty == Type::Ast {
+            return Exact;
+        }
+
+        let from_type = |t: &Type| {
+            if t == ty {
+                Exact
+            } else if t.is_convertible_to(ty) {
+                Compatible
+            } else {
+                Incompatible
+            }
+        };
+
+        match arg {
+            AstArgument::Integer(i) => {
+                if *ty == Type::I4 {
+                    Exact
+                } else if ty.is_float() || Value::I4(*i).cast(ty).is_ok() {
+                    Compatible
+                } else {
+                    Incompatible
+                }
+            },
+            AstArgument::Float(_) => from_type(&Type::F4),
+            AstArgument::String(_) => from_type(&Type::Str),
+            AstArgument::Name(AstName::Name(w)) => match w.as_str() {
+                "true" | "false" => from_type(&Type::Bool),
+                _ if *ty == Type::Str || *ty == Type::Path => Compatible,
+                _ => Incompatible,
+            },
+            AstArgument::Name(AstName::Variable(n)) => match scp.get_variable_type(n) {
+                Some(t) => from_type(&t),
+                None => Compatible,
+            },
+            AstArgument::Name(AstName::Placeholder) => Compatible,
+            AstArgument::Block(AstBlock::Argument(_)) => match ty {
+                Type::Block => Exact,
+                Type::Command => Compatible,
+                _ => Incompatible,
+            },
+            AstArgument::Block(AstBlock::Inferred(_)) => match ty {
+                Type::Block => Exact,
+                _ => Compatible,
+            },
+            AstArgument::Block(AstBlock::Evaluated(_)) | AstArgument::Name(AstName::Block(_)) => Compatible,
+            AstArgument::Descriptor(_) => from_type(&Type::Descriptor),
+            AstArgument::Switch(_) | AstArgument::None => Incompatible,
+        }
+    }
+
+}
+
+
+
+impl MatchError {
+    /// Creates a new `Type` error.
+    pub fn new_type(name: &str, expected: &str, found: &AstArgument) -> Error {
+        Error::from(MatchError::Type {
+            name: name.to_owned(),
+            expected: expected.to_owned(),
+            found: found.to_string(),
+        })
+    }
+    /// Creates a new `SwitchSyntax` error.
+    pub fn new_switch_syntax(name: &str, syntax: &'static str) -> Error {
+        Error::from(MatchError::SwitchSyntax {
+            name: name.to_owned(),
+            syntax: syntax.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchError::Arity { expected, found } => write!(f, "Expected {} positional argument(s), found {}.", expected, found),
+            MatchError::StaticWord { word } => write!(f, "Expected the word '{}'.", word),
+            MatchError::Type { name, expected, found } => write!(f, "Argument '{}' expects a value of type '{}', found '{}'.", name, expected, found),
+            MatchError::UnknownType { ty } => write!(f, "Unknown type '{}'.", ty),
+            MatchError::UnknownSwitch { name } => write!(f, "Unknown argument '{}'.", name),
+            MatchError::SwitchSyntax { name, syntax } => write!(f, "Argument '{}' must be given as {}.", name, syntax),
+            MatchError::MissingArgument { name } => write!(f, "Missing argument '{}'.", name),
+            MatchError::IncompleteGroup { group } => write!(f, "Arguments [{}] must be given all together.", group),
+        }
+    }
+}
+
+impl Fail for MatchError {}
+
+
+
+impl<'d> DescriptorMatcher<'d> {
+
+    /// Creates a new matcher for the given descriptor.
+    pub fn new(desc: &'d AstDescriptor) -> DescriptorMatcher<'d> {
+        DescriptorMatcher {
+            desc,
+        }
+    }
+
+
+
+    /// Matches the given arguments against the descriptor. The scope is used
+    /// to get the types of the variables.
+    pub fn matches<'a>(&self, scp: &Scope, args: &'a [AstArgument]) -> Result<DescriptorMatch<'d, 'a>, Error> {
+        let mut collector = ArgumentCollector::new();
+        collector.collect(args.iter());
+
+        for n in collector.switches.keys() {
+            if !self.iter_arguments().any(|d| !is_positional(d) && d.name == *n) {
+                return Err(Error::from(MatchError::UnknownSwitch { name: n.clone() }));
+            }
+        }
+
+        let groups = self.optional_positional_groups();
+        let mut error = None;
+
+        for mask in group_masks(groups.len()) {
+            let included = |i: usize| match groups.iter().position(|g| *g == i) {
+                Some(p) => mask & (1 << p) != 0,
+                None => false,
+            };
+
+            match self.match_with(scp, &collector, &included) {
+                Ok(m) => return Ok(m),
+                Err(e) => if error.is_none() {
+                    error = Some(e);
+                },
+            }
+        }
+
+        Err(error.unwrap())
+    }
+
+
+    /// Iterates over all the argument descriptors.
+    fn iter_arguments(&self) -> impl Iterator<Item = &'d AstArgumentDescriptor> {
+        self.desc.arguments.iter().flat_map(|i| match i {
+            AstDescriptorItem::Static(_) => Vec::new(),
+            AstDescriptorItem::Mandatory(d) => vec![d],
+            AstDescriptorItem::Optional(v) => v.iter().collect(),
+        })
+    }
+
+    /// Gets the indices of the optional groups containing positional arguments.
+    fn optional_positional_groups(&self) -> Vec<usize> {
+        self.desc.arguments.iter().enumerate()
+            .filter(|(_, i)| match i {
+                AstDescriptorItem::Optional(v) => v.iter().any(is_positional),
+                _ => false,
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Matches the arguments, including only the optional groups of positional
+    /// arguments for which `included` returns true.
+    fn match_with<'a>(&self, scp: &Scope, collector: &ArgumentCollector<'a>, included: &dyn Fn(usize) -> bool) -> Result<DescriptorMatch<'d, 'a>, Error> {
+        let mut exact = 0;
+        let mut omitted = 0;
+        let mut arguments = Vec::new();
+
+        // positional slots, in order.
+        let mut slots: Vec<(Option<&'d String>, Option<&'d AstArgumentDescriptor>)> = Vec::new();
+
+        for (i, item) in self.desc.arguments.iter().enumerate() {
+            match item {
+                AstDescriptorItem::Static(w) => slots.push((Some(w), None)),
+                AstDescriptorItem::Mandatory(d) if is_positional(d) => slots.push((None, Some(d))),
+                AstDescriptorItem::Optional(v) if included(i) => {
+                    for d in v.iter().filter(|d| is_positional(d)) {
+                        slots.push((None, Some(d)));
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let positionals = &collector.positionals;
+        let lists = slots.iter().filter(|(_, d)| d.is_some_and(is_list)).count();
+        let fixed = slots.len() - lists;
+
+        // The arguments can't be split between several positional lists.
+        let arity_ok = match lists {
+            0 => positionals.len() == fixed,
+            1 => positionals.len() >= fixed,
+            _ => false,
+        };
+        if !arity_ok {
+            return Err(Error::from(MatchError::Arity {
+                expected: match lists {
+                    0 => fixed.to_string(),
+                    1 => format!("at least {}", fixed),
+                    _ => format!("{} and a single positional list of", fixed),
+                },
+                found: positionals.len(),
+            }));
+        }
+
+        let list_len = positionals.len() - fixed;
+        let mut p = 0;
+
+        for (w, d) in slots.iter() {
+            if let Some(w) = w {
+                match positionals[p] {
+                    AstArgument::Name(AstName::Name(n)) if n == *w => {},
+                    _ => return Err(Error::from(MatchError::StaticWord { word: (*w).clone() })),
+                }
+                exact += 1;
+                p += 1;
+                continue;
+            }
+
+            let d = d.unwrap();
+            let count = if is_list(d) { list_len } else { 1 };
+            let given = positionals[p..p+count].to_vec();
+
+            for a in given.iter() {
+                exact += match is_list(d) {
+                    true => check_list_type(scp, d, positional_type(d), a)?,
+                    false => check_type(scp, d, positional_type(d), a)?,
+                };
+            }
+
+            arguments.push((d, MatchedArgument::Positionals(given)));
+            p += count;
+        }
+
+        // non-positional arguments.
+        for (i, item) in self.desc.arguments.iter().enumerate() {
+            let (descs, mandatory): (Vec<&'d AstArgumentDescriptor>, bool) = match item {
+                AstDescriptorItem::Static(_) => continue,
+                AstDescriptorItem::Mandatory(d) => (vec![d], true),
+                AstDescriptorItem::Optional(v) => (v.iter().collect(), false),
+            };
+            let mut given = 0;
+            let mut expected = 0;
+
+            for d in descs.iter() {
+                if is_positional(d) {
+                    expected += 1;
+                    if included(i) {
+                        given += 1;
+                    }
+                    continue;
+                }
+
+                expected += 1;
+
+                match collector.switches.get(&d.name) {
+                    Some(s) => {
+                        for sw in s.iter() {
+                            exact += check_switch(scp, d, sw)?;
+                        }
+                        given += 1;
+                        arguments.push((d, MatchedArgument::Switches(s.clone())));
+                    },
+                    None if mandatory => {
+                        return Err(Error::from(MatchError::MissingArgument { name: d.name.clone() }));
+                    },
+                    None => {
+                        omitted += 1;
+                        arguments.push((d, MatchedArgument::Omitted));
+                    },
+                }
+            }
+
+            if given != 0 && given != expected {
+                let names: Vec<&str> = descs.iter().map(|d| d.name.as_str()).collect();
+                return Err(Error::from(MatchError::IncompleteGroup { group: names.join(", ") }));
+            }
+        }
+
+        // omitted optional positional arguments.
+        for (i, item) in self.desc.arguments.iter().enumerate() {
+            if let AstDescriptorItem::Optional(v) = item {
+                if !included(i) {
+                    for d in v.iter().filter(|d| is_positional(d)) {
+                        arguments.push((d, MatchedArgument::Omitted));
+                    }
+                }
+            }
+        }
+
+        Ok(DescriptorMatch {
+            precision: Precision {
+                omitted,
+                exact,
+            },
+            arguments,
+        })
+    }
+
+}
+
+
+
+/// Checks if the argument descriptor describes a positional argument.
+pub fn is_positional(d: &AstArgumentDescriptor) -> bool {
+    matches!(d.kind, AstArgumentKind::Positional(_, _) | AstArgumentKind::PositionalList(_, _))
+}
+
+/// Checks if the argument descriptor describes a positional list argument.
+fn is_list(d: &AstArgumentDescriptor) -> bool {
+    matches!(d.kind, AstArgumentKind::PositionalList(_, _))
+}
+
+/// Gets the type of a positional argument descriptor.
+fn positional_type(d: &AstArgumentDescriptor) -> &String {
+    match &d.kind {
+        AstArgumentKind::Positional(t, _) | AstArgumentKind::PositionalList(t, _) => t,
+        _ => unreachable!(),
+    }
+}
+
+/// Iterates over the masks of included groups, from the ones including the
+/// most groups to the ones including the least.
+fn group_masks(count: usize) -> Vec<u32> {
+    let count = count.min(16);
+    let mut masks: Vec<u32> = (0..(1u32 << count)).collect();
+
+    masks.sort_by(|a, b| b.count_ones().cmp(&a.count_ones()).then(b.cmp(a)));
+    masks
+}
+
+/// Checks if the arrays and streams given for a multi-valued argument of the
+/// given type give their items.
+pub fn is_spliced(t: &Type) -> bool {
+    !matches!(t, Type::Generic | Type::Ast | Type::Block | Type::Array(_) | Type::Stream(_))
+}
+
+/// Checks the type of an item given for a multi-valued argument, which can be
+/// a variable holding an array or a stream of values of the described type.
+fn check_list_type(scp: &Scope, d: &AstArgumentDescriptor, ty: &str, arg: &AstArgument) -> Result<usize, Error> {
+    if let (AstArgument::Name(AstName::Variable(n)), Some(t)) = (arg, Type::from_name(ty)) {
+        if let Some(Type::Array(i)) | Some(Type::Stream(i)) = scp.get_variable_type(n) {
+            if is_spliced(&t) && i.is_convertible_to(&t) {
+                return Ok(0);
+            }
+        }
+    }
+
+    check_type(scp, d, ty, arg)
+}
+
+/// Checks the type of an argument, returning 1 if the type is exact.
+fn check_type(scp: &Scope, d: &AstArgumentDescriptor, ty: &str, arg: &AstArgument) -> Result<usize, Error> {
+    let t = match Type::from_name(ty) {
+        Some(t) => t,
+        None => return Err(Error::from(MatchError::UnknownType { ty: ty.to_owned() })),
+    };
+
+    match Compatibility::of(scp, arg, &t) {
+        Compatibility::Exact => Ok(1),
+        Compatibility::Compatible => Ok(0),
+        Compatibility::Incompatible => Err(MatchError::new_type(&d.name, ty, arg)),
+    }
+}
+
+/// Checks a switch against a non-positional argument descriptor, returning the
+/// number of values with an exact type.
+fn check_switch(scp: &Scope, d: &AstArgumentDescriptor, sw: &AstSwitch) -> Result<usize, Error> {
+    match (&d.kind, sw) {
+        (AstArgumentKind::Flag(_), AstSwitch::On(_)) | (AstArgumentKind::Flag(_), AstSwitch::Off(_)) => Ok(1),
+        (AstArgumentKind::Flag(_), _) => Err(MatchError::new_switch_syntax(&d.name, "a flag (/name)")),
+
+        (AstArgumentKind::Option(t, _), AstSwitch::Option(_, v)) => check_type(scp, d, t, v),
+        (AstArgumentKind::Option(_, _), _) => Err(MatchError::new_switch_syntax(&d.name, "an option (name = value)")),
+
+        (AstArgumentKind::List(t, _), AstSwitch::List(_, v)) => {
+            v.iter().map(|a| check_list_type(scp, d, t, a)).sum()
+        },
+        (AstArgumentKind::List(_, _), _) => Err(MatchError::new_switch_syntax(&d.name, "a list (name[] = v0,v1,...)")),
+
+        (AstArgumentKind::Choice(values, _), AstSwitch::Choice(_, v)) => check_choice(d, values, v),
+        (AstArgumentKind::NamedChoice(values, _), AstSwitch::Choice(_, v)) => check_named_choice(d, values, v),
+        (AstArgumentKind::Choice(_, _), _) | (AstArgumentKind::NamedChoice(_, _), _) => {
+            Err(MatchError::new_switch_syntax(&d.name, "a choice (name -> value)"))
+        },
+
+        (AstArgumentKind::MultiChoice(values, _), AstSwitch::MultiChoice(_, v)) => {
+            v.iter().map(|a| check_choice(d, values, a)).sum()
+        },
+        (AstArgumentKind::NamedMultiChoice(values, _), AstSwitch::MultiChoice(_, v)) => {
+            v.iter().map(|a| check_named_choice(d, values, a)).sum()
+        },
+        (AstArgumentKind::MultiChoice(_, _), _) | (AstArgumentKind::NamedMultiChoice(_, _), _) => {
+            Err(MatchError::new_switch_syntax(&d.name, "a multi-choice (name => v0,v1,...)"))
+        },
+
+        (AstArgumentKind::Positional(_, _), _) | (AstArgumentKind::PositionalList(_, _), _) => unreachable!(),
+    }
+}
+
+/// Checks that a choice is one of the described values. Values only known at
+/// runtime are accepted.
+fn check_choice(d: &AstArgumentDescriptor, values: &[AstArgument], v: &AstArgument) -> Result<usize, Error> {
+    match v {
+        AstArgument::Name(AstName::Variable(_)) | AstArgument::Block(_) => return Ok(0),
+        _ => {},
+    }
+
+    ChoiceOptionSwitch::new((), values.iter().map(|a| (a.clone(), ())).collect())
+        .choose(&d.name, v)
+        .map(|_| 1)
+}
+
+/// Checks that a named choice is one of the described names.
+fn check_named_choice(d: &AstArgumentDescriptor, values: &[(String, AstArgument)], v: &AstArgument) -> Result<usize, Error> {
+    ChoiceOptionSwitch::new_described(values, &None)
+        .choose(&d.name, v)
+        .map(|_| 1)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(src: &str) -> AstDescriptor {
+        ns_parser::descriptor(src).unwrap()
+    }
+
+    fn arguments(src: &str) -> Vec<AstArgument> {
+        ns_parser::file(src).unwrap().remove(0).arguments
+    }
+
+    #[test]
+    fn several_positional_lists_are_an_arity_error() {
+        let desc = descriptor("'f <a... str> <b... str>;");
+        let args = arguments("f x y z;");
+
+        let e = DescriptorMatcher::new(&desc).matches(&Scope::new(), &args).err().unwrap();
+
+        assert!(matches!(e.downcast_ref::<MatchError>(), Some(MatchError::Arity { found: 3, .. })));
+    }
+
+    #[test]
+    fn exact_types_are_more_precise() {
+        let exact = descriptor("'f <a i4>;");
+        let converted = descriptor("'f <a i8>;");
+        let args = arguments("f 1;");
+
+        let exact = DescriptorMatcher::new(&exact).matches(&Scope::new(), &args).ok().unwrap().precision;
+        let converted = DescriptorMatcher::new(&converted).matches(&Scope::new(), &args).ok().unwrap().precision;
+
+        assert_eq!(exact, Precision { omitted: 0, exact: 1 });
+        assert_eq!(converted, Precision { omitted: 0, exact: 0 });
+        assert!(exact > converted);
+    }
+
+    #[test]
+    fn omitted_switches_are_less_precise() {
+        let omitting = descriptor("'f <a i8> [/b];");
+        let exact = descriptor("'f <a i4> [/b];");
+        let complete = descriptor("'f <a i8>;");
+        let args = arguments("f 1;");
+
+        let omitting = DescriptorMatcher::new(&omitting).matches(&Scope::new(), &args).ok().unwrap().precision;
+        let exact = DescriptorMatcher::new(&exact).matches(&Scope::new(), &args).ok().unwrap().precision;
+        let complete = DescriptorMatcher::new(&complete).matches(&Scope::new(), &args).ok().unwrap().precision;
+
+        assert_eq!(omitting.omitted, 1);
+        assert!(complete > omitting);
+        assert!(complete > exact);
+    }
+
+    #[test]
+    fn incompatible_types_are_type_errors() {
+        let desc = descriptor("'f <a i4>;");
+        let args = arguments("f word;");
+
+        let e = DescriptorMatcher::new(&desc).matches(&Scope::new(), &args).err().unwrap();
+
+        assert!(matches!(e.downcast_ref::<MatchError>(), Some(MatchError::Type { name, .. }) if name == "a"));
+    }
+
+    #[test]
+    fn wrong_static_words_are_errors() {
+        let desc = descriptor("'f 'to <a str>;");
+        let args = arguments("f from x;");
+
+        let e = DescriptorMatcher::new(&desc).matches(&Scope::new(), &args).err().unwrap();
+
+        assert!(matches!(e.downcast_ref::<MatchError>(), Some(MatchError::StaticWord { word }) if word == "to"));
+    }
+}
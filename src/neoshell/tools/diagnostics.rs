@@ -0,0 +1,99 @@
+#![deny(missing_docs)]
+//! Diagnostics reported while compiling and executing commands.
+
+use std::fmt;
+
+use crate::neoshell::parser::*;
+
+
+/// Severity of a diagnostic.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Severity {
+    /// Probable mistake, which doesn't prevent the execution.
+    Warning,
+    /// Mistake preventing the execution.
+    Error,
+}
+
+/// Diagnostic about a piece of code.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// Severity of the diagnostic.
+    pub severity: Severity,
+    /// Description of the problem.
+    pub message: String,
+    /// Location of the problematic code, if known.
+    pub span: Option<AstSpan>,
+}
+
+/// Sink receiving the diagnostics reported by the tools.
+pub trait DiagnosticSink {
+    /// Reports a diagnostic.
+    fn report(&mut self, diag: Diagnostic);
+}
+
+
+
+impl Diagnostic {
+
+    /// Creates a new warning.
+    pub fn new_warning(message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+            span: None,
+        }
+    }
+    /// Creates a new error.
+    pub fn new_error(message: String) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            span: None,
+        }
+    }
+    /// Sets the location of the diagnostic.
+    pub fn with_span(mut self, span: Option<AstSpan>) -> Diagnostic {
+        self.span = span;
+        self
+    }
+
+
+    /// Formats the diagnostic with the line and column of its location in
+    /// the given source.
+    pub fn format(&self, src: &str) -> String {
+        match self.span {
+            Some(s) => {
+                let (line, col) = s.locate(src);
+                format!("{} at {}:{}: {}", self.severity, line, col, self.message)
+            },
+            None => self.to_string(),
+        }
+    }
+
+}
+
+
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn report(&mut self, diag: Diagnostic) {
+        self.push(diag);
+    }
+}
+
+
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
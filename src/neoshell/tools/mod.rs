@@ -1,6 +1,18 @@
 
+pub mod argument_binder;
 pub mod argument_collector;
+pub mod choice_merger;
+pub mod declaration_checker;
+pub mod diagnostics;
+pub mod descriptor_matcher;
 pub mod switch_usage;
+pub mod type_checker;
 
+pub use self::argument_binder::*;
 pub use self::argument_collector::*;
+pub use self::choice_merger::*;
+pub use self::declaration_checker::*;
+pub use self::diagnostics::*;
+pub use self::descriptor_matcher::*;
 pub use self::switch_usage::*;
+pub use self::type_checker::*;
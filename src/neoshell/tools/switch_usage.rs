@@ -1,4 +1,8 @@
 #![deny(missing_docs)]
+//! Behaviors of command switches.
+
+use std::fmt;
+use std::ops::BitOr;
 
 use crate::neoshell::parser::*;
 
@@ -7,18 +11,33 @@ use failure::*;
 
 /// Switch usage trait. Defines how a switch behaves.
 pub trait SwitchUsage<'a, T> {
+	/// Gets the value of the switch when it is omitted.
 	fn initial_value(&'a self) -> T;
 
-	/// Collects the switch.
-	fn collect(&'a mut self, name: &String, sw: &AstSwitch, v: T) -> Result<T, Error>;
+	/// Collects the switch. `v` is the value collected so far, or the initial
+	/// value.
+	fn collect(&'a self, name: &str, sw: &AstSwitch, v: T) -> Result<T, Error>;
 }
 
 /// Errors related to switch usages.
-#[derive(Debug, Fail)]
+#[derive(Debug)]
 pub enum SwitchUsageError {
 	/// Invalid usage of a switch.
-	#[fail(display = "Invalid usage for switch '{}' : {}", name, msg)]
-	InvalidUsage{ name: String, msg: String }
+	InvalidUsage{
+		/// Name of the switch.
+		name: String,
+		/// Description of the invalid usage.
+		msg: String,
+	},
+	/// Value not allowed by a choice switch.
+	InvalidChoice{
+		/// Name of the switch.
+		name: String,
+		/// Given value.
+		value: String,
+		/// Valid values, separated by commas.
+		valid: String,
+	},
 }
 
 
@@ -41,13 +60,15 @@ pub struct TernarySwitch<T> {
 	disabled: T,
 }
 
-/// Switch that can have multiple values, only one at a time. Only supports the option switches.
+/// Switch that can have multiple values, only one at a time. Only supports the choice switches :
+/// `switch-name -> value`.
 pub struct ChoiceOptionSwitch<T> {
 	initial: T,
 	values: Vec<(AstArgument, T)>,
 }
 
-/// Switch that can have multiple values, multiple at one time. Only supports the option switches.
+/// Switch that can have multiple values, multiple at one time. Only supports the multi-choice
+/// switches : `switch-name => value, ...`. The chosen values are combined with `|`.
 pub struct MultiChoiceOptionSwitch<T> {
 	initial: T,
 	values: Vec<(AstArgument, T)>,
@@ -58,25 +79,131 @@ pub struct MultiChoiceOptionSwitch<T> {
 
 
 impl SwitchUsageError {
+	/// Creates a new `InvalidUsage` error.
 	pub fn new_invalid_usage(n: String, m: &'static str) -> Error {
 		Error::from(SwitchUsageError::InvalidUsage {
 			name: n,
 			msg: m.to_owned(),
 		})
 	}
+	/// Creates a new `InvalidChoice` error.
+	pub fn new_invalid_choice<T>(n: &str, v: &AstArgument, values: &[(AstArgument, T)]) -> Error {
+		let valid: Vec<String> = values.iter().map(|(a, _)| a.to_string()).collect();
+
+		Error::from(SwitchUsageError::InvalidChoice {
+			name: n.to_owned(),
+			value: v.to_string(),
+			valid: valid.join(", "),
+		})
+	}
+}
+
+impl fmt::Display for SwitchUsageError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SwitchUsageError::InvalidUsage { name, msg } => write!(f, "Invalid usage for switch '{}' : {}", name, msg),
+			SwitchUsageError::InvalidChoice { value, name, valid } => write!(f, "Invalid value '{}' for switch '{}'. Valid values are : {}.", value, name, valid),
+		}
+	}
+}
+
+impl Fail for SwitchUsageError {}
+
+
+impl<T> EnablingSwitch<T> {
+	/// Creates a new enabling switch.
+	pub fn new(initial: T, enabled: T) -> EnablingSwitch<T> {
+		EnablingSwitch {
+			initial,
+			enabled,
+		}
+	}
+}
+
+impl<T> DisablingSwitch<T> {
+	/// Creates a new disabling switch.
+	pub fn new(initial: T, disabled: T) -> DisablingSwitch<T> {
+		DisablingSwitch {
+			initial,
+			disabled,
+		}
+	}
+}
+
+impl<T> TernarySwitch<T> {
+	/// Creates a new ternary switch.
+	pub fn new(initial: T, enabled: T, disabled: T) -> TernarySwitch<T> {
+		TernarySwitch {
+			initial,
+			enabled,
+			disabled,
+		}
+	}
+}
+
+impl<T> ChoiceOptionSwitch<T> {
+	/// Creates a new choice switch, mapping the allowed values to their
+	/// results.
+	pub fn new(initial: T, values: Vec<(AstArgument, T)>) -> ChoiceOptionSwitch<T> {
+		ChoiceOptionSwitch {
+			initial,
+			values,
+		}
+	}
+	/// Creates a new named choice switch, mapping the allowed names to their
+	/// results : `switch-name -> name`.
+	pub fn new_named(initial: T, values: Vec<(String, T)>) -> ChoiceOptionSwitch<T> {
+		ChoiceOptionSwitch::new(initial, named_values(values))
+	}
+	/// Gets the result associated with the given value, or an error listing
+	/// the valid values.
+	pub fn choose(&self, name: &str, v: &AstArgument) -> Result<&T, Error> {
+		find_value(name, v, &self.values)
+	}
+}
+
+impl ChoiceOptionSwitch<Option<AstArgument>> {
+	/// Creates the switch of a described named choice, mapping the described
+	/// names to their values. The initial value is the value of the default
+	/// name, if any.
+	pub fn new_described(pairs: &[(String, AstArgument)], def: &Option<AstArgument>) -> ChoiceOptionSwitch<Option<AstArgument>> {
+		let values = named_values(pairs.iter().map(|(n, a)| (n.clone(), Some(a.clone()))).collect());
+		let initial = def.as_ref().map(|d| match find_value("", d, &values) {
+			Ok(Some(a)) => a.clone(),
+			_ => d.clone(),
+		});
+
+		ChoiceOptionSwitch::new(initial, values)
+	}
+}
+
+impl<T> MultiChoiceOptionSwitch<T> {
+	/// Creates a new multi-choice switch, mapping the allowed values to their
+	/// results.
+	pub fn new(initial: T, values: Vec<(AstArgument, T)>) -> MultiChoiceOptionSwitch<T> {
+		MultiChoiceOptionSwitch {
+			initial,
+			values,
+		}
+	}
+	/// Creates a new named multi-choice switch, mapping the allowed names to
+	/// their results : `switch-name => name, ...`.
+	pub fn new_named(initial: T, values: Vec<(String, T)>) -> MultiChoiceOptionSwitch<T> {
+		MultiChoiceOptionSwitch::new(initial, named_values(values))
+	}
 }
 
 
 impl<'a, T> SwitchUsage<'a, &'a T> for EnablingSwitch<T> {
 
 	fn initial_value(&'a self) -> &'a T {
-		return &self.initial;
+		&self.initial
 	}
 
-	fn collect(&'a mut self, name: &String, sw: &AstSwitch, _: &'a T) -> Result<&'a T, Error> {
+	fn collect(&'a self, name: &str, sw: &AstSwitch, _: &'a T) -> Result<&'a T, Error> {
 		match sw {
 			AstSwitch::On(_) => Ok(&self.enabled),
-			_ => Err( SwitchUsageError::new_invalid_usage(name.clone(), "This switch only accepts on switch (+switch-name).") )
+			_ => Err( SwitchUsageError::new_invalid_usage(name.to_owned(), "This switch only accepts on switch (+switch-name).") )
 		}
 	}
 
@@ -85,13 +212,13 @@ impl<'a, T> SwitchUsage<'a, &'a T> for EnablingSwitch<T> {
 impl<'a, T> SwitchUsage<'a, &'a T> for DisablingSwitch<T> {
 
 	fn initial_value(&'a self) -> &'a T {
-		return &self.initial;
+		&self.initial
 	}
 
-	fn collect(&'a mut self, name: &String, sw: &AstSwitch, _: &'a T) -> Result<&'a T, Error> {
+	fn collect(&'a self, name: &str, sw: &AstSwitch, _: &'a T) -> Result<&'a T, Error> {
 		match sw {
 			AstSwitch::Off(_) => Ok(&self.disabled),
-			_ => Err( SwitchUsageError::new_invalid_usage(name.clone(), "This switch only accepts off switchs (-switch-name).") )
+			_ => Err( SwitchUsageError::new_invalid_usage(name.to_owned(), "This switch only accepts off switchs (-switch-name).") )
 		}
 	}
 
@@ -100,15 +227,114 @@ impl<'a, T> SwitchUsage<'a, &'a T> for DisablingSwitch<T> {
 impl<'a, T> SwitchUsage<'a, &'a T> for TernarySwitch<T> {
 
 	fn initial_value(&'a self) -> &'a T {
-		return &self.initial;
+		&self.initial
 	}
 
-	fn collect(&'a mut self, name: &String, sw: &AstSwitch, _: &'a T) -> Result<&'a T, Error> {
+	fn collect(&'a self, name: &str, sw: &AstSwitch, _: &'a T) -> Result<&'a T, Error> {
 		match sw {
 			AstSwitch::On(_) => Ok(&self.enabled),
 			AstSwitch::Off(_) => Ok(&self.disabled),
-			_ => Err( SwitchUsageError::new_invalid_usage(name.clone(), "This switch only accepts on or off switchs (+switch-name, -switch-name).") )
+			_ => Err( SwitchUsageError::new_invalid_usage(name.to_owned(), "This switch only accepts on or off switchs (+switch-name, -switch-name).") )
 		}
 	}
 
 }
+
+impl<'a, T> SwitchUsage<'a, &'a T> for ChoiceOptionSwitch<T> {
+
+	fn initial_value(&'a self) -> &'a T {
+		&self.initial
+	}
+
+	fn collect(&'a self, name: &str, sw: &AstSwitch, _: &'a T) -> Result<&'a T, Error> {
+		match sw {
+			AstSwitch::Choice(_, v) => self.choose(name, v),
+			_ => Err( SwitchUsageError::new_invalid_usage(name.to_owned(), "This switch only accepts choice switches (switch-name -> value).") )
+		}
+	}
+
+}
+
+impl<'a, T> SwitchUsage<'a, T> for MultiChoiceOptionSwitch<T>
+where
+	T: Clone + BitOr<Output = T>
+{
+
+	fn initial_value(&'a self) -> T {
+		self.initial.clone()
+	}
+
+	fn collect(&'a self, name: &str, sw: &AstSwitch, v: T) -> Result<T, Error> {
+		match sw {
+			AstSwitch::MultiChoice(_, values) => {
+				let mut r = v;
+				for v in values {
+					r = r | find_value(name, v, &self.values)?.clone();
+				}
+				Ok(r)
+			},
+			_ => Err( SwitchUsageError::new_invalid_usage(name.to_owned(), "This switch only accepts multi-choice switches (switch-name => value, ...).") )
+		}
+	}
+
+}
+
+
+
+/// Finds the result associated with the given value of a choice.
+fn find_value<'a, T>(name: &str, v: &AstArgument, values: &'a [(AstArgument, T)]) -> Result<&'a T, Error> {
+	match values.iter().find(|(a, _)| a == v) {
+		Some((_, r)) => Ok(r),
+		None => Err( SwitchUsageError::new_invalid_choice(name, v, values) ),
+	}
+}
+
+/// Turns named values into words associated with their values.
+fn named_values<T>(values: Vec<(String, T)>) -> Vec<(AstArgument, T)> {
+	values.into_iter()
+		.map(|(n, v)| (AstArgument::Name(AstName::Name(n)), v))
+		.collect()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn name(n: &str) -> AstArgument {
+		AstArgument::Name(AstName::Name(n.to_owned()))
+	}
+
+	#[test]
+	fn named_choices_collect_their_values() {
+		let s = ChoiceOptionSwitch::new_named(0, vec![("low".to_owned(), 1), ("high".to_owned(), 2)]);
+		let sw = AstSwitch::Choice("c".to_owned(), Box::new(name("high")));
+
+		assert_eq!(s.collect("c", &sw, s.initial_value()).ok(), Some(&2));
+	}
+
+	#[test]
+	fn invalid_choices_list_the_valid_values() {
+		let s = ChoiceOptionSwitch::new_named(0, vec![("low".to_owned(), 1), ("high".to_owned(), 2)]);
+		let e = s.choose("c", &name("mid")).err().unwrap();
+
+		assert_eq!(e.to_string(), "Invalid value 'mid' for switch 'c'. Valid values are : low, high.");
+	}
+
+	#[test]
+	fn multi_choices_combine_their_values() {
+		let s = MultiChoiceOptionSwitch::new_named(0, vec![("r".to_owned(), 4), ("w".to_owned(), 2), ("x".to_owned(), 1)]);
+		let sw = AstSwitch::MultiChoice("m".to_owned(), vec![name("r"), name("x")]);
+
+		assert_eq!(s.collect("m", &sw, s.initial_value()).ok(), Some(5));
+	}
+
+	#[test]
+	fn described_choices_start_with_the_default_value() {
+		let pairs = vec![("low".to_owned(), AstArgument::Integer(1)), ("high".to_owned(), AstArgument::Integer(2))];
+		let s = ChoiceOptionSwitch::new_described(&pairs, &Some(name("high")));
+
+		assert!(s.initial_value() == &Some(AstArgument::Integer(2)));
+	}
+}
@@ -0,0 +1,362 @@
+#![deny(missing_docs)]
+//! Compile time checking of the types of command arguments and streams.
+
+use crate::neoshell::parser::*;
+use crate::neoshell::vm::*;
+
+use super::argument_binder::repetition_warning;
+use super::declaration_checker::is_declarator;
+use super::descriptor_matcher::*;
+use super::diagnostics::*;
+
+
+/// Checks at compile time, after the macro expansion, that the arguments of
+/// the commands have the types described by the resolved overloads, and that
+/// the piped commands generate and consume compatible streams.
+///
+/// The types of literals, variables and evaluated blocks are inferred. The
+/// variables declared by the declaring commands (see `DECLARATORS`) take
+/// their declared type, or the type of their value. Unknown types are
+/// accepted, and the arrays and streams given to multi-valued arguments are
+/// checked item by item. Flags, options and choices given several times are
+/// reported as warnings.
+pub struct TypeChecker<'r> {
+    registery: &'r CommandRegistery,
+}
+
+
+impl<'r> TypeChecker<'r> {
+
+    /// Creates a new checker resolving the commands in the given registery.
+    pub fn new(registery: &'r CommandRegistery) -> TypeChecker<'r> {
+        TypeChecker {
+            registery,
+        }
+    }
+
+
+    /// Checks the given commands, reporting the violations to the given sink.
+    /// Returns the number of errors found.
+    pub fn check(&self, scp: &Scope, cmds: &[AstCommand], sink: &mut dyn DiagnosticSink) -> usize {
+        let mut diags = Vec::new();
+        self.check_block(scp, cmds, &mut diags);
+
+        let errors = diags.iter().filter(|d| d.severity == Severity::Error).count();
+        for d in diags {
+            sink.report(d);
+        }
+
+        errors
+    }
+
+
+    fn check_block(&self, scp: &Scope, cmds: &[AstCommand], diags: &mut Vec<Diagnostic>) {
+        let sub = Scope::extends(scp);
+
+        for c in cmds {
+            self.check_command(&sub, c, diags);
+        }
+    }
+
+    fn check_command(&self, scp: &Scope, cmd: &AstCommand, diags: &mut Vec<Diagnostic>) {
+        let mut desc = self.check_stage(scp, cmd, diags);
+        let mut stage = cmd;
+
+        while let Some(p) = &stage.pipe {
+            let consumer = self.check_stage(scp, p, diags);
+            self.check_pipe(stage, desc, p, consumer, diags);
+
+            desc = consumer;
+            stage = p;
+        }
+
+        self.declare(scp, cmd);
+    }
+
+    /// Checks a command of a pipeline, returning the descriptor of its
+    /// resolved overload.
+    fn check_stage(&self, scp: &Scope, cmd: &AstCommand, diags: &mut Vec<Diagnostic>) -> Option<&'r AstDescriptor> {
+        if let AstName::Block(b) = &cmd.name {
+            self.check_block(scp, block_commands(b), diags);
+        }
+        for a in cmd.arguments.iter() {
+            if let AstArgument::Block(b) = a {
+                self.check_block(scp, block_commands(b), diags);
+            }
+        }
+
+        self.check_arguments(scp, cmd, diags)
+    }
+
+    /// Checks the arguments of a command against the descriptor of its
+    /// resolved overload, which is returned. Literals and variables are
+    /// checked by the resolution, leaving the evaluated blocks.
+    fn check_arguments(&self, scp: &Scope, cmd: &AstCommand, diags: &mut Vec<Diagnostic>) -> Option<&'r AstDescriptor> {
+        let desc = match self.resolve(scp, cmd) {
+            Ok(d) => d?,
+            Err(e) => {
+                diags.push(Diagnostic::new_error(e.to_string()).with_span(cmd.span));
+                return None;
+            },
+        };
+
+        let m = match DescriptorMatcher::new(desc).matches(scp, &cmd.arguments) {
+            Ok(m) => m,
+            Err(e) => {
+                diags.push(Diagnostic::new_error(e.to_string()).with_span(cmd.span));
+                return Some(desc);
+            },
+        };
+
+        for (d, given) in m.arguments {
+            if let MatchedArgument::Switches(v) = &given {
+                if let Some(w) = repetition_warning(desc, d, v.len()) {
+                    diags.push(w.with_span(cmd.span));
+                }
+            }
+
+            let ty = match described_type(d) {
+                Some(t) => t,
+                None => continue,
+            };
+            let given = match &given {
+                MatchedArgument::Positionals(v) => v.clone(),
+                MatchedArgument::Switches(v) => v.iter().flat_map(|s| switch_values(s)).collect(),
+                MatchedArgument::Omitted => Vec::new(),
+            };
+
+            let multi = matches!(d.kind, AstArgumentKind::PositionalList(..) | AstArgumentKind::List(..));
+
+            for a in given.into_iter().filter(|a| matches!(a, AstArgument::Block(_))) {
+                if let Some(found) = self.infer(scp, a, &ty) {
+                    let spliced = match &found {
+                        Type::Array(i) | Type::Stream(i) => multi && is_spliced(&ty) && i.is_convertible_to(&ty),
+                        _ => false,
+                    };
+
+                    if !found.is_convertible_to(&ty) && !spliced {
+                        diags.push(Diagnostic::new_error(format!(
+                            "Argument '{}' of command '{}' expects a value of type '{}', found a block of type '{}'.",
+                            d.name, desc.name, ty, found
+                        )).with_span(cmd.span));
+                    }
+                }
+            }
+        }
+
+        Some(desc)
+    }
+
+    /// Checks that the stream generated by a command can be consumed by the
+    /// command it is piped into.
+    fn check_pipe(&self, cmd: &AstCommand, producer: Option<&AstDescriptor>, pipe: &AstCommand, consumer: Option<&AstDescriptor>, diags: &mut Vec<Diagnostic>) {
+        let output = match producer {
+            Some(d) => match &d.output {
+                Some(t) => Type::from_name(t),
+                None => {
+                    diags.push(Diagnostic::new_error(format!(
+                        "Command '{}' doesn't generate a stream and can't be piped into '{}'.", d.name, pipe.name
+                    )).with_span(cmd.span));
+                    return;
+                },
+            },
+            None => None,
+        };
+        let input = match consumer {
+            Some(d) => match &d.input {
+                Some(t) => Type::from_name(t),
+                None => {
+                    diags.push(Diagnostic::new_error(format!(
+                        "Command '{}' doesn't consume a stream and can't be piped from '{}'.", d.name, cmd.name
+                    )).with_span(pipe.span));
+                    return;
+                },
+            },
+            None => None,
+        };
+
+        if let (Some(o), Some(i)) = (output, input) {
+            if !o.is_convertible_to(&i) {
+                diags.push(Diagnostic::new_error(format!(
+                    "Command '{}' generates a stream of '{}', but '{}' consumes a stream of '{}'.", cmd.name, o, pipe.name, i
+                )).with_span(pipe.span));
+            }
+        }
+    }
+
+    /// Declares the variable declared by a declaring command, with its type.
+    fn declare(&self, scp: &Scope, cmd: &AstCommand) {
+        match &cmd.name {
+            AstName::Name(n) if is_declarator(n) => {},
+            _ => return,
+        }
+
+        let (var, ty) = match cmd.arguments.as_slice() {
+            [AstArgument::Name(AstName::Name(v)), AstArgument::Name(AstName::Name(t)), _] => (v, Type::from_name(t)),
+            [AstArgument::Name(AstName::Name(v)), a] => (v, self.infer(scp, a, &Type::Generic)),
+            _ => return,
+        };
+
+        scp.declare_type(var, ty.unwrap_or(Type::Generic));
+    }
+
+
+    /// Resolves the overload called by a command, and gets its descriptor.
+    /// Unknown commands and commands without descriptor are not checked.
+    fn resolve(&self, scp: &Scope, cmd: &AstCommand) -> Result<Option<&'r AstDescriptor>, failure::Error> {
+        let name = match &cmd.name {
+            AstName::Name(n) => n,
+            _ => return Ok(None),
+        };
+
+        if !self.registery.has_command(name) {
+            return Ok(None);
+        }
+
+        let r = self.registery.resolve(scp, name, &[AstTime::Runtime], &cmd.arguments)?;
+        Ok(self.registery.get_descriptor(name, &r))
+    }
+
+    /// Infers the type of an argument given for an argument of the given
+    /// type. Returns `None` if the type can't be known at compile time.
+    fn infer(&self, scp: &Scope, arg: &AstArgument, expected: &Type) -> Option<Type> {
+        match arg {
+            AstArgument::None => Some(Type::Void),
+            AstArgument::Integer(_) => Some(Type::I4),
+            AstArgument::Float(_) => Some(Type::F4),
+            AstArgument::String(_) => Some(Type::Str),
+            AstArgument::Name(AstName::Name(w)) if w == "true" || w == "false" => Some(Type::Bool),
+            AstArgument::Name(AstName::Name(_)) => Some(Type::Str),
+            AstArgument::Name(AstName::Variable(v)) => scp.get_variable_type(v),
+            AstArgument::Name(AstName::Placeholder) | AstArgument::Name(AstName::Block(_)) => None,
+            AstArgument::Switch(_) => None,
+            AstArgument::Descriptor(_) => Some(Type::Descriptor),
+            AstArgument::Block(AstBlock::Argument(_)) => Some(Type::Block),
+            AstArgument::Block(AstBlock::Inferred(_)) if *expected == Type::Block => Some(Type::Block),
+            AstArgument::Block(AstBlock::Evaluated(c)) | AstArgument::Block(AstBlock::Inferred(c)) => {
+                self.infer_block(scp, c)
+            },
+        }
+    }
+
+    /// Infers the type of the value of an evaluated block : the stream
+    /// generated by its last command, if its items have a known type.
+    fn infer_block(&self, scp: &Scope, cmds: &[AstCommand]) -> Option<Type> {
+        let mut last = cmds.last()?;
+
+        while let Some(p) = &last.pipe {
+            last = p;
+        }
+
+        if let AstName::Name(n) = &last.name {
+            if is_declarator(n) {
+                return Some(Type::Void);
+            }
+        }
+
+        let desc = self.resolve(scp, last).ok()??;
+        match Type::from_name(desc.output.as_ref()?)? {
+            Type::Generic => None,
+            t => Some(Type::Stream(Box::new(t))),
+        }
+    }
+
+}
+
+
+
+/// Gets the commands of a block.
+fn block_commands(b: &AstBlock) -> &[AstCommand] {
+    match b {
+        AstBlock::Evaluated(c) | AstBlock::Argument(c) | AstBlock::Inferred(c) => c,
+    }
+}
+
+/// Gets the values given by a switch.
+fn switch_values(s: &AstSwitch) -> Vec<&AstArgument> {
+    match s {
+        AstSwitch::Option(_, v) | AstSwitch::Choice(_, v) => vec![v.as_ref()],
+        AstSwitch::List(_, v) | AstSwitch::MultiChoice(_, v) => v.iter().collect(),
+        AstSwitch::On(_) | AstSwitch::Off(_) => Vec::new(),
+    }
+}
+
+/// Gets the described type of the values of an argument, if it has one.
+fn described_type(d: &AstArgumentDescriptor) -> Option<Type> {
+    match &d.kind {
+        AstArgumentKind::Positional(t, _) | AstArgumentKind::PositionalList(t, _) |
+        AstArgumentKind::Option(t, _) | AstArgumentKind::List(t, _) => Type::from_name(t),
+        _ => None,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::Error;
+
+    struct NopCommand { }
+
+    impl RuntimeCommand for NopCommand {
+        fn execute(&self, _vm: &VM, _scp: &mut Scope, _cmd: &AstCommand) -> Result<Value, Error> {
+            Ok(Value::Void)
+        }
+    }
+
+    fn check(src: &str) -> (usize, Vec<Diagnostic>) {
+        let mut reg = CommandRegistery::new();
+        reg.register_described("'num <n i4>;", NopCommand{}).unwrap();
+        reg.register_described("'opt [n = i4];", NopCommand{}).unwrap();
+        reg.register_described("'gen str|>;", NopCommand{}).unwrap();
+        reg.register_described("'sum |>i4;", NopCommand{}).unwrap();
+
+        let cmds = ns_parser::file(src).unwrap();
+        let mut diags = Vec::new();
+        let errors = TypeChecker::new(&reg).check(&Scope::new(), &cmds, &mut diags);
+
+        (errors, diags)
+    }
+
+    #[test]
+    fn well_typed_commands_have_no_diagnostics() {
+        let (errors, diags) = check("num 1; opt n = 2; unknown !{gen;}; let x 3; num $x;");
+
+        assert_eq!(errors, 0);
+        assert!(diags.is_empty());
+    }
+
+    #[test]
+    fn blocks_of_the_wrong_type_are_errors() {
+        let (errors, diags) = check("num !{gen;};");
+
+        assert_eq!(errors, 1);
+        assert!(diags[0].message.contains("found a block of type '<str>'"), "{}", diags[0].message);
+        assert!(diags[0].span.is_some());
+    }
+
+    #[test]
+    fn incompatible_pipes_are_errors() {
+        assert_eq!(check("gen |> sum;").0, 1);
+        assert_eq!(check("num 1 |> sum;").0, 1);
+        assert_eq!(check("gen |> num 1;").0, 1);
+    }
+
+    #[test]
+    fn declared_variables_take_their_types() {
+        let (errors, diags) = check("let s \"a\"; num $s;");
+
+        assert_eq!(errors, 1);
+        assert!(diags[0].message.contains("num"));
+    }
+
+    #[test]
+    fn repeated_options_are_warnings() {
+        let (errors, diags) = check("opt n = 1 n = 2;");
+
+        assert_eq!(errors, 0);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+    }
+}
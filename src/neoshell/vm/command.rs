@@ -1,11 +1,14 @@
 #![deny(missing_docs)]
+//! Command executor traits.
 
+use std::fmt;
 
 use crate::neoshell::parser::*;
-use super::vm::VM;
+use super::machine::VM;
 use super::scope::Scope;
+use super::value::{Value, ValueStream};
 
-use failure::Error;
+use failure::*;
 
 
 
@@ -25,7 +28,62 @@ pub trait MacroCommand {
 
 /// Runtime command executor.
 ///
+/// Generators return their output as a stream value. Consumers receive the
+/// stream generated by the previous command of the pipeline through
+/// `execute_piped`, pulling its items lazily.
 pub trait RuntimeCommand {
     /// Executes the command.
-    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<AstArgument, Error>;
+    fn execute(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error>;
+
+    /// Executes the command, consuming the given input stream. By default,
+    /// commands don't consume streams.
+    fn execute_piped(&self, _vm: &VM, _scp: &mut Scope, cmd: &AstCommand, _input: ValueStream) -> Result<Value, Error> {
+        Err(CommandError::new_not_a_consumer(&cmd.name.to_string()))
+    }
+}
+
+/// Errors related to command invocations.
+#[derive(Debug)]
+pub enum CommandError {
+    /// The arguments given to the command are invalid.
+    InvalidArguments {
+        /// Name of the command.
+        name: String,
+        /// Description of the problem.
+        msg: String,
+    },
+    /// A stream is piped into a command which doesn't consume streams.
+    NotAConsumer {
+        /// Name of the command.
+        name: String,
+    },
+}
+
+
+
+impl CommandError {
+    /// Creates a new `InvalidArguments` error.
+    pub fn new_invalid_arguments(name: &str, msg: &str) -> Error {
+        Error::from(CommandError::InvalidArguments {
+            name: name.to_owned(),
+            msg: msg.to_owned(),
+        })
+    }
+    /// Creates a new `NotAConsumer` error.
+    pub fn new_not_a_consumer(name: &str) -> Error {
+        Error::from(CommandError::NotAConsumer {
+            name: name.to_owned(),
+        })
+    }
 }
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::InvalidArguments { name, msg } => write!(f, "Invalid arguments for command '{}' : {}", name, msg),
+            CommandError::NotAConsumer { name } => write!(f, "Command '{}' doesn't consume streams.", name),
+        }
+    }
+}
+
+impl Fail for CommandError {}
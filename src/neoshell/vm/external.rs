@@ -0,0 +1,363 @@
+#![deny(missing_docs)]
+//! External programs run as commands.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use failure::*;
+
+use crate::neoshell::parser::*;
+
+use super::process::*;
+use super::redirection::*;
+use super::scope::*;
+use super::value::*;
+use super::machine::*;
+
+
+/// Word ending the flags and the redirections of an external program.
+pub const END_OF_FLAGS: &str = "--";
+
+
+
+/// Program found in `PATH`, used for commands which aren't registered.
+///
+/// Strings, numbers, booleans and words are passed as arguments to the
+/// program, while arrays and streams pass each of their items. The programs
+/// are run by a `ProcessPipeline`, returning their exit codes. A non-zero
+/// exit code is an error unless the `/allow-failure` flag is given.
+///
+/// When the output of a program is captured by an evaluated block, the
+/// `/raw`, `/lines`, `/with-stderr` and `/with-status` flags give the
+/// `CaptureOptions` of the capture.
+///
+/// The programs receive the environment of the scope running them, where
+/// `env = { NAME value; ... }` overrides variables for a single program.
+///
+/// The `/background` flag, given to any program of a pipeline, runs the
+/// pipeline as a background job instead of waiting for it. The pipeline then
+/// returns the id of the job, used by `wait`, `jobs` and `kill`. Only
+/// pipelines made of programs can run in the background : a program running
+/// in the background can't read the output of a command nor be piped into
+/// one, but its standard streams can be redirected.
+///
+/// A `--` word ends these flags and the redirections : the arguments following
+/// it are all passed to the program, `/name` flags as `/name` and `name = v`
+/// options as `name=v`, so `prog -- /background stdout = x;` passes
+/// `/background` and `stdout=x`. The `--` itself isn't passed, and has to be
+/// written twice to pass one.
+pub struct ExternalCommand {
+    name: String,
+    path: PathBuf,
+}
+
+/// Arguments of an external program.
+pub struct ExternalArguments {
+    /// Arguments passed to the program.
+    pub argv: Vec<String>,
+    /// Whether a non-zero exit code is allowed.
+    pub allow_failure: bool,
+    /// Redirections of the standard streams of the program.
+    pub redirections: Redirections,
+    /// Environment variables of the program.
+    pub env: HashMap<String, String>,
+    /// Options of the capture of the output of the program.
+    pub capture: CaptureOptions,
+    /// Whether the pipeline runs in the background.
+    pub background: bool,
+}
+
+/// Errors related to external programs.
+#[derive(Debug)]
+pub enum ExternalError {
+    /// The program exited with a non-zero exit code.
+    Failed {
+        /// Name of the program.
+        name: String,
+        /// Exit code of the program.
+        code: i32,
+    },
+    /// A process of a pipeline exited with a non-zero exit code.
+    PipelineFailed {
+        /// Programs of the pipeline.
+        pipeline: String,
+        /// Exit codes of the processes.
+        codes: String,
+    },
+    /// The program couldn't be started.
+    Spawn {
+        /// Name of the program.
+        name: String,
+        /// Description of the problem.
+        msg: String,
+    },
+    /// An argument can't be passed to a program.
+    InvalidArgument {
+        /// Name of the program.
+        name: String,
+        /// Description of the argument.
+        arg: String,
+    },
+    /// A pipeline running in the background is piped into a command.
+    PipedBackground {
+        /// Name of the last program of the pipeline.
+        name: String,
+        /// Name of the command it is piped into.
+        consumer: String,
+    },
+    /// A pipeline running in the background reads the output of a command.
+    FedBackground {
+        /// Name of the first program of the pipeline.
+        name: String,
+    },
+}
+
+
+
+impl ExternalError {
+    /// Creates a new `Failed` error for a single process, or a new
+    /// `PipelineFailed` error.
+    pub fn new_failed(names: &[String], codes: &[i32]) -> Error {
+        match (names, codes) {
+            ([name], [code]) => Error::from(ExternalError::Failed {
+                name: name.clone(),
+                code: *code,
+            }),
+            _ => Error::from(ExternalError::PipelineFailed {
+                pipeline: names.join(" |> "),
+                codes: codes.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "),
+            }),
+        }
+    }
+    /// Creates a new `Spawn` error.
+    pub fn new_spawn(name: &str, e: &std::io::Error) -> Error {
+        Error::from(ExternalError::Spawn {
+            name: name.to_owned(),
+            msg: e.to_string(),
+        })
+    }
+    /// Creates a new `InvalidArgument` error.
+    pub fn new_invalid_argument(name: &str, arg: String) -> Error {
+        Error::from(ExternalError::InvalidArgument {
+            name: name.to_owned(),
+            arg,
+        })
+    }
+    /// Creates a new `PipedBackground` error.
+    pub fn new_piped_background(name: &str, consumer: &AstName) -> Error {
+        Error::from(ExternalError::PipedBackground {
+            name: name.to_owned(),
+            consumer: consumer.to_string(),
+        })
+    }
+    /// Creates a new `FedBackground` error.
+    pub fn new_fed_background(name: &str) -> Error {
+        Error::from(ExternalError::FedBackground {
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for ExternalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExternalError::Failed { name, code } => write!(f, "Program '{}' exited with code {}.", name, code),
+            ExternalError::PipelineFailed { pipeline, codes } => write!(f, "Pipeline '{}' exited with codes {}.", pipeline, codes),
+            ExternalError::Spawn { name, msg } => write!(f, "Program '{}' couldn't be started : {}", name, msg),
+            ExternalError::InvalidArgument { arg, name } => write!(f, "Argument '{}' can't be passed to program '{}'.", arg, name),
+            ExternalError::PipedBackground { name, consumer } => write!(f, "Program '{}' runs in the background and can't be piped into '{}' : only pipelines of programs can run in the background.", name, consumer),
+            ExternalError::FedBackground { name } => write!(f, "Program '{}' runs in the background and can't read the output of a command : only pipelines of programs can run in the background.", name),
+        }
+    }
+}
+
+impl Fail for ExternalError {}
+
+
+
+impl ExternalCommand {
+
+    /// Finds the program with the given name in the directories of the given
+    /// `PATH`.
+    pub fn find(name: &str, path: &str) -> Option<ExternalCommand> {
+        env::split_paths(path)
+            .map(|d| d.join(name))
+            .find(|p| is_executable(p))
+            .map(|p| ExternalCommand {
+                name: name.to_owned(),
+                path: p,
+            })
+    }
+
+
+    /// Gets the name of the program.
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+    /// Gets the path of the program.
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+
+    /// Evaluates the arguments of the given command into the arguments of the
+    /// program.
+    pub fn arguments(&self, vm: &VM, scp: &mut Scope, cmd: &AstCommand) -> Result<ExternalArguments, Error> {
+        let mut args = ExternalArguments {
+            argv: Vec::new(),
+            allow_failure: false,
+            redirections: Redirections::open(vm, scp, &cmd.redirections)?,
+            env: scp.get_environment(),
+            capture: CaptureOptions::default(),
+            background: false,
+        };
+
+        let mut iter = cmd.arguments.iter();
+
+        for a in iter.by_ref() {
+            match a {
+                AstArgument::Name(AstName::Name(n)) if n == END_OF_FLAGS => break,
+                AstArgument::Switch(AstSwitch::On(n)) => match n.as_str() {
+                    "allow-failure" => args.allow_failure = true,
+                    "raw" => args.capture.raw = true,
+                    "lines" => args.capture.lines = true,
+                    "with-stderr" => args.capture.stderr = true,
+                    "with-status" => args.capture.status = true,
+                    "background" => args.background = true,
+                    _ => return Err(ExternalError::new_invalid_argument(&self.name, a.to_string())),
+                },
+                AstArgument::Switch(AstSwitch::Option(n, v)) if n == "env" => self.override_env(vm, scp, &mut args.env, v)?,
+                AstArgument::Switch(_) => return Err(ExternalError::new_invalid_argument(&self.name, a.to_string())),
+                _ => self.push_value(&mut args.argv, vm.evaluate(scp, a)?)?,
+            }
+        }
+
+        for a in iter {
+            match a {
+                AstArgument::Switch(AstSwitch::On(n)) => args.argv.push(format!("/{}", n)),
+                AstArgument::Switch(AstSwitch::Option(n, v)) => {
+                    let v = vm.evaluate(scp, v)?;
+                    args.argv.push(format!("{}={}", n, v));
+                },
+                AstArgument::Switch(_) => return Err(ExternalError::new_invalid_argument(&self.name, a.to_string())),
+                _ => self.push_value(&mut args.argv, vm.evaluate(scp, a)?)?,
+            }
+        }
+
+        Ok(args)
+    }
+    /// Builds the process running the program with the given arguments.
+    pub fn command(&self, args: &ExternalArguments) -> Command {
+        let mut c = Command::new(&self.path);
+        c.args(&args.argv).env_clear().envs(&args.env);
+        c
+    }
+
+
+    /// Overrides environment variables with the commands of a block, each one
+    /// naming a variable and giving its value.
+    fn override_env(&self, vm: &VM, scp: &mut Scope, env: &mut HashMap<String, String>, arg: &AstArgument) -> Result<(), Error> {
+        let cmds = match arg {
+            AstArgument::Block(AstBlock::Inferred(c)) | AstArgument::Block(AstBlock::Argument(c)) => c,
+            _ => return Err(ExternalError::new_invalid_argument(&self.name, format!("env = {}", arg))),
+        };
+
+        for c in cmds {
+            match (&c.name, c.arguments.as_slice()) {
+                (AstName::Name(n), [v]) => {
+                    let v = vm.evaluate(scp, v)?;
+                    env.insert(n.clone(), v.to_string());
+                },
+                _ => return Err(ExternalError::new_invalid_argument(&self.name, format!("env = {{ {}; }}", c))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push_value(&self, argv: &mut Vec<String>, v: Value) -> Result<(), Error> {
+        match v {
+            Value::Void => Ok(()),
+            Value::Array(_, a) => a.into_iter().try_for_each(|v| self.push_value(argv, v)),
+            Value::Stream(_, s) => s.into_iter().try_for_each(|v| self.push_value(argv, v?)),
+            Value::Closure(_) | Value::Command(_) | Value::Ast(_) => {
+                Err(ExternalError::new_invalid_argument(&self.name, v.to_string()))
+            },
+            v => {
+                argv.push(v.to_string());
+                Ok(())
+            },
+        }
+    }
+
+}
+
+
+
+/// Gets the exit code of a process.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    match (status.code(), status.signal()) {
+        (Some(c), _) => c,
+        (None, Some(s)) => 128 + s,
+        (None, None) => -1,
+    }
+}
+
+/// Checks if the given path is an executable file.
+fn is_executable(p: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match p.metadata() {
+        Ok(m) => m.is_file() && m.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neoshell::builtins::tests::{output, run};
+
+    fn value(src: &str) -> String {
+        run(src).unwrap().to_string()
+    }
+
+    #[test]
+    fn programs_are_found_in_path() {
+        let path = env::var("PATH").unwrap_or_default();
+
+        assert!(ExternalCommand::find("sh", &path).is_some());
+        assert!(ExternalCommand::find("neoshell-missing-program", &path).is_none());
+        assert!(run("neoshell-missing-program;").is_err());
+    }
+
+    #[test]
+    fn values_are_passed_as_arguments() {
+        assert_eq!(output("let n 3; echo a $n 1.5 true stdout = $out;").unwrap(), "a 3 1.5 true\n");
+        assert_eq!(output("let r !{ range 1 3 |> collect }; echo $r stdout = $out;").unwrap(), "1 2\n");
+        assert!(run("echo /unknown-flag;").is_err());
+    }
+
+    #[test]
+    fn programs_return_their_exit_code() {
+        assert_eq!(value("true;"), "0");
+        assert_eq!(value("false /allow-failure;"), "1");
+        assert_eq!(value("sh \"-c\" \"exit 3\" /allow-failure;"), "3");
+
+        let e = run("sh \"-c\" \"exit 3\";").err().unwrap();
+        assert!(matches!(e.downcast_ref::<ExternalError>(), Some(ExternalError::Failed { code: 3, .. })));
+    }
+
+    #[test]
+    fn end_of_flags_passes_the_following_arguments() {
+        assert_eq!(output("echo a stdout = $out -- /background n = 1;").unwrap(), "a /background n=1\n");
+        assert_eq!(output("echo stdout = $out -- -- a;").unwrap(), "-- a\n");
+    }
+}
@@ -0,0 +1,174 @@
+#![deny(missing_docs)]
+//! Pipelines of programs running in the background.
+
+use std::fmt;
+use failure::*;
+
+use super::process::*;
+
+
+/// Jobs started by the VM, identified by ids starting at 1. A job stays in
+/// the table until it is waited, keeping its exit codes once it ended.
+pub struct JobTable {
+    jobs: Vec<Job>,
+    next: i32,
+}
+
+/// Pipeline of programs running in the background.
+pub struct Job {
+    id: i32,
+    command: String,
+    pipeline: ProcessPipeline,
+}
+
+/// State of a job.
+pub enum JobState {
+    /// Some processes of the job are still running.
+    Running,
+    /// All the processes of the job ended, with the given exit codes.
+    Done(Vec<i32>),
+}
+
+/// Errors related to jobs.
+#[derive(Debug)]
+pub enum JobError {
+    /// No job has the given id.
+    UnknownJob {
+        /// Id of the job.
+        id: i32,
+    },
+    /// The signal couldn't be sent to the job.
+    Signal {
+        /// Id of the job.
+        id: i32,
+        /// Signal sent.
+        signal: i32,
+        /// Description of the problem.
+        msg: String,
+    },
+}
+
+
+
+impl JobError {
+    /// Creates a new `UnknownJob` error.
+    pub fn new_unknown_job(id: i32) -> Error {
+        Error::from(JobError::UnknownJob {
+            id,
+        })
+    }
+    /// Creates a new `Signal` error.
+    pub fn new_signal(id: i32, signal: i32, e: &std::io::Error) -> Error {
+        Error::from(JobError::Signal {
+            id,
+            signal,
+            msg: e.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::UnknownJob { id } => write!(f, "Job {} doesn't exist.", id),
+            JobError::Signal { signal, id, msg } => write!(f, "Signal {} couldn't be sent to job {} : {}", signal, id, msg),
+        }
+    }
+}
+
+impl Fail for JobError {}
+
+
+
+impl JobTable {
+
+    /// Creates a new empty job table.
+    pub fn new() -> JobTable {
+        JobTable {
+            jobs: Vec::new(),
+            next: 1,
+        }
+    }
+
+
+    /// Adds a job running the given pipeline, started by the given command.
+    /// Returns the id of the job.
+    pub fn add(&mut self, command: String, pipeline: ProcessPipeline) -> i32 {
+        let id = self.next;
+        self.next += 1;
+
+        self.jobs.push(Job {
+            id,
+            command,
+            pipeline,
+        });
+
+        id
+    }
+    /// Removes the given job from the table, to wait for it.
+    pub fn remove(&mut self, id: i32) -> Result<Job, Error> {
+        match self.jobs.iter().position(|j| j.id == id) {
+            Some(i) => Ok(self.jobs.remove(i)),
+            None => Err(JobError::new_unknown_job(id)),
+        }
+    }
+    /// Removes all the jobs from the table, in the order they were started.
+    pub fn remove_all(&mut self) -> Vec<Job> {
+        self.jobs.drain(..).collect()
+    }
+
+
+    /// Gets the given job.
+    pub fn get_mut(&mut self, id: i32) -> Result<&mut Job, Error> {
+        match self.jobs.iter_mut().find(|j| j.id == id) {
+            Some(j) => Ok(j),
+            None => Err(JobError::new_unknown_job(id)),
+        }
+    }
+    /// Iterates over the jobs, in the order they were started.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Job> {
+        self.jobs.iter_mut()
+    }
+    /// Checks if the table has no jobs.
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+}
+
+impl Default for JobTable {
+    fn default() -> JobTable {
+        JobTable::new()
+    }
+}
+
+impl Job {
+
+    /// Gets the id of the job.
+    pub fn get_id(&self) -> i32 {
+        self.id
+    }
+    /// Gets the command which started the job.
+    pub fn get_command(&self) -> &String {
+        &self.command
+    }
+
+
+    /// Gets the state of the job, without waiting for it.
+    pub fn poll(&mut self) -> Result<JobState, Error> {
+        Ok(match self.pipeline.try_wait()? {
+            Some(codes) => JobState::Done(codes),
+            None => JobState::Running,
+        })
+    }
+    /// Waits for the end of the job, and gets its exit codes. A failure of
+    /// its processes is an error, as for the pipelines run in the foreground.
+    pub fn wait(mut self) -> Result<Vec<i32>, Error> {
+        self.pipeline.wait()
+    }
+    /// Sends a signal to the processes of the job.
+    pub fn signal(&self, sig: i32) -> Result<(), Error> {
+        self.pipeline.signal(sig).map_err(|e| JobError::new_signal(self.id, sig, &e))
+    }
+
+}
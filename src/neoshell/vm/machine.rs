@@ -0,0 +1,1131 @@
+#![deny(missing_docs)]
+//! Virtual machine compiling and executing Neoshell code.
+
+use std::cell::{RefCell, RefMut};
+use std::fmt;
+
+use failure::*;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::tools::*;
+
+use super::command::*;
+use super::external::*;
+use super::jobs::*;
+use super::process::*;
+use super::redirection::*;
+use super::scope::*;
+use super::registery::*;
+use super::signals::*;
+use super::value::*;
+use super::variable::*;
+
+
+/// Virtual machine executing Neoshell code.
+///
+/// The VM keeps track of the commands being executed, to enforce the calling
+/// rules between execution times : compile time commands may only call other
+/// compile time commands, without recursion, while macros and runtime commands
+/// may call macros and runtime commands.
+///
+/// The VM also owns the state of the programs it runs : the jobs running in
+/// the background, and the signals forwarded to its programs, which interrupt
+/// the running script (see `SignalForwarder`).
+pub struct VM {
+    registery: CommandRegistery,
+    recursion_limit: usize,
+    calls: RefCell<Vec<Call>>,
+    bound: RefCell<Vec<(usize, Value)>>,
+    returning: RefCell<Option<Value>>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    streams: RefCell<StandardStreams>,
+    jobs: RefCell<JobTable>,
+    signals: SignalForwarder,
+    error_span: RefCell<Option<AstSpan>>,
+}
+
+/// Command being executed by the VM.
+struct Call {
+    /// Overload being executed.
+    res: Resolution,
+    /// Name of the command.
+    name: String,
+    /// Values bound by the instantiated command it was invoked from, with the
+    /// index of their placeholder in the arguments.
+    bound: Vec<(usize, Value)>,
+}
+
+/// Errors related to the compilation and the execution of commands.
+#[derive(Debug)]
+pub enum VMError {
+    /// Macro expansion got deeper than the recursion limit.
+    RecursionLimit {
+        /// Recursion limit of the VM.
+        limit: usize
+    },
+    /// A macro expanded into a compile time command.
+    CompileTimeInMacro {
+        /// Name of the compile time command.
+        name: String
+    },
+    /// A macro used as a pipe stage didn't expand into exactly one command.
+    InvalidPipeStage {
+        /// Name of the macro.
+        name: String
+    },
+    /// A command called a command of a forbidden execution time.
+    ForbiddenCall {
+        /// Name of the calling command.
+        caller: String,
+        /// Execution time of the calling command.
+        caller_time: AstTime,
+        /// Name of the called command.
+        callee: String,
+        /// Execution time of the called command.
+        callee_time: AstTime,
+    },
+    /// A compile time command called itself.
+    RecursiveCompileTime {
+        /// Name of the command.
+        name: String
+    },
+    /// Type checking found errors, reported as diagnostics.
+    TypeErrors {
+        /// Number of errors.
+        count: usize
+    },
+    /// A command bound its arguments without being described.
+    Undescribed {
+        /// Name of the command.
+        name: String
+    },
+    /// A macro with redirections didn't expand into exactly one command.
+    RedirectedMacro {
+        /// Name of the macro.
+        name: String
+    },
+    /// A command piped into another one didn't generate a stream.
+    NotAGenerator {
+        /// Name of the command.
+        name: String,
+        /// Name of the command it is piped into.
+        consumer: String,
+    },
+    /// A compile time command or a macro was named by a variable, a block or
+    /// a placeholder.
+    UnnamedCommand {
+        /// Name given to the command.
+        name: String,
+        /// Execution time of the command.
+        time: AstTime,
+    },
+    /// A command given as a compile time command isn't one.
+    NotCompileTime {
+        /// Name of the command.
+        name: String
+    },
+    /// The execution was interrupted by a signal forwarded by the shell.
+    Interrupted {
+        /// Number of the signal.
+        signal: i32
+    },
+}
+
+
+
+impl VMError {
+    /// Creates a new `RecursionLimit` error.
+    pub fn new_recursion_limit(limit: usize) -> Error {
+        Error::from(VMError::RecursionLimit {
+            limit,
+        })
+    }
+    /// Creates a new `CompileTimeInMacro` error.
+    pub fn new_compile_time_in_macro(name: &AstName) -> Error {
+        Error::from(VMError::CompileTimeInMacro {
+            name: name.to_string(),
+        })
+    }
+    /// Creates a new `InvalidPipeStage` error.
+    pub fn new_invalid_pipe_stage(name: &AstName) -> Error {
+        Error::from(VMError::InvalidPipeStage {
+            name: name.to_string(),
+        })
+    }
+    /// Creates a new `RedirectedMacro` error.
+    pub fn new_redirected_macro(name: &AstName) -> Error {
+        Error::from(VMError::RedirectedMacro {
+            name: name.to_string(),
+        })
+    }
+    /// Creates a new `UnnamedCommand` error.
+    pub fn new_unnamed_command(name: &AstName, time: AstTime) -> Error {
+        Error::from(VMError::UnnamedCommand {
+            name: name.to_string(),
+            time,
+        })
+    }
+    /// Creates a new `NotCompileTime` error.
+    pub fn new_not_compile_time(name: &AstName) -> Error {
+        Error::from(VMError::NotCompileTime {
+            name: name.to_string(),
+        })
+    }
+    /// Creates a new `Interrupted` error.
+    pub fn new_interrupted(signal: i32) -> Error {
+        Error::from(VMError::Interrupted {
+            signal,
+        })
+    }
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VMError::RecursionLimit { limit } => write!(f, "Macro expansion reached the recursion limit ({}).", limit),
+            VMError::CompileTimeInMacro { name } => write!(f, "Macro expansion produced the compile time command '{}'.", name),
+            VMError::InvalidPipeStage { name } => write!(f, "Macro '{}' is used in a pipe and must expand into exactly one command.", name),
+            VMError::ForbiddenCall { caller_time, caller, callee_time, callee } => write!(f, "The {} command '{}' can't call the {} command '{}'.", caller_time, caller, callee_time, callee),
+            VMError::RecursiveCompileTime { name } => write!(f, "The compile time command '{}' can't call itself.", name),
+            VMError::TypeErrors { count } => write!(f, "Type checking failed with {} error(s).", count),
+            VMError::Undescribed { name } => write!(f, "Command '{}' has no descriptor to bind its arguments with.", name),
+            VMError::RedirectedMacro { name } => write!(f, "Macro '{}' has redirections and must expand into exactly one command.", name),
+            VMError::NotAGenerator { name, consumer } => write!(f, "Command '{}' is piped into '{}' but didn't generate a stream.", name, consumer),
+            VMError::UnnamedCommand { name, time } => write!(f, "'{}' can't be used as the name of a {} command.", name, time),
+            VMError::NotCompileTime { name } => write!(f, "'{}' is not a compile time command.", name),
+            VMError::Interrupted { signal } => write!(f, "Interrupted by signal {}.", signal),
+        }
+    }
+}
+
+impl Fail for VMError {}
+
+
+
+impl VM {
+
+    /// Creates a new VM object.
+    pub fn new() -> VM {
+        VM {
+            registery: CommandRegistery::new(),
+            recursion_limit: 256,
+            calls: RefCell::new(Vec::new()),
+            bound: RefCell::new(Vec::new()),
+            returning: RefCell::new(None),
+            diagnostics: RefCell::new(Vec::new()),
+            streams: RefCell::new(StandardStreams::inherit()),
+            jobs: RefCell::new(JobTable::new()),
+            signals: SignalForwarder::new(),
+            error_span: RefCell::new(None),
+        }
+    }
+
+
+    /// Gets the command registery;
+    pub fn get_registery_mut(&mut self) -> &mut CommandRegistery {
+        &mut self.registery
+    }
+    /// Gets the command registery;
+    pub fn get_registery(&self) -> &CommandRegistery {
+        &self.registery
+    }
+
+
+    /// Sets the maximum depth of macro expansions.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+    /// Gets the maximum depth of macro expansions.
+    pub fn get_recursion_limit(&self) -> usize {
+        self.recursion_limit
+    }
+
+
+    /// Gets the execution time of the command being executed, if any.
+    pub fn get_current_time(&self) -> Option<AstTime> {
+        self.calls.borrow().last().map(|c| c.res.time.clone())
+    }
+    /// Marks the start of the execution of a command, checking that the
+    /// command being executed is allowed to call it. The command receives the
+    /// values bound by the instantiated command being invoked, if any (see
+    /// `invoke`).
+    fn enter(&self, res: Resolution, name: &String) -> Result<(), Error> {
+        self.check_call(&res.time, name)?;
+        let bound = self.bound.replace(Vec::new());
+        self.calls.borrow_mut().push(Call {
+            res,
+            name: name.clone(),
+            bound,
+        });
+        Ok(())
+    }
+    /// Checks that the command being executed is allowed to call the given
+    /// command.
+    fn check_call(&self, time: &AstTime, name: &String) -> Result<(), Error> {
+        let calls = self.calls.borrow();
+
+        if let Some(Call { res: caller, name: caller_name, .. }) = calls.last() {
+            let caller_time = &caller.time;
+            let allowed = match caller_time {
+                AstTime::CompileTime => *time == AstTime::CompileTime,
+                AstTime::Macro | AstTime::Runtime => *time != AstTime::CompileTime,
+            };
+
+            if !allowed {
+                return Err(Error::from(VMError::ForbiddenCall {
+                    caller: caller_name.clone(),
+                    caller_time: caller_time.clone(),
+                    callee: name.clone(),
+                    callee_time: time.clone(),
+                }));
+            }
+        }
+
+        if *time == AstTime::CompileTime && calls.iter().any(|c| c.res.time == AstTime::CompileTime && c.name == *name) {
+            return Err(Error::from(VMError::RecursiveCompileTime {
+                name: name.clone(),
+            }));
+        }
+
+        Ok(())
+    }
+    /// Marks the end of the execution of the last entered command.
+    fn leave(&self) {
+        self.calls.borrow_mut().pop();
+    }
+
+
+
+    /// Compiles the given commands.
+    ///
+    /// All the compile time commands are executed first, then the macros are
+    /// expanded in place until none remains. The resulting commands are
+    /// checked, and can be executed with `run`. If the compilation fails, the
+    /// location of the failing command is kept until taken with
+    /// `take_error_span`.
+    ///
+    /// Compilation can be nested in a compile time command (e.g. to compile
+    /// the body of a defined command), in which case the commands it executes
+    /// aren't considered called by the compile time command.
+    pub fn compile(&mut self, scp: &mut Scope, cmds: Vec<AstCommand>) -> Result<Vec<AstCommand>, Error> {
+        self.error_span.replace(None);
+        let calls = self.calls.replace(Vec::new());
+        let r = self.compile_unit(scp, cmds);
+
+        self.calls.replace(calls);
+        r
+    }
+    /// Runs compiled commands, returning the result of the last one, or the
+    /// value given to `return`. If the execution fails, the location of the
+    /// failing command is kept until taken with `take_error_span`.
+    pub fn run(&self, scp: &mut Scope, cmds: &[AstCommand]) -> Result<Value, Error> {
+        self.error_span.replace(None);
+        let r = self.execute_block(scp, cmds);
+
+        match self.take_return() {
+            Some(v) => r.map(|_| v),
+            None => r,
+        }
+    }
+    /// Runs compiled commands as a script, returning the value given to a
+    /// top level `return`, if any. The result of the last command is
+    /// ignored. Errors are located as with `run`.
+    pub fn run_script(&self, scp: &mut Scope, cmds: &[AstCommand]) -> Result<Option<Value>, Error> {
+        self.error_span.replace(None);
+        let r = self.execute_block(scp, cmds);
+        let v = self.take_return();
+
+        r.map(|_| v)
+    }
+
+
+    /// Makes the running block return the given value. Blocks stop being
+    /// executed until the value is taken back with `take_return`.
+    pub fn set_return(&self, val: Value) {
+        self.returning.replace(Some(val));
+    }
+    /// Takes the value given to `return`, if any.
+    pub fn take_return(&self) -> Option<Value> {
+        self.returning.replace(None)
+    }
+    /// Checks if a block is returning.
+    pub fn is_returning(&self) -> bool {
+        self.returning.borrow().is_some()
+    }
+
+
+    /// Gets the standard streams of the command being executed.
+    pub fn get_streams(&self) -> StandardStreams {
+        self.streams.borrow().clone()
+    }
+    /// Gets the output stream of the command being executed, where the
+    /// builtin commands write their output.
+    pub fn stdout(&self) -> OutputStream {
+        self.streams.borrow().stdout.clone()
+    }
+    /// Gets the signal state of the programs run by the VM, which can be
+    /// installed to forward the signals received by the process.
+    pub fn get_signals(&self) -> SignalForwarder {
+        self.signals.clone()
+    }
+    /// Gets the jobs running in the background.
+    pub fn get_jobs(&self) -> RefMut<'_, JobTable> {
+        self.jobs.borrow_mut()
+    }
+
+
+    /// Reports a diagnostic, kept until taken with `take_diagnostics`.
+    pub fn report(&self, diag: Diagnostic) {
+        self.diagnostics.borrow_mut().push(diag);
+    }
+    /// Takes the diagnostics reported so far.
+    pub fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.replace(Vec::new())
+    }
+    /// Takes the location of the innermost command whose compilation or
+    /// execution failed, if known.
+    pub fn take_error_span(&self) -> Option<AstSpan> {
+        self.error_span.replace(None)
+    }
+    /// Keeps the location of a failing command, unless a command inside it
+    /// already failed.
+    fn locate(&self, e: Error, span: Option<AstSpan>) -> Error {
+        let mut s = self.error_span.borrow_mut();
+        if s.is_none() {
+            *s = span;
+        }
+        e
+    }
+    /// Binds the arguments of the given command to the descriptor of the
+    /// overload being executed. Used by described commands, see
+    /// `ArgumentBinder`.
+    pub fn bind_arguments(&self, scp: &Scope, cmd: &AstCommand) -> Result<BoundArguments, Error> {
+        let calls = self.calls.borrow();
+        let Call { res, name, bound } = match calls.last() {
+            Some(c) => c,
+            None => return Err(Error::from(VMError::Undescribed {
+                name: cmd.name.to_string(),
+            })),
+        };
+
+        match self.registery.get_descriptor(name, res) {
+            Some(d) => ArgumentBinder::new(d).bind_values(scp, &cmd.arguments, bound),
+            None => Err(Error::from(VMError::Undescribed {
+                name: name.clone(),
+            })),
+        }
+    }
+
+
+    /// Compiles commands, see `compile`.
+    fn compile_unit(&mut self, scp: &mut Scope, cmds: Vec<AstCommand>) -> Result<Vec<AstCommand>, Error> {
+        let cmds = self.compile_ct_commands(scp, cmds)?;
+        let cmds = self.expand_macros(scp, cmds, 0)?;
+
+        let mut checker = DeclarationChecker::new();
+        if let Err(e) = checker.check(scp, &cmds) {
+            self.error_span.replace(checker.get_error_span());
+            return Err(e);
+        }
+
+        let mut diags = Vec::new();
+        let errors = TypeChecker::new(&self.registery).check(scp, &cmds, &mut diags);
+
+        self.diagnostics.borrow_mut().extend(diags);
+
+        if errors > 0 {
+            return Err(Error::from(VMError::TypeErrors {
+                count: errors,
+            }));
+        }
+
+        Ok(cmds)
+    }
+
+
+    /// Resolves the overload to call for a command, from its arguments.
+    ///
+    /// Commands marked as compile time or macro commands are only looked up
+    /// in their table, the other ones are looked up in all the tables.
+    pub fn resolve(&self, scp: &Scope, cmd: &AstCommand) -> Result<Resolution, Error> {
+        let times = match cmd.time {
+            AstTime::CompileTime => vec![AstTime::CompileTime],
+            AstTime::Macro => vec![AstTime::Macro],
+            AstTime::Runtime => vec![AstTime::Runtime, AstTime::CompileTime, AstTime::Macro],
+        };
+
+        self.resolve_in(scp, cmd, &times)
+    }
+    /// Resolves the overload to call for a command among the given tables.
+    fn resolve_in(&self, scp: &Scope, cmd: &AstCommand, times: &[AstTime]) -> Result<Resolution, Error> {
+        match &cmd.name {
+            AstName::Name(n) => self.registery.resolve(scp, n, times, &cmd.arguments),
+            _ => Ok(Resolution {
+                time: AstTime::Runtime,
+                index: 0,
+            }),
+        }
+    }
+    /// Finds the program run by a command which isn't registered, in the
+    /// `PATH` of the given scope.
+    fn find_external(&self, scp: &Scope, cmd: &AstCommand) -> Option<ExternalCommand> {
+        match &cmd.name {
+            AstName::Name(n) if !self.registery.has_command(n) && !self.registery.has_macro(n) => {
+                ExternalCommand::find(n, &scp.get_environment_variable(&"PATH".to_owned())?)
+            },
+            _ => None,
+        }
+    }
+    /// Gets the type of the stream consumed by a command, if it is described
+    /// as a consumer.
+    fn input_type(&self, scp: &Scope, cmd: &AstCommand) -> Option<Type> {
+        let name = match &cmd.name {
+            AstName::Name(n) => n,
+            _ => return None,
+        };
+
+        self.resolve_in(scp, cmd, &[AstTime::Runtime]).ok()
+            .and_then(|r| self.registery.get_descriptor(name, &r))
+            .and_then(|d| d.input.as_ref())
+            .and_then(|t| Type::from_name(t))
+    }
+    /// Gets the execution time of a command. Unknown commands are runtime
+    /// commands, and are reported when executed. The choice between overloads
+    /// of the same table is left to the execution, as the types of the
+    /// variables may not be known yet.
+    fn resolve_time(&self, scp: &Scope, cmd: &AstCommand) -> Result<AstTime, Error> {
+        let times = match cmd.time {
+            AstTime::CompileTime => return Ok(AstTime::CompileTime),
+            AstTime::Macro => return Ok(AstTime::Macro),
+            AstTime::Runtime => [AstTime::Runtime, AstTime::CompileTime, AstTime::Macro],
+        };
+        let name = match &cmd.name {
+            AstName::Name(n) => n,
+            _ => return Ok(AstTime::Runtime),
+        };
+
+        match self.registery.best_matches(scp, name, &times, &cmd.arguments) {
+            Ok(best) => {
+                if best.iter().all(|r| r.time == best[0].time) {
+                    Ok(best[0].time.clone())
+                } else {
+                    self.registery.resolve(scp, name, &times, &cmd.arguments).map(|r| r.time)
+                }
+            },
+            Err(e) => match e.downcast_ref::<RegisteryError>() {
+                Some(RegisteryError::NotFound { .. }) => Ok(AstTime::Runtime),
+                _ => Err(e),
+            },
+        }
+    }
+
+
+    /// Executes the compile time commands, and removes them from the AST.
+    fn compile_ct_commands(&mut self, scp: &mut Scope, cmds: Vec<AstCommand>) -> Result<Vec<AstCommand>, Error> {
+        let mut r = Vec::with_capacity(cmds.len());
+
+        for c in cmds {
+            let span = c.span;
+
+            match self.compile_ct_command(scp, c) {
+                Ok(Some(c)) => r.push(c),
+                Ok(None) => {},
+                Err(e) => return Err(self.locate(e, span)),
+            }
+        }
+
+        Ok(r)
+    }
+    /// Executes a compile time command, or the compile time commands found in
+    /// the arguments and the pipe of a command. Returns the command left in
+    /// the AST, if any.
+    fn compile_ct_command(&mut self, scp: &mut Scope, mut c: AstCommand) -> Result<Option<AstCommand>, Error> {
+        let registery = &self.registery;
+        let (args, redirections) = match &c.name {
+            AstName::Name(n) => split_redirections(c.arguments, |a| registery.declares_argument(n, a)),
+            _ => split_redirections(c.arguments, |_| false),
+        };
+        c.arguments = args;
+        c.redirections.extend(redirections);
+        c.time = self.resolve_time(scp, &c)?;
+
+        if c.time == AstTime::CompileTime {
+            return match self.execute_ct_command(scp, &c) {
+                Some(e) => Err(e),
+                None => Ok(None),
+            };
+        }
+
+        if let AstName::Block(AstBlock::Evaluated(b)) = c.name {
+            c.name = AstName::Block(AstBlock::Evaluated(self.compile_ct_commands(scp, b)?));
+        }
+        c.arguments = self.compile_ct_arguments(scp, c.arguments)?;
+
+        if let Some(p) = c.pipe.take() {
+            let mut p = self.compile_ct_commands(scp, vec![*p])?;
+            c.pipe = p.pop().map(Box::new);
+        }
+
+        Ok(Some(c))
+    }
+    /// Executes the compile time commands found in the blocks of the given
+    /// arguments.
+    fn compile_ct_arguments(&mut self, scp: &mut Scope, args: Vec<AstArgument>) -> Result<Vec<AstArgument>, Error> {
+        let mut r = Vec::with_capacity(args.len());
+
+        for a in args {
+            r.push(match a {
+                AstArgument::Block(AstBlock::Evaluated(c)) => AstArgument::Block(AstBlock::Evaluated(self.compile_ct_commands(scp, c)?)),
+                AstArgument::Block(AstBlock::Argument(c)) => AstArgument::Block(AstBlock::Argument(self.compile_ct_commands(scp, c)?)),
+                AstArgument::Block(AstBlock::Inferred(c)) => AstArgument::Block(AstBlock::Inferred(self.compile_ct_commands(scp, c)?)),
+                _ => a,
+            });
+        }
+
+        Ok(r)
+    }
+
+
+    /// Expands the macros, splicing their result in place, until none remains
+    /// or the recursion limit is reached.
+    fn expand_macros(&self, scp: &mut Scope, cmds: Vec<AstCommand>, depth: usize) -> Result<Vec<AstCommand>, Error> {
+        let mut r = Vec::with_capacity(cmds.len());
+
+        for c in cmds {
+            let span = c.span;
+
+            match self.expand_macro(scp, c, depth) {
+                Ok(e) => r.extend(e),
+                Err(e) => return Err(self.locate(e, span)),
+            }
+        }
+
+        Ok(r)
+    }
+    /// Expands a macro, or the macros found in the arguments and the pipe of
+    /// a command. Returns the resulting commands.
+    fn expand_macro(&self, scp: &mut Scope, mut c: AstCommand, depth: usize) -> Result<Vec<AstCommand>, Error> {
+        c.time = self.resolve_time(scp, &c)?;
+
+        match c.time {
+            AstTime::CompileTime => Err(VMError::new_compile_time_in_macro(&c.name)),
+            AstTime::Macro => {
+                if depth >= self.recursion_limit {
+                    return Err(VMError::new_recursion_limit(self.recursion_limit));
+                }
+
+                let e = self.execute_macro(scp, &c)?;
+                let mut e = self.expand_macros(scp, e, depth + 1)?;
+
+                if !c.redirections.is_empty() {
+                    if e.len() != 1 {
+                        return Err(VMError::new_redirected_macro(&c.name));
+                    }
+                    e[0].redirections.append(&mut c.redirections);
+                }
+                if let Some(p) = c.pipe.take() {
+                    if e.len() != 1 {
+                        return Err(VMError::new_invalid_pipe_stage(&c.name));
+                    }
+                    e[0].pipe = Some(p);
+                    e = self.expand_macros(scp, e, depth + 1)?;
+                }
+
+                Ok(e)
+            },
+            AstTime::Runtime => {
+                if let AstName::Block(AstBlock::Evaluated(b)) = c.name {
+                    c.name = AstName::Block(AstBlock::Evaluated(self.expand_macros(scp, b, depth)?));
+                }
+                c.arguments = self.expand_macros_in_arguments(scp, c.arguments, depth)?;
+
+                if let Some(p) = c.pipe.take() {
+                    let name = p.name.clone();
+                    let mut p = self.expand_macros(scp, vec![*p], depth)?;
+
+                    if p.len() != 1 {
+                        return Err(VMError::new_invalid_pipe_stage(&name));
+                    }
+                    c.pipe = p.pop().map(Box::new);
+                }
+
+                Ok(vec![c])
+            },
+        }
+    }
+    /// Expands the macros found in the blocks of the given arguments.
+    fn expand_macros_in_arguments(&self, scp: &mut Scope, args: Vec<AstArgument>, depth: usize) -> Result<Vec<AstArgument>, Error> {
+        let mut r = Vec::with_capacity(args.len());
+
+        for a in args {
+            r.push(match a {
+                AstArgument::Block(AstBlock::Evaluated(c)) => AstArgument::Block(AstBlock::Evaluated(self.expand_macros(scp, c, depth)?)),
+                AstArgument::Block(AstBlock::Argument(c)) => AstArgument::Block(AstBlock::Argument(self.expand_macros(scp, c, depth)?)),
+                AstArgument::Block(AstBlock::Inferred(c)) => AstArgument::Block(AstBlock::Inferred(self.expand_macros(scp, c, depth)?)),
+                _ => a,
+            });
+        }
+
+        Ok(r)
+    }
+
+
+
+    /// Executes a compile time command. Other commands give an error.
+    pub fn execute_ct_command(&mut self, scp: &mut Scope, cmd: &AstCommand) -> Option<Error> {
+        if cmd.time != AstTime::CompileTime {
+            return Some(self.locate(VMError::new_not_compile_time(&cmd.name), cmd.span));
+        }
+
+        let cmd_name: &String = match &cmd.name {
+            AstName::Name(n) => n,
+            n => return Some(self.locate(VMError::new_unnamed_command(n, AstTime::CompileTime), cmd.span)),
+        };
+
+        if !self.registery.has_ct_command(cmd_name) {
+            let other = if self.registery.has_command(cmd_name) {
+                Some(AstTime::Runtime)
+            } else if self.registery.has_macro(cmd_name) {
+                Some(AstTime::Macro)
+            } else {
+                None
+            };
+
+            if let Some(Err(e)) = other.map(|t| self.check_call(&t, cmd_name)) {
+                return Some(e);
+            }
+        }
+
+        let res = match self.resolve_in(scp, cmd, &[AstTime::CompileTime]) {
+            Ok(r) => r,
+            Err(e) => return Some(e),
+        };
+        let index = res.index;
+
+        if let Err(e) = self.enter(res, cmd_name) {
+            return Some(e);
+        }
+
+        let r = match self.registery.take_ct_command(cmd_name, index) {
+            None => Some(Error::from(VMError::RecursiveCompileTime {
+                name: cmd_name.clone(),
+            })),
+            Some(mut c) => {
+                let r = c.execute(self, scp, cmd);
+                self.registery.restore_ct_command(cmd_name, index, c);
+                r
+            }
+        };
+
+        self.leave();
+        r
+    }
+    /// Executes a macro command.
+    pub fn execute_macro(&self, scp: &mut Scope, cmd: &AstCommand) -> Result<Vec<AstCommand>, Error> {
+        let cmd_name: &String = match &cmd.name {
+            AstName::Name(n) => n,
+            n => return Err(self.locate(VMError::new_unnamed_command(n, AstTime::Macro), cmd.span)),
+        };
+
+        let r = self.resolve_in(scp, cmd, &[AstTime::Macro])?;
+
+        let c = match self.registery.get_macro(cmd_name, r.index) {
+            None => return Err(RegisteryError::new_not_found(cmd_name)),
+            Some(c) => c,
+        };
+
+        self.enter(r, cmd_name)?;
+        let r = c.execute(self, scp, cmd);
+        self.leave();
+        r
+    }
+    /// Executes a list of commands, returning the result of the last one. The
+    /// execution stops early when a command returns, or with an error when a
+    /// signal is forwarded by the shell (see `SignalForwarder`).
+    pub fn execute_block(&self, scp: &mut Scope, cmds: &[AstCommand]) -> Result<Value, Error> {
+        let mut r = Value::Void;
+
+        for c in cmds {
+            self.signals.check()?;
+
+            r = match self.execute_command(scp, c) {
+                Ok(v) => v,
+                Err(e) => return Err(self.locate(e, c.span)),
+            };
+
+            if self.is_returning() {
+                break;
+            }
+        }
+
+        Ok(r)
+    }
+    /// Executes a block evaluated into a value. If the last command runs
+    /// programs, their output is captured as the value of the block instead
+    /// of their exit codes.
+    fn execute_captured(&self, scp: &mut Scope, cmds: &[AstCommand]) -> Result<Value, Error> {
+        let (last, cmds) = match cmds.split_last() {
+            Some(c) => c,
+            None => return Ok(Value::Void),
+        };
+
+        let r = self.execute_block(scp, cmds)?;
+        if self.is_returning() {
+            return Ok(r);
+        }
+
+        self.execute_pipeline(scp, last, None, true)
+    }
+    /// Evaluates an argument into a value.
+    ///
+    /// Variables are looked up through the given scope and its parents.
+    /// Evaluated blocks are executed in a sub-scope, capturing the output of
+    /// the programs run by their last command, and argument blocks are turned
+    /// into closures capturing the given scope.
+    pub fn evaluate(&self, scp: &mut Scope, arg: &AstArgument) -> Result<Value, Error> {
+        match arg {
+            AstArgument::Name(AstName::Variable(n)) => {
+                match scp.get_variable(n) {
+                    Some(v) => Ok(v.to_value()),
+                    None => Err(ScopeError::new_undeclared_variable(n)),
+                }
+            },
+            AstArgument::Block(AstBlock::Evaluated(c)) | AstArgument::Block(AstBlock::Inferred(c)) => {
+                self.execute_captured(&mut Scope::extends(scp), c)
+            },
+            AstArgument::Block(AstBlock::Argument(c)) => {
+                Ok(Value::Closure(Closure::new(c.clone(), scp)))
+            },
+            _ => Value::from_ast(arg),
+        }
+    }
+    /// Invokes an instantiated command with the given arguments, which are
+    /// evaluated in the given scope.
+    ///
+    /// Registered commands receive the bound arguments followed by the given
+    /// ones, the bound values being given as placeholders replaced when the
+    /// command binds its arguments (see `bind_arguments`). Blocks are executed
+    /// in a sub-scope of their captured scope, and receive all the arguments
+    /// as an array in `$args`.
+    pub fn invoke(&self, scp: &mut Scope, inst: &InstancedCommand, args: &[AstArgument]) -> Result<Value, Error> {
+        match &inst.target {
+            CommandTarget::Registered(n) => {
+                let mut cmd = AstCommand::new(AstTime::Runtime, AstName::Name(n.clone()));
+                let mut bound = Vec::new();
+
+                for (i, a) in inst.arguments.iter().enumerate() {
+                    match a {
+                        InstanceArgument::Ast(a) => cmd.arguments.push(a.clone()),
+                        InstanceArgument::Value(v) => {
+                            cmd.arguments.push(AstArgument::Name(AstName::Placeholder));
+                            bound.push((i, v.clone()));
+                        },
+                    }
+                }
+                cmd.arguments.extend(args.iter().cloned());
+
+                self.bound.replace(bound);
+                let r = self.execute_command(scp, &cmd);
+
+                self.bound.replace(Vec::new());
+                r
+            },
+            CommandTarget::Closure(c) => {
+                let mut values = Vec::with_capacity(inst.arguments.len() + args.len());
+                for a in &inst.arguments {
+                    values.push(match a {
+                        InstanceArgument::Ast(a) => self.evaluate(scp, a)?,
+                        InstanceArgument::Value(v) => v.clone(),
+                    });
+                }
+                for a in args {
+                    values.push(self.evaluate(scp, a)?);
+                }
+
+                self.call(c, values)
+            },
+        }
+    }
+    /// Calls a closure with the given arguments, given as an array in
+    /// `$args`.
+    pub fn call(&self, c: &Closure, args: Vec<Value>) -> Result<Value, Error> {
+        let scope = c.get_scope().ok_or_else(ScopeError::new_released_scope)?;
+        let mut sub = Scope::extends(&scope);
+        sub.declare("args", Variable::Value(Value::Array(Type::Generic, args)));
+
+        let r = self.execute_block(&mut sub, &c.body)?;
+        Ok(self.take_return().unwrap_or(r))
+    }
+    /// Executes a command. Macros are expanded and executed in place.
+    ///
+    /// Piped commands are executed from the generator to the last consumer,
+    /// each consumer receiving the stream generated by the previous command.
+    /// The streams are lazy : items are generated only when a consumer pulls
+    /// them, so a consumer stopping early stops the whole pipeline.
+    pub fn execute_command(&self, scp: &mut Scope, cmd: &AstCommand) -> Result<Value, Error> {
+        self.execute_pipeline(scp, cmd, None, false)
+    }
+    /// Executes a command receiving the given input stream, and the commands
+    /// it is piped into. Unknown commands run the program with the same name
+    /// found in `PATH`, if any. If the pipeline ends with programs, their
+    /// output can be captured.
+    fn execute_pipeline(&self, scp: &mut Scope, cmd: &AstCommand, input: Option<ValueStream>, capture: bool) -> Result<Value, Error> {
+        if let Some(e) = self.find_external(scp, cmd) {
+            return self.execute_processes(scp, cmd, e, input, capture);
+        }
+
+        let out = match cmd.redirections.is_empty() {
+            true => self.execute_stage(scp, cmd, input)?,
+            false => self.execute_redirected(scp, cmd, input)?,
+        };
+
+        let p = match &cmd.pipe {
+            Some(p) => p,
+            None => return Ok(out),
+        };
+
+        match out.into_stream() {
+            Some((_, s)) => self.execute_pipeline(scp, p, Some(self.signals.interruptible(s)), capture),
+            None => Err(Error::from(VMError::NotAGenerator {
+                name: cmd.name.to_string(),
+                consumer: p.name.to_string(),
+            })),
+        }
+    }
+    /// Executes the consecutive programs of a pipeline starting with the given
+    /// command, connected by OS pipes, and the commands they are piped into.
+    /// The output of the programs is read as lines, or as bytes if it is
+    /// piped into a command consuming `u1` values.
+    ///
+    /// Programs running in the background must make the whole pipeline, which
+    /// returns the id of their job.
+    fn execute_processes(&self, scp: &mut Scope, cmd: &AstCommand, first: ExternalCommand, input: Option<ValueStream>, capture: bool) -> Result<Value, Error> {
+        let mut stages = Vec::new();
+        let mut stage = cmd;
+        let mut e = first;
+
+        loop {
+            let args = e.arguments(self, scp, stage)?;
+            stages.push((e, args));
+
+            match stage.pipe.as_ref().map(|p| (p, self.find_external(scp, p))) {
+                Some((p, Some(next))) => {
+                    stage = p;
+                    e = next;
+                },
+                _ => break,
+            }
+        }
+
+        if stages.iter().any(|(_, args)| args.background) {
+            if let Some(p) = &stage.pipe {
+                return Err(ExternalError::new_piped_background(stages.last().unwrap().0.get_name(), &p.name));
+            }
+            if input.is_some() {
+                return Err(ExternalError::new_fed_background(stages[0].0.get_name()));
+            }
+
+            let pipeline = ProcessPipeline::run_background(stages, &self.get_streams(), &self.signals)?;
+            return Ok(Value::I4(self.jobs.borrow_mut().add(cmd.to_string(), pipeline)));
+        }
+
+        let p = match &stage.pipe {
+            Some(p) => p,
+            None => {
+                let output = match stages.last() {
+                    Some((_, args)) if capture => ProcessOutput::Capture(args.capture),
+                    _ => ProcessOutput::Inherit,
+                };
+
+                return ProcessPipeline::run(stages, &self.get_streams(), &self.signals, input, output);
+            },
+        };
+        let output = match self.input_type(scp, p) {
+            Some(Type::U1) => ProcessOutput::Bytes,
+            _ => ProcessOutput::Lines,
+        };
+
+        match ProcessPipeline::run(stages, &self.get_streams(), &self.signals, input, output)? {
+            Value::Stream(_, s) => self.execute_pipeline(scp, p, Some(self.signals.interruptible(s)), capture),
+            v => Ok(v),
+        }
+    }
+    /// Executes a single command of a pipeline with its standard streams
+    /// redirected, for the commands it executes too. A redirected input is
+    /// given as input stream if the command consumes one.
+    fn execute_redirected(&self, scp: &mut Scope, cmd: &AstCommand, input: Option<ValueStream>) -> Result<Value, Error> {
+        let r = Redirections::open(self, scp, &cmd.redirections)?;
+        let input = match self.input_type(scp, cmd) {
+            Some(t) => r.input_stream(t == Type::U1)?.or(input),
+            None => input,
+        };
+
+        let streams = r.apply(&self.streams.borrow());
+        let previous = self.streams.replace(streams);
+        let out = self.execute_stage(scp, cmd, input);
+
+        self.streams.replace(previous);
+        out
+    }
+    /// Executes a single command of a pipeline, ignoring the command it is
+    /// piped into.
+    fn execute_stage(&self, scp: &mut Scope, cmd: &AstCommand, input: Option<ValueStream>) -> Result<Value, Error> {
+        let cmd_name: &String = match &cmd.name {
+            AstName::Placeholder => return Err(VMError::new_unnamed_command(&cmd.name, AstTime::Runtime)),
+            AstName::Name(n) => n,
+            AstName::Variable(_) | AstName::Block(_) if input.is_some() => {
+                return Err(CommandError::new_not_a_consumer(&cmd.name.to_string()));
+            },
+            AstName::Variable(n) => {
+                return match scp.get_variable(n) {
+                    Some(Variable::Command(c)) => self.invoke(scp, &c, &cmd.arguments),
+                    Some(v) => Err(ValueError::new_type_mismatch(&Type::Command, v.get_type())),
+                    None => Err(ScopeError::new_undeclared_variable(n)),
+                };
+            },
+            AstName::Block(AstBlock::Evaluated(c)) | AstName::Block(AstBlock::Argument(c)) | AstName::Block(AstBlock::Inferred(c)) => {
+                return match self.execute_captured(&mut Scope::extends(scp), c)? {
+                    Value::Command(c) => self.invoke(scp, &c, &cmd.arguments),
+                    v => Err(ValueError::new_type_mismatch(&Type::Command, v.get_type())),
+                };
+            },
+        };
+
+        let r = self.resolve_in(scp, cmd, &[AstTime::Runtime, AstTime::Macro])?;
+
+        if r.time == AstTime::Macro {
+            let e = self.execute_macro(scp, cmd)?;
+            let mut e = self.expand_macros(scp, e, 1)?;
+
+            return match input {
+                None => self.execute_block(scp, &e),
+                Some(_) if e.len() != 1 => Err(VMError::new_invalid_pipe_stage(&cmd.name)),
+                Some(s) => {
+                    let mut c = e.remove(0);
+                    c.pipe = None;
+                    self.execute_pipeline(scp, &c, Some(s), false)
+                },
+            };
+        }
+
+        let c = match self.registery.get_command(cmd_name, r.index) {
+            None => return Err(RegisteryError::new_not_found(cmd_name)),
+            Some(c) => c,
+        };
+        let input = match (input, self.registery.get_descriptor(cmd_name, &r)) {
+            (Some(s), Some(AstDescriptor { input: Some(t), .. })) => match Type::from_name(t) {
+                Some(t) => Some(ValueStream::new(s.map(move |v| v.and_then(|v| v.cast(&t))))),
+                None => Some(s),
+            },
+            (input, _) => input,
+        };
+
+        self.enter(r, cmd_name)?;
+        let r = match input {
+            Some(s) => c.execute_piped(self, scp, cmd, s),
+            None => c.execute(self, scp, cmd),
+        };
+        self.leave();
+        r
+    }
+
+}
+
+impl Default for VM {
+    fn default() -> VM {
+        VM::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neoshell::builtins::*;
+
+    fn new_vm() -> VM {
+        let mut vm = VM::new();
+        register_builtins(vm.get_registery_mut()).unwrap();
+        vm
+    }
+
+    fn compile(vm: &mut VM, src: &str) -> Result<Vec<AstCommand>, Error> {
+        vm.compile(&mut Scope::new(), ns_parser::file(src)?)
+    }
+
+    #[test]
+    fn repeated_switches_are_reported_when_compiling() {
+        let mut vm = new_vm();
+
+        compile(&mut vm, "puts \"a\" sep = \"-\" sep = \"+\";").unwrap();
+        let diags = vm.take_diagnostics();
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+
+        compile(&mut vm, "puts \"a\" sep = \"-\";").unwrap();
+        assert!(vm.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn runtime_commands_are_not_executed_as_compile_time_commands() {
+        let mut vm = VM::new();
+        let cmd = AstCommand::new(AstTime::Runtime, AstName::Name("puts".to_owned()));
+
+        let e = vm.execute_ct_command(&mut Scope::new(), &cmd);
+        assert_eq!(e.map(|e| e.to_string()), Some("'puts' is not a compile time command.".to_owned()));
+    }
+
+    #[test]
+    fn compile_time_commands_cannot_call_themselves() {
+        let e = compile(&mut new_vm(), "def-ct-cmd 'rec; { rec; }; rec;").err().unwrap();
+        assert!(matches!(e.downcast_ref::<VMError>(), Some(VMError::RecursiveCompileTime { name }) if name == "rec"));
+    }
+
+    #[test]
+    fn compile_time_commands_cannot_call_runtime_commands() {
+        let e = compile(&mut new_vm(), "def-ct-cmd 'p; { puts \"x\"; }; p;").err().unwrap();
+        match e.downcast_ref::<VMError>() {
+            Some(VMError::ForbiddenCall { caller, caller_time, callee, callee_time }) => {
+                assert_eq!((caller.as_str(), callee.as_str()), ("p", "puts"));
+                assert!(*caller_time == AstTime::CompileTime && *callee_time == AstTime::Runtime);
+            },
+            _ => panic!("Unexpected error : {}", e),
+        }
+    }
+
+    #[test]
+    fn compile_time_commands_run_before_the_other_commands() {
+        let mut vm = new_vm();
+        let mut scp = Scope::new();
+        let prog = vm.compile(&mut scp, ns_parser::file("foo; def-cmd 'foo; { value 1; };").unwrap()).unwrap();
+
+        assert_eq!(prog.len(), 1);
+        assert_eq!(vm.run(&mut scp, &prog).unwrap().to_string(), "1");
+    }
+
+    #[test]
+    fn macros_are_expanded_until_none_remains() {
+        let mut vm = new_vm();
+        let prog = compile(&mut vm, "def-macro 'm1; { value &{ m2; m2; }; }; def-macro 'm2; { value &{ value 7; }; }; m1;").unwrap();
+
+        assert_eq!(prog.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "), "value 7, value 7");
+    }
+
+    #[test]
+    fn recursive_macros_reach_the_recursion_limit() {
+        let mut vm = new_vm();
+        vm.set_recursion_limit(8);
+
+        let e = compile(&mut vm, "def-macro 'loop; { value &{ loop; }; }; loop;").err().unwrap();
+        assert!(matches!(e.downcast_ref::<VMError>(), Some(VMError::RecursionLimit { limit: 8 })));
+    }
+
+    #[test]
+    fn failing_compilations_locate_the_command() {
+        let mut vm = new_vm();
+
+        assert!(compile(&mut vm, "value 1;\nset x 1;").is_err());
+        assert_eq!(vm.take_error_span().map(|s| s.start), Some(9));
+    }
+}
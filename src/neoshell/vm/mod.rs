@@ -1,12 +1,26 @@
 
 pub mod command;
+pub mod external;
+pub mod jobs;
+pub mod machine;
+pub mod paths;
+pub mod process;
+pub mod redirection;
 pub mod registery;
 pub mod scope;
+pub mod signals;
+pub mod value;
 pub mod variable;
-pub mod vm;
 
 pub use self::command::*;
+pub use self::external::*;
+pub use self::jobs::*;
+pub use self::machine::*;
+pub use self::paths::*;
+pub use self::process::*;
+pub use self::redirection::*;
 pub use self::registery::*;
 pub use self::scope::*;
+pub use self::signals::*;
+pub use self::value::*;
 pub use self::variable::*;
-pub use self::vm::*;
@@ -0,0 +1,503 @@
+#![deny(missing_docs)]
+//! Paths of files, home expansion and glob patterns.
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use failure::*;
+
+use super::signals::SignalForwarder;
+use super::value::*;
+
+
+/// Lazy expansion of a glob pattern into the paths of the existing files
+/// matching it. The directories are read only when the paths are pulled.
+///
+/// The pattern is split into components by `/`. In a component, `*` matches
+/// any sequence of characters, `?` any single character, `[abc]` or `[a-z]`
+/// one of the given characters, and `[!abc]` or `[^abc]` any other character.
+/// `\` escapes the next character. A `**` component matches zero or more
+/// directories, without following symbolic links. Wildcards don't match the
+/// names starting with `.` unless the pattern starts with a `.` too.
+///
+/// The paths are generated in a depth-first order, the entries of each
+/// directory being sorted by name. The matches of `**` found in a directory
+/// come before those found in its sub-directories. Directories which can't be
+/// read are skipped. If no file matches, the expansion ends with a `NoMatch`
+/// error, unless an empty expansion is allowed. The expansion can be
+/// interrupted by the signals received by a VM, see `interrupted_by`.
+pub struct Glob {
+    pattern: String,
+    components: Vec<Component>,
+    pending: Vec<(PathBuf, usize)>,
+    matched: bool,
+    allow_empty: bool,
+    signals: Option<SignalForwarder>,
+}
+
+/// Component of a glob pattern.
+enum Component {
+    /// Name without wildcards.
+    Literal(String),
+    /// Name with wildcards.
+    Pattern(Vec<Token>),
+    /// `**`, matching zero or more directories.
+    Recursive,
+}
+
+/// Part of a component with wildcards.
+enum Token {
+    /// A character.
+    Char(char),
+    /// `?`, any character.
+    Any,
+    /// `*`, any sequence of characters.
+    Many,
+    /// `[...]`, one of the ranges of characters, or any other character if
+    /// negated.
+    Class(Vec<(char, char)>, bool),
+}
+
+/// Errors related to paths and glob patterns.
+#[derive(Debug)]
+pub enum PathError {
+    /// No file matches the pattern.
+    NoMatch {
+        /// The glob pattern.
+        pattern: String,
+    },
+    /// The path starts with `~` but the home directory isn't known.
+    NoHome {
+        /// The path.
+        path: String,
+    },
+}
+
+
+
+impl PathError {
+    /// Creates a new `NoMatch` error.
+    pub fn new_no_match(pattern: &str) -> Error {
+        Error::from(PathError::NoMatch {
+            pattern: pattern.to_owned(),
+        })
+    }
+    /// Creates a new `NoHome` error.
+    pub fn new_no_home(path: &str) -> Error {
+        Error::from(PathError::NoHome {
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::NoMatch { pattern } => write!(f, "No file matches the pattern '{}'.", pattern),
+            PathError::NoHome { path } => write!(f, "Path '{}' can't be expanded, as '$HOME' isn't set.", path),
+        }
+    }
+}
+
+impl Fail for PathError {}
+
+
+
+/// Expands a leading `~` into the given home directory : `~` alone or
+/// followed by `/`. Other paths, including `~user` ones, are kept as is.
+pub fn expand_home(path: &str, home: Option<String>) -> Result<PathBuf, Error> {
+    let rest = match path.strip_prefix('~') {
+        Some(r) if r.is_empty() || r.starts_with('/') => r.trim_start_matches('/'),
+        _ => return Ok(PathBuf::from(path)),
+    };
+
+    match home {
+        Some(h) if rest.is_empty() => Ok(PathBuf::from(h)),
+        Some(h) => Ok(Path::new(&h).join(rest)),
+        None => Err(PathError::new_no_home(path)),
+    }
+}
+
+
+
+impl Glob {
+
+    /// Creates the expansion of the given pattern. Relative patterns are
+    /// expanded from the current directory, and give relative paths.
+    pub fn new(pattern: &str, allow_empty: bool) -> Glob {
+        let root = match pattern.starts_with('/') {
+            true => PathBuf::from("/"),
+            false => PathBuf::new(),
+        };
+        let mut components: Vec<Component> = Vec::new();
+
+        for c in pattern.split('/').filter(|c| !c.is_empty()) {
+            let c = Component::parse(c);
+
+            if let (Component::Recursive, Some(Component::Recursive)) = (&c, components.last()) {
+                continue;
+            }
+            components.push(c);
+        }
+
+        Glob {
+            pattern: pattern.to_owned(),
+            components,
+            pending: vec![(root, 0)],
+            matched: false,
+            allow_empty,
+            signals: None,
+        }
+    }
+    /// Makes the expansion fail once a signal interrupts the running script,
+    /// checked before each path explored.
+    pub fn interrupted_by(mut self, signals: &SignalForwarder) -> Glob {
+        self.signals = Some(signals.clone());
+        self
+    }
+
+
+    /// Continues the expansion from a path matching the first components.
+    fn expand(&mut self, path: PathBuf, i: usize) {
+        let mut next = Vec::new();
+
+        match &self.components[i] {
+            Component::Literal(l) => {
+                let p = path.join(l);
+
+                if fs::symlink_metadata(&p).is_ok() {
+                    next.push((p, i + 1));
+                }
+            },
+            Component::Pattern(t) => {
+                for n in read_names(&path, false) {
+                    if matches(t, &n.to_string_lossy()) {
+                        next.push((path.join(n), i + 1));
+                    }
+                }
+            },
+            Component::Recursive => {
+                next.push((path.clone(), i + 1));
+
+                for n in read_names(&path, true) {
+                    if !n.to_string_lossy().starts_with('.') {
+                        next.push((path.join(n), i));
+                    }
+                }
+            },
+        }
+
+        self.pending.extend(next.into_iter().rev());
+    }
+
+}
+
+impl Iterator for Glob {
+    type Item = Result<Value, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, i)) = self.pending.pop() {
+            if let Some(Err(e)) = self.signals.as_ref().map(|s| s.check()) {
+                return Some(Err(e));
+            }
+            if i < self.components.len() {
+                self.expand(path, i);
+                continue;
+            }
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+
+            self.matched = true;
+            return Some(Ok(Value::Path(path)));
+        }
+
+        if !self.matched && !self.allow_empty {
+            self.matched = true;
+            return Some(Err(PathError::new_no_match(&self.pattern)));
+        }
+
+        None
+    }
+}
+
+
+
+impl Component {
+
+    /// Parses a component of a glob pattern.
+    fn parse(c: &str) -> Component {
+        if c == "**" {
+            return Component::Recursive;
+        }
+
+        let mut tokens = Vec::new();
+        let mut chars = c.chars();
+
+        while let Some(ch) = chars.next() {
+            tokens.push(match ch {
+                '*' => Token::Many,
+                '?' => Token::Any,
+                '\\' => Token::Char(chars.next().unwrap_or('\\')),
+                '[' => {
+                    let rest: String = chars.clone().collect();
+
+                    match parse_class(&rest) {
+                        Some((t, len)) => {
+                            chars.by_ref().take(len).for_each(drop);
+                            t
+                        },
+                        None => Token::Char('['),
+                    }
+                },
+                ch => Token::Char(ch),
+            });
+        }
+
+        if tokens.iter().all(|t| matches!(t, Token::Char(_))) {
+            return Component::Literal(tokens.iter().map(|t| match t {
+                Token::Char(c) => *c,
+                _ => unreachable!(),
+            }).collect());
+        }
+
+        Component::Pattern(tokens)
+    }
+
+}
+
+impl Token {
+
+    /// Checks if the token matches the given character.
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            Token::Char(c) => *c == ch,
+            Token::Any => true,
+            Token::Many => true,
+            Token::Class(ranges, negated) => ranges.iter().any(|(a, b)| *a <= ch && ch <= *b) != *negated,
+        }
+    }
+
+}
+
+
+
+/// Parses a character class, following its opening `[`. Returns the class
+/// and the number of characters it spans, or `None` if it isn't closed.
+fn parse_class(s: &str) -> Option<(Token, usize)> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    let mut ranges = Vec::new();
+
+    if negated {
+        i += 1;
+    }
+
+    // A `]` right after the opening bracket is part of the class.
+    let start = i;
+    while i < chars.len() && (chars[i] != ']' || i == start) {
+        let a = chars[i];
+
+        if i + 2 < chars.len() && chars[i + 1] == '-' && chars[i + 2] != ']' {
+            ranges.push((a, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((a, a));
+            i += 1;
+        }
+    }
+
+    if i >= chars.len() {
+        return None;
+    }
+
+    Some((Token::Class(ranges, negated), i + 1))
+}
+
+/// Checks if a name matches the tokens of a component. Names starting with
+/// `.` are only matched by a leading `.`.
+fn matches(tokens: &[Token], name: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+
+    if name.first() == Some(&'.') && !matches!(tokens.first(), Some(Token::Char('.'))) {
+        return false;
+    }
+
+    matches_from(tokens, &name)
+}
+
+/// Checks if the characters match the tokens. On a mismatch, the last `*`
+/// seen takes one more character and the matching resumes after it, which
+/// keeps the matching linear in the length of the name for each token.
+fn matches_from(tokens: &[Token], name: &[char]) -> bool {
+    let mut t = 0;
+    let mut n = 0;
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match tokens.get(t) {
+            Some(Token::Many) => {
+                star = Some((t, n));
+                t += 1;
+            },
+            Some(tok) if tok.matches(name[n]) => {
+                t += 1;
+                n += 1;
+            },
+            _ => match star {
+                Some((st, sn)) => {
+                    star = Some((st, sn + 1));
+                    t = st + 1;
+                    n = sn + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+
+    tokens[t..].iter().all(|t| matches!(t, Token::Many))
+}
+
+/// Reads the names of the entries of a directory, sorted. Only the
+/// directories are kept if asked, symbolic links excluded. A directory which
+/// can't be read has no entries.
+fn read_names(dir: &Path, dirs_only: bool) -> Vec<OsString> {
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut names: Vec<OsString> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| !dirs_only || e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+        .map(|e| e.file_name())
+        .collect();
+
+    names.sort();
+    names
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches_str(pattern: &str, name: &str) -> bool {
+        match Component::parse(pattern) {
+            Component::Pattern(t) => matches(&t, name),
+            Component::Literal(l) => l == name,
+            Component::Recursive => true,
+        }
+    }
+
+    /// Creates an empty directory for a test, removing any previous one.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("neoshell-glob-{}-{}", name, std::process::id()));
+
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn expand(pattern: &str) -> Vec<String> {
+        Glob::new(pattern, true)
+            .map(|v| match v.unwrap() {
+                Value::Path(p) => p.to_string_lossy().into_owned(),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_components() {
+        assert!(matches!(Component::parse("**"), Component::Recursive));
+        assert!(matches!(Component::parse("src"), Component::Literal(l) if l == "src"));
+        assert!(matches!(Component::parse("a\\*b"), Component::Literal(l) if l == "a*b"));
+        assert!(matches!(Component::parse("[ab"), Component::Literal(l) if l == "[ab"));
+        assert!(matches!(Component::parse("*.rs"), Component::Pattern(t) if t.len() == 4));
+        assert!(matches!(Component::parse("a?[xy]"), Component::Pattern(t) if t.len() == 3));
+    }
+
+    #[test]
+    fn parse_classes() {
+        assert!(matches!(parse_class("a-c]x"), Some((Token::Class(r, false), 4)) if r == [('a', 'c')]));
+        assert!(matches!(parse_class("!ab]"), Some((Token::Class(r, true), 4)) if r == [('a', 'a'), ('b', 'b')]));
+        assert!(matches!(parse_class("^a]"), Some((Token::Class(_, true), 3))));
+        assert!(matches!(parse_class("]a]"), Some((Token::Class(r, false), 3)) if r == [(']', ']'), ('a', 'a')]));
+        assert!(matches!(parse_class("a-]"), Some((Token::Class(r, false), 3)) if r == [('a', 'a'), ('-', '-')]));
+        assert!(parse_class("abc").is_none());
+    }
+
+    #[test]
+    fn match_wildcards() {
+        assert!(matches_str("*.rs", "main.rs"));
+        assert!(!matches_str("*.rs", "main.rc"));
+        assert!(matches_str("m??n.*", "main.rs"));
+        assert!(matches_str("[a-m]*", "main"));
+        assert!(!matches_str("[!a-m]*", "main"));
+        assert!(matches_str("*a*b*", "xaybz"));
+        assert!(!matches_str("*a*b*", "xbyaz"));
+        assert!(matches_str("***", ""));
+    }
+
+    #[test]
+    fn match_many_stars_quickly() {
+        let name = "a".repeat(40);
+
+        assert!(!matches_str("*a*a*a*a*a*a*a*a*a*a*b", &name));
+        assert!(matches_str("*a*a*a*a*a*a*a*a*a*a*", &name));
+    }
+
+    #[test]
+    fn hidden_files() {
+        assert!(!matches_str("*", ".git"));
+        assert!(!matches_str("?git", ".git"));
+        assert!(!matches_str("[.]git", ".git"));
+        assert!(matches_str(".*", ".git"));
+        assert!(matches_str(".g*", ".git"));
+    }
+
+    #[test]
+    fn recursive_order() {
+        let dir = test_dir("order");
+
+        for d in ["b/c", "a", ".hidden"].iter() {
+            fs::create_dir_all(dir.join(d)).unwrap();
+        }
+        for f in ["z.txt", "a/y.txt", "b/x.txt", "b/c/w.txt", ".hidden/v.txt", "b/u.rs"].iter() {
+            fs::write(dir.join(f), "").unwrap();
+        }
+
+        let root = dir.to_string_lossy().into_owned();
+        let found = expand(&format!("{}/**/*.txt", root));
+        let expected: Vec<String> = ["z.txt", "a/y.txt", "b/x.txt", "b/c/w.txt"].iter()
+            .map(|f| format!("{}/{}", root, f))
+            .collect();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn no_match() {
+        let dir = test_dir("empty");
+        let pattern = format!("{}/*.txt", dir.to_string_lossy());
+
+        let mut glob = Glob::new(&pattern, false);
+        let first = glob.next();
+        let empty = expand(&pattern);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(first, Some(Err(_))));
+        assert!(glob.next().is_none());
+        assert!(empty.is_empty());
+    }
+}
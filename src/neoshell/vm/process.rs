@@ -0,0 +1,787 @@
+#![deny(missing_docs)]
+//! Pipelines of external processes connected by OS pipes.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, PipeReader, PipeWriter, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use failure::*;
+
+use super::external::*;
+use super::redirection::*;
+use super::signals::*;
+use super::value::*;
+
+
+/// Exit code of a process killed by `SIGPIPE`.
+const SIGPIPE_CODE: i32 = 128 + 13;
+
+/// Number of pieces of output read ahead from the last process of a pipeline.
+const OUTPUT_BUFFER: usize = 64;
+
+/// Number of items of an input stream handed ahead to the thread writing them
+/// into the first process of a pipeline.
+const INPUT_BUFFER: usize = 64;
+
+/// Time waited for some output of a pipeline while the thread writing its
+/// input is busy.
+const INPUT_WAIT: Duration = Duration::from_millis(10);
+
+
+/// Destination of the output of the last process of a pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessOutput {
+    /// The output is written to the output of the shell.
+    Inherit,
+    /// The output is read as a stream of `str` lines.
+    Lines,
+    /// The output is read as a stream of `u1` bytes.
+    Bytes,
+    /// The output is captured as the value of an evaluated block.
+    Capture(CaptureOptions),
+}
+
+/// Options of the capture of the output of a pipeline, given by the last
+/// program. By default, the output is captured as a `str` without its
+/// trailing line feeds.
+///
+/// Capturing the error output or the exit codes makes the value an array :
+/// `[output, stderr, status]`, the status being the exit code of the process,
+/// or the array of the exit codes of the processes of the pipeline.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CaptureOptions {
+    /// Keeps the trailing line feeds (`/raw`).
+    pub raw: bool,
+    /// Splits the output into lines (`/lines`). Without error output nor exit
+    /// codes, the lines are a lazy stream.
+    pub lines: bool,
+    /// Captures the error output of the processes too (`/with-stderr`).
+    pub stderr: bool,
+    /// Captures the exit codes too, allowing the processes to fail
+    /// (`/with-status`).
+    pub status: bool,
+}
+
+/// External processes running concurrently, each one writing its output to
+/// the input of the next one through an OS pipe.
+///
+/// The processes of a pipeline are put in their own process group, led by the
+/// first process, so signals can be sent to the whole pipeline. They receive
+/// the signals forwarded by the shell, see `SignalForwarder`. A
+/// pipeline writing its output to the terminal in the foreground is also
+/// given the terminal until its end, so the signals of the terminal reach its
+/// processes only once, without the shell.
+pub struct ProcessPipeline {
+    processes: Vec<Process>,
+    signals: SignalForwarder,
+    targets: Vec<i32>,
+    terminal: Option<libc::pid_t>,
+}
+
+/// Process of a pipeline.
+struct Process {
+    name: String,
+    child: Child,
+    allow_failure: bool,
+}
+
+/// Input of a process of a pipeline, unless it is redirected.
+enum StageInput {
+    /// Output of the previous process.
+    Pipe(PipeReader),
+    /// Input stream written by the pipeline.
+    Fed,
+    /// No input : the previous process has its output redirected, or the
+    /// pipeline runs in the background.
+    Closed,
+    /// Input of the standard streams.
+    Inherit,
+}
+
+/// Piece of output read from the last process of a pipeline.
+enum Chunk {
+    Line(String),
+    Bytes(Vec<u8>),
+}
+
+/// Stream reading the output of a pipeline, while writing its input stream
+/// into the first process.
+struct ProcessStream {
+    pipeline: ProcessPipeline,
+    input: Option<StreamInput>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+    output: Receiver<io::Result<Chunk>>,
+    pending: VecDeque<Value>,
+    codes: Vec<i32>,
+    done: bool,
+}
+
+/// Input stream of a pipeline whose output is read. The items are handed to a
+/// separate thread writing them into the first process, so the shell never
+/// blocks on a full pipe while the output of the pipeline waits to be read.
+struct StreamInput {
+    stream: ValueStream,
+    next: Option<Vec<u8>>,
+    writer: SyncSender<Vec<u8>>,
+}
+
+
+
+impl ProcessPipeline {
+
+    /// Starts the given programs, connecting each one to the next. The first
+    /// process reads the given input stream, or the input of the given
+    /// standard streams, which are also used for the outputs of the
+    /// processes. The redirections of each program take precedence over the
+    /// pipes.
+    ///
+    /// If the output is read, the items of the input stream are pulled only
+    /// as the processes read them, avoiding the buffering of whole streams.
+    /// Otherwise the input stream is written entirely, and the
+    /// processes are waited.
+    pub fn run(stages: Vec<(ExternalCommand, ExternalArguments)>, streams: &StandardStreams, signals: &SignalForwarder, input: Option<ValueStream>, output: ProcessOutput) -> Result<Value, Error> {
+        let output = match output {
+            ProcessOutput::Capture(o) if o.lines && !o.stderr && !o.status => ProcessOutput::Lines,
+            o => o,
+        };
+        let errors = match output {
+            ProcessOutput::Capture(o) if o.stderr => Some(io::pipe()?),
+            _ => None,
+        };
+
+        let first = match input.is_some() {
+            true => StageInput::Fed,
+            false => StageInput::Inherit,
+        };
+        let piped = output != ProcessOutput::Inherit;
+        let (mut pipeline, stdin, reader) = ProcessPipeline::start(stages, streams, signals, first, piped, errors.as_ref().map(|(_, w)| w))?;
+        if !piped {
+            pipeline.give_terminal();
+        }
+        let errors = errors.map(|(r, _)| read_all(r));
+
+        let input = match (input, stdin) {
+            (Some(s), Some(i)) => Some((s, i)),
+            _ => None,
+        };
+
+        match output {
+            ProcessOutput::Inherit => {
+                if let Some((s, mut i)) = input {
+                    if let Err(e) = write_stream(s, &mut i) {
+                        pipeline.kill();
+                        return Err(e);
+                    }
+                }
+
+                Ok(status_value(pipeline.wait()?))
+            },
+            ProcessOutput::Capture(o) => {
+                if o.status {
+                    pipeline.processes.iter_mut().for_each(|p| p.allow_failure = true);
+                }
+
+                let mut stream = ProcessStream::new(pipeline, input, read_output(reader, ProcessOutput::Bytes));
+                let mut out = Vec::new();
+
+                while let Some(c) = stream.next_chunk() {
+                    if let Chunk::Bytes(b) = c? {
+                        out.extend(b);
+                    }
+                }
+
+                let mut values = vec![match o.lines {
+                    true => Value::Array(Type::Str, text(out, false).lines().map(|l| Value::Str(l.to_owned())).collect()),
+                    false => Value::Str(text(out, o.raw)),
+                }];
+                if let Some(h) = errors {
+                    let err = h.join().unwrap_or_else(|_| Ok(Vec::new()))?;
+                    values.push(Value::Str(text(err, o.raw)));
+                }
+                if o.status {
+                    values.push(status_value(stream.codes.clone()));
+                }
+
+                Ok(match values.len() {
+                    1 => values.remove(0),
+                    _ => Value::Array(Type::Generic, values),
+                })
+            },
+            ProcessOutput::Lines | ProcessOutput::Bytes => {
+                let ty = match output {
+                    ProcessOutput::Bytes => Type::U1,
+                    _ => Type::Str,
+                };
+
+                Ok(Value::Stream(ty, ValueStream::new(ProcessStream::new(pipeline, input, read_output(reader, output)))))
+            },
+        }
+    }
+
+
+    /// Starts the given programs in the background, connected as by `run`.
+    /// The processes write to the given standard streams, but only read their
+    /// redirected input.
+    pub fn run_background(stages: Vec<(ExternalCommand, ExternalArguments)>, streams: &StandardStreams, signals: &SignalForwarder) -> Result<ProcessPipeline, Error> {
+        let (pipeline, _, _) = ProcessPipeline::start(stages, streams, signals, StageInput::Closed, false, None)?;
+        Ok(pipeline)
+    }
+
+
+    /// Spawns the processes of a pipeline, the first one having the given
+    /// input. Returns the pipeline, the input of the first process if it is
+    /// fed, and the output of the last process if it is piped. The error
+    /// outputs are written into the given pipe, if any. The process group of
+    /// the pipeline receives the signals forwarded by the given state.
+    fn start(stages: Vec<(ExternalCommand, ExternalArguments)>, streams: &StandardStreams, signals: &SignalForwarder, first: StageInput, piped: bool, errors: Option<&PipeWriter>) -> Result<(ProcessPipeline, Option<ChildStdin>, Option<PipeReader>), Error> {
+        let count = stages.len();
+        let mut pipeline = ProcessPipeline {
+            processes: Vec::new(),
+            signals: signals.clone(),
+            targets: Vec::new(),
+            terminal: None,
+        };
+        let mut stdin = None;
+        let mut reader = None;
+        let mut first = Some(first);
+
+        for (i, (e, args)) in stages.into_iter().enumerate() {
+            let input = match reader.take() {
+                Some(r) => StageInput::Pipe(r),
+                None => first.take().unwrap_or(StageInput::Closed),
+            };
+            let spawned = pipeline.spawn(&e, &args, streams, input, piped || i + 1 < count, errors);
+
+            let (mut child, r) = match spawned {
+                Ok(c) => c,
+                Err(err) => {
+                    pipeline.kill();
+                    return Err(ExternalError::new_spawn(e.get_name(), &err));
+                },
+            };
+            if i == 0 {
+                stdin = child.stdin.take();
+            }
+            if i == 0 {
+                let target = -(child.id() as i32);
+
+                signals.register(target);
+                pipeline.targets.push(target);
+            }
+            reader = r;
+
+            pipeline.processes.push(Process {
+                name: e.get_name().clone(),
+                child,
+                allow_failure: args.allow_failure,
+            });
+        }
+
+        Ok((pipeline, stdin, reader))
+    }
+    /// Spawns a process of the pipeline. Returns the process, and the reader
+    /// of its output if it is piped.
+    fn spawn(&self, e: &ExternalCommand, args: &ExternalArguments, streams: &StandardStreams, input: StageInput, piped: bool, errors: Option<&PipeWriter>) -> io::Result<(Child, Option<PipeReader>)> {
+        let r = &args.redirections;
+        let streams = r.apply(streams);
+        let mut c = e.command(args);
+
+        c.process_group(self.processes.first().map(|p| p.child.id() as i32).unwrap_or(0));
+
+        match (&r.stdin, input) {
+            (None, StageInput::Pipe(p)) => c.stdin(Stdio::from(p)),
+            (None, StageInput::Fed) => c.stdin(Stdio::piped()),
+            (None, StageInput::Closed) => c.stdin(Stdio::null()),
+            _ => c.stdin(streams.stdin.to_stdio()?),
+        };
+
+        let pipe = match piped && r.stdout.is_none() {
+            true => Some(io::pipe()?),
+            false => None,
+        };
+        let stdout = match &pipe {
+            Some((_, w)) => Stdio::from(w.try_clone()?),
+            None => streams.stdout.to_stdio()?,
+        };
+        let stderr = match (&r.stderr, &pipe, errors) {
+            (Some(ErrorRedirection::Merge), Some((_, w)), _) => Stdio::from(w.try_clone()?),
+            (None, _, Some(w)) => Stdio::from(w.try_clone()?),
+            _ => streams.stderr.to_stdio()?,
+        };
+
+        let child = c.stdout(stdout).stderr(stderr).spawn()?;
+        Ok((child, pipe.map(|(r, _)| r)))
+    }
+
+    /// Waits for the end of the processes, and gets their exit codes. A
+    /// non-zero exit code is an error, unless the failure of the process is
+    /// allowed, or the process was killed by `SIGPIPE` because the next one
+    /// closed its input.
+    pub fn wait(&mut self) -> Result<Vec<i32>, Error> {
+        let statuses = self.processes.iter_mut()
+            .map(|p| p.child.wait().map_err(|e| ExternalError::new_spawn(&p.name, &e)))
+            .collect::<Result<Vec<_>, _>>();
+        self.unregister();
+
+        let codes = statuses?.into_iter().map(exit_code).collect::<Vec<_>>();
+        let last = codes.len() - 1;
+        let failed = self.processes.iter().zip(codes.iter()).enumerate()
+            .any(|(i, (p, c))| *c != 0 && !p.allow_failure && !(i < last && *c == SIGPIPE_CODE));
+        if failed {
+            let names = self.processes.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+            return Err(ExternalError::new_failed(&names, &codes));
+        }
+
+        Ok(codes)
+    }
+    /// Gets the exit codes of the processes if they all ended, without
+    /// waiting for them. Failures aren't errors here, they are reported by
+    /// `wait`.
+    pub fn try_wait(&mut self) -> Result<Option<Vec<i32>>, Error> {
+        let mut codes = Vec::new();
+
+        for p in self.processes.iter_mut() {
+            match p.child.try_wait().map_err(|e| ExternalError::new_spawn(&p.name, &e))? {
+                Some(s) => codes.push(exit_code(s)),
+                None => return Ok(None),
+            }
+        }
+        self.unregister();
+
+        Ok(Some(codes))
+    }
+    /// Sends a signal to the process group of the processes.
+    pub fn signal(&self, sig: i32) -> io::Result<()> {
+        if let Some(p) = self.processes.first() {
+            if unsafe { libc::kill(-(p.child.id() as i32), sig) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+    /// Kills the processes still running, and waits for their end.
+    pub fn kill(&mut self) {
+        for p in self.processes.iter_mut() {
+            let _ = p.child.kill();
+            let _ = p.child.wait();
+        }
+
+        self.unregister();
+    }
+
+    /// Stops forwarding the signals of the shell to the processes, and takes
+    /// the terminal back.
+    fn unregister(&mut self) {
+        for t in self.targets.drain(..) {
+            self.signals.unregister(t);
+        }
+
+        if let Some(g) = self.terminal.take() {
+            set_terminal(g);
+        }
+    }
+
+    /// Gives the terminal to the process group of the pipeline, if the shell
+    /// has it. The processes stopped by reading the terminal before getting
+    /// it are resumed.
+    fn give_terminal(&mut self) {
+        let pgid = match self.processes.first() {
+            Some(p) => p.child.id() as libc::pid_t,
+            None => return,
+        };
+        let shell = unsafe { libc::getpgrp() };
+        let owned = unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::tcgetpgrp(libc::STDIN_FILENO) == shell };
+
+        if owned && set_terminal(pgid) {
+            self.terminal = Some(shell);
+            unsafe {
+                libc::kill(-pgid, libc::SIGCONT);
+            }
+        }
+    }
+
+}
+
+
+
+impl Iterator for ProcessStream {
+    type Item = Result<Value, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(v) = self.pending.pop_front() {
+                return Some(Ok(v));
+            }
+
+            match self.next_chunk()? {
+                Ok(Chunk::Line(l)) => self.pending.push_back(Value::Str(l)),
+                Ok(Chunk::Bytes(b)) => self.pending.extend(b.into_iter().map(Value::U1)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+impl ProcessStream {
+
+    /// Creates a new stream reading the output of the pipeline.
+    fn new(pipeline: ProcessPipeline, input: Option<(ValueStream, ChildStdin)>, output: Receiver<io::Result<Chunk>>) -> ProcessStream {
+        let (input, writer) = match input {
+            Some((stream, i)) => {
+                let (writer, handle) = write_input(i);
+                (Some(StreamInput { stream, next: None, writer }), Some(handle))
+            },
+            None => (None, None),
+        };
+
+        ProcessStream {
+            pipeline,
+            input,
+            writer,
+            output,
+            pending: VecDeque::new(),
+            codes: Vec::new(),
+            done: false,
+        }
+    }
+
+
+    /// Gets the next piece of output, handing the input stream to the writing
+    /// thread meanwhile. The processes are waited at the end of the output.
+    fn next_chunk(&mut self) -> Option<Result<Chunk, Error>> {
+        loop {
+            if self.done {
+                return None;
+            }
+
+            let received = match self.input {
+                Some(_) => match self.feed_input() {
+                    Ok(true) => self.output.try_recv(),
+                    Ok(false) => self.output.recv_timeout(INPUT_WAIT).map_err(|e| match e {
+                        RecvTimeoutError::Timeout => TryRecvError::Empty,
+                        RecvTimeoutError::Disconnected => TryRecvError::Disconnected,
+                    }),
+                    Err(e) => return Some(Err(e)),
+                },
+                None => self.output.recv().map_err(|_| TryRecvError::Disconnected),
+            };
+
+            match received {
+                Ok(c) => return Some(c.map_err(Error::from)),
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => {
+                    self.done = true;
+                    self.input = None;
+
+                    self.codes = match self.pipeline.wait() {
+                        Ok(c) => c,
+                        Err(e) => return Some(Err(e)),
+                    };
+
+                    return match self.writer.take().map(|h| h.join()) {
+                        Some(Ok(Err(e))) => Some(Err(Error::from(e))),
+                        _ => None,
+                    };
+                },
+            }
+        }
+    }
+
+    /// Hands the next item of the input stream to the writing thread, closing
+    /// the input of the first process at the end of the stream. Returns
+    /// `false` if the thread is still busy with the previous items.
+    fn feed_input(&mut self) -> Result<bool, Error> {
+        let input = self.input.as_mut().unwrap();
+
+        let bytes = match input.next.take() {
+            Some(b) => b,
+            None => match input.stream.next() {
+                Some(Ok(v)) => value_bytes(&v),
+                Some(Err(e)) => {
+                    self.input = None;
+                    return Err(e);
+                },
+                None => {
+                    self.input = None;
+                    return Ok(true);
+                },
+            },
+        };
+
+        match input.writer.try_send(bytes) {
+            Ok(()) => Ok(true),
+            Err(TrySendError::Full(b)) => {
+                input.next = Some(b);
+                Ok(false)
+            },
+            Err(TrySendError::Disconnected(_)) => {
+                self.input = None;
+                Ok(true)
+            },
+        }
+    }
+
+}
+
+impl Drop for ProcessStream {
+    fn drop(&mut self) {
+        if !self.done {
+            self.input = None;
+            self.pipeline.kill();
+        }
+    }
+}
+
+
+
+/// Gets the value of the exit codes of a pipeline : the exit code of its
+/// process, or the array of the exit codes of its processes.
+pub fn status_value(codes: Vec<i32>) -> Value {
+    match codes.as_slice() {
+        [c] => Value::I4(*c),
+        _ => Value::Array(Type::I4, codes.into_iter().map(Value::I4).collect()),
+    }
+}
+
+/// Converts a captured output into a string, without its trailing line feeds
+/// unless the output is raw.
+fn text(mut b: Vec<u8>, raw: bool) -> String {
+    while !raw && matches!(b.last(), Some(b'\n') | Some(b'\r')) {
+        b.pop();
+    }
+
+    String::from_utf8_lossy(&b).into_owned()
+}
+
+/// Writes a value into the input of a process, see `value_bytes`. Returns
+/// `false` if the process closed its input.
+fn write_value<W: Write>(i: &mut W, v: &Value) -> Result<bool, Error> {
+    match i.write_all(&value_bytes(v)) {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+/// Converts a value written into the input of a process into bytes : `u1`
+/// values are raw bytes, and other values are lines.
+fn value_bytes(v: &Value) -> Vec<u8> {
+    match v {
+        Value::U1(b) => vec![*b],
+        v => format!("{}\n", v).into_bytes(),
+    }
+}
+
+/// Writes a whole stream into the input of a process, until the process
+/// closes its input.
+fn write_stream<W: Write>(s: ValueStream, i: &mut W) -> Result<(), Error> {
+    for v in s {
+        if !write_value(i, &v?)? {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes the given process group the foreground process group of the
+/// terminal of the shell. `SIGTTOU` is blocked meanwhile, as the shell may not
+/// be in the foreground anymore. Returns `false` if it fails.
+fn set_terminal(pgid: libc::pid_t) -> bool {
+    unsafe {
+        let mut set = std::mem::zeroed();
+        let mut old = std::mem::zeroed();
+
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGTTOU);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, &mut old);
+        let r = libc::tcsetpgrp(libc::STDIN_FILENO, pgid);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &old, std::ptr::null_mut());
+
+        r == 0
+    }
+}
+
+/// Writes the bytes received into the input of a process in a separate
+/// thread, at most `INPUT_BUFFER` pieces behind. The thread stops when the
+/// sender is dropped or when the process closes its input.
+fn write_input(mut i: ChildStdin) -> (SyncSender<Vec<u8>>, JoinHandle<io::Result<()>>) {
+    let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(INPUT_BUFFER);
+
+    let handle = thread::spawn(move || {
+        for b in rx {
+            match i.write_all(&b) {
+                Ok(()) => {},
+                Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    });
+
+    (tx, handle)
+}
+
+/// Reads the output of a process in a separate thread, ahead of the stream by
+/// at most `OUTPUT_BUFFER` pieces. The process then waits for the stream to be
+/// pulled. Without output, the stream is empty.
+fn read_output<R: Read + Send + 'static>(output: Option<R>, mode: ProcessOutput) -> Receiver<io::Result<Chunk>> {
+    let (tx, rx) = mpsc::sync_channel(OUTPUT_BUFFER);
+    let output = match output {
+        Some(o) => o,
+        None => return rx,
+    };
+
+    thread::spawn(move || {
+        let mut r = BufReader::new(output);
+
+        loop {
+            let chunk = match mode {
+                ProcessOutput::Bytes => read_bytes(&mut r),
+                _ => read_line(&mut r),
+            };
+
+            match chunk {
+                Ok(Some(c)) => if tx.send(Ok(c)).is_err() {
+                    break;
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    break;
+                },
+            }
+        }
+    });
+
+    rx
+}
+
+/// Reads a whole output in a separate thread.
+fn read_all(mut r: PipeReader) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut b = Vec::new();
+        r.read_to_end(&mut b).map(|_| b)
+    })
+}
+
+/// Reads the available bytes. Returns `None` at the end of the output.
+fn read_bytes<R: Read>(r: &mut BufReader<R>) -> io::Result<Option<Chunk>> {
+    let b = r.fill_buf()?.to_vec();
+    r.consume(b.len());
+
+    if b.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Chunk::Bytes(b)))
+}
+
+/// Reads a line, without its line feed. Returns `None` at the end of the
+/// output.
+fn read_line<R: Read>(r: &mut BufReader<R>) -> io::Result<Option<Chunk>> {
+    let mut l = Vec::new();
+
+    if r.read_until(b'\n', &mut l)? == 0 {
+        return Ok(None);
+    }
+    if l.last() == Some(&b'\n') {
+        l.pop();
+    }
+
+    Ok(Some(Chunk::Line(String::from_utf8_lossy(&l).into_owned())))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neoshell::builtins::tests::{output, run};
+
+    use std::collections::HashMap;
+    use std::env;
+
+    fn cat() -> (ExternalCommand, ExternalArguments) {
+        let path = env::var("PATH").unwrap_or_default();
+
+        (ExternalCommand::find("cat", &path).unwrap(), ExternalArguments {
+            argv: Vec::new(),
+            allow_failure: false,
+            redirections: Redirections::default(),
+            env: HashMap::new(),
+            capture: CaptureOptions::default(),
+            background: false,
+        })
+    }
+
+    #[test]
+    fn large_input_items_do_not_block_the_output() {
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let lines: Vec<String> = (1..=200_000).map(|i| i.to_string()).collect();
+            let input = ValueStream::from_values(vec![Value::Str(lines.join("\n"))]);
+
+            let count = match ProcessPipeline::run(vec![cat(), cat()], &StandardStreams::inherit(), &SignalForwarder::new(), Some(input), ProcessOutput::Lines) {
+                Ok(Value::Stream(_, s)) => s.filter(|v| v.is_ok()).count(),
+                _ => 0,
+            };
+            let _ = tx.send(count);
+        });
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(60)).ok(), Some(200_000));
+    }
+
+    #[test]
+    fn programs_are_piped_into_programs() {
+        assert_eq!(output("seq 1 3 |> tac stdout = $out;").unwrap(), "3\n2\n1\n");
+    }
+
+    #[test]
+    fn commands_and_programs_exchange_lines() {
+        assert_eq!(run("seq 1 5 |> count;").unwrap().to_string(), "5");
+        assert_eq!(output("range 1 4 |> cat stdout = $out;").unwrap(), "1\n2\n3\n");
+        assert_eq!(output("seq 1 9 |> take 2 |> cat stdout = $out;").unwrap(), "1\n2\n");
+    }
+
+    #[test]
+    fn byte_consumers_read_bytes() {
+        assert_eq!(run("def-cmd 'bytes |>u1; { stream $in |> count; }; printf abc |> bytes;").unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn pipelines_report_the_codes_of_their_processes() {
+        let e = run("false |> true;").err().unwrap();
+        assert!(matches!(e.downcast_ref::<ExternalError>(), Some(ExternalError::PipelineFailed { codes, .. }) if codes == "1, 0"));
+        assert!(run("false /allow-failure |> true;").is_ok());
+    }
+
+    #[test]
+    fn captured_outputs_are_strings() {
+        assert_eq!(run("let s !{ echo hi }; value $s;").unwrap().to_string(), "hi");
+        assert_eq!(run("let s !{ echo hi /raw }; value $s;").unwrap().to_string(), "hi\n");
+        assert_eq!(run("let s !{ seq 1 3 |> tac }; value $s;").unwrap().to_string(), "3\n2\n1");
+        assert!(run("let s !{ false };").is_err());
+    }
+
+    #[test]
+    fn captured_outputs_are_split_into_lines() {
+        assert_eq!(run("let l !{ seq 1 3 /lines }; stream $l |> count;").unwrap().to_string(), "3");
+    }
+
+    #[test]
+    fn captured_errors_and_statuses_are_arrays() {
+        let v = run("let s !{ sh \"-c\" \"echo o; echo e >&2; exit 2\" /with-stderr /with-status }; value $s;").unwrap();
+        match v {
+            Value::Array(_, v) => assert_eq!(v.iter().map(|v| v.to_string()).collect::<Vec<_>>(), ["o", "e", "2"]),
+            v => panic!("Expected an array, got {}", v),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+#![deny(missing_docs)]
+//! Redirections of the standard streams of commands.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::AsFd;
+use std::path::Path;
+use std::process::Stdio;
+use std::rc::Rc;
+
+use failure::*;
+
+use crate::neoshell::parser::*;
+
+use super::external::END_OF_FLAGS;
+use super::paths::expand_home;
+use super::scope::*;
+use super::value::*;
+use super::machine::*;
+
+
+/// Input stream of the commands.
+#[derive(Clone)]
+pub enum InputStream {
+    /// Input of the shell.
+    Stdin,
+    /// File opened for reading.
+    File(Rc<File>),
+}
+
+/// Output stream of the commands.
+#[derive(Clone)]
+pub enum OutputStream {
+    /// Output of the shell.
+    Stdout,
+    /// Error output of the shell.
+    Stderr,
+    /// File opened for writing.
+    File(Rc<File>),
+}
+
+/// Standard streams used by the commands being executed, and given to the
+/// programs they run.
+#[derive(Clone)]
+pub struct StandardStreams {
+    /// Input stream.
+    pub stdin: InputStream,
+    /// Output stream.
+    pub stdout: OutputStream,
+    /// Error stream.
+    pub stderr: OutputStream,
+}
+
+/// Redirections of the standard streams of a command, with their files
+/// opened :
+///
+///  - `stdin = <path>` reads the input from a file,
+///  - `stdout = <path>` writes the output into a file,
+///  - `stderr = <path>` writes the error output into a file,
+///  - `stderr -> merge` writes the error output into the output,
+///  - `/append` appends to the files instead of truncating them.
+///
+/// A redirected input replaces the stream piped into the command.
+#[derive(Default)]
+pub struct Redirections {
+    /// File read as input.
+    pub stdin: Option<Rc<File>>,
+    /// File written as output.
+    pub stdout: Option<Rc<File>>,
+    /// Destination of the error output.
+    pub stderr: Option<ErrorRedirection>,
+}
+
+/// Destination of a redirected error output.
+pub enum ErrorRedirection {
+    /// File written as error output.
+    File(Rc<File>),
+    /// The error output is written into the output.
+    Merge,
+}
+
+/// Errors related to redirections.
+#[derive(Debug)]
+pub enum RedirectionError {
+    /// The redirection isn't valid.
+    InvalidRedirection {
+        /// The invalid redirection.
+        redirection: String,
+    },
+    /// The redirected file couldn't be opened.
+    Open {
+        /// Path of the file.
+        path: String,
+        /// Description of the problem.
+        msg: String,
+    },
+}
+
+
+
+impl RedirectionError {
+    /// Creates a new `InvalidRedirection` error.
+    pub fn new_invalid_redirection(sw: &AstSwitch) -> Error {
+        Error::from(RedirectionError::InvalidRedirection {
+            redirection: sw.to_string(),
+        })
+    }
+    /// Creates a new `Open` error.
+    pub fn new_open(path: &str, e: &io::Error) -> Error {
+        Error::from(RedirectionError::Open {
+            path: path.to_owned(),
+            msg: e.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RedirectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RedirectionError::InvalidRedirection { redirection } => write!(f, "Invalid redirection '{}'.", redirection),
+            RedirectionError::Open { path, msg } => write!(f, "File '{}' couldn't be opened : {}", path, msg),
+        }
+    }
+}
+
+impl Fail for RedirectionError {}
+
+
+
+impl StandardStreams {
+
+    /// Creates the standard streams of the shell.
+    pub fn inherit() -> StandardStreams {
+        StandardStreams {
+            stdin: InputStream::Stdin,
+            stdout: OutputStream::Stdout,
+            stderr: OutputStream::Stderr,
+        }
+    }
+
+}
+
+impl InputStream {
+
+    /// Gets the stream as the input of a process.
+    pub fn to_stdio(&self) -> io::Result<Stdio> {
+        match self {
+            InputStream::Stdin => Ok(Stdio::inherit()),
+            InputStream::File(f) => Ok(Stdio::from(f.try_clone()?)),
+        }
+    }
+
+}
+
+impl OutputStream {
+
+    /// Gets the stream as an output of a process.
+    pub fn to_stdio(&self) -> io::Result<Stdio> {
+        match self {
+            OutputStream::Stdout => {
+                io::stdout().flush()?;
+                Ok(Stdio::from(io::stdout().as_fd().try_clone_to_owned()?))
+            },
+            OutputStream::Stderr => Ok(Stdio::from(io::stderr().as_fd().try_clone_to_owned()?)),
+            OutputStream::File(f) => Ok(Stdio::from(f.try_clone()?)),
+        }
+    }
+
+}
+
+impl Write for OutputStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputStream::Stdout => io::stdout().write(buf),
+            OutputStream::Stderr => io::stderr().write(buf),
+            OutputStream::File(f) => f.as_ref().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputStream::Stdout => io::stdout().flush(),
+            OutputStream::Stderr => io::stderr().flush(),
+            OutputStream::File(f) => f.as_ref().flush(),
+        }
+    }
+}
+
+
+
+impl Redirections {
+
+    /// Evaluates the redirections of a command, and opens their files.
+    pub fn open(vm: &VM, scp: &mut Scope, switches: &[AstSwitch]) -> Result<Redirections, Error> {
+        let append = switches.iter().any(|s| matches!(s, AstSwitch::On(n) if n == "append"));
+        let mut r = Redirections::default();
+
+        for s in switches {
+            match s {
+                AstSwitch::Option(n, v) => {
+                    let path = match vm.evaluate(scp, v)? {
+                        Value::Path(p) => p,
+                        v => match v.cast(&Type::Str)? {
+                            Value::Str(s) => expand_home(&s, scp.get_environment_variable(&"HOME".to_owned()))?,
+                            _ => return Err(RedirectionError::new_invalid_redirection(s)),
+                        },
+                    };
+
+                    match n.as_str() {
+                        "stdin" => r.stdin = Some(Rc::new(open_file(&path, OpenOptions::new().read(true))?)),
+                        "stdout" => r.stdout = Some(Rc::new(open_output(&path, append)?)),
+                        "stderr" => r.stderr = Some(ErrorRedirection::File(Rc::new(open_output(&path, append)?))),
+                        _ => return Err(RedirectionError::new_invalid_redirection(s)),
+                    }
+                },
+                AstSwitch::Choice(n, v) if n == "stderr" => match v.as_ref() {
+                    AstArgument::Name(AstName::Name(m)) if m == "merge" => r.stderr = Some(ErrorRedirection::Merge),
+                    _ => return Err(RedirectionError::new_invalid_redirection(s)),
+                },
+                AstSwitch::On(n) if n == "append" => {},
+                _ => return Err(RedirectionError::new_invalid_redirection(s)),
+            }
+        }
+
+        Ok(r)
+    }
+
+
+    /// Applies the redirections to the given standard streams.
+    pub fn apply(&self, streams: &StandardStreams) -> StandardStreams {
+        let stdout = match &self.stdout {
+            Some(f) => OutputStream::File(f.clone()),
+            None => streams.stdout.clone(),
+        };
+
+        StandardStreams {
+            stdin: match &self.stdin {
+                Some(f) => InputStream::File(f.clone()),
+                None => streams.stdin.clone(),
+            },
+            stderr: match &self.stderr {
+                Some(ErrorRedirection::File(f)) => OutputStream::File(f.clone()),
+                Some(ErrorRedirection::Merge) => stdout.clone(),
+                None => streams.stderr.clone(),
+            },
+            stdout,
+        }
+    }
+
+    /// Reads the redirected input as a stream of `str` lines, or of `u1`
+    /// bytes.
+    pub fn input_stream(&self, bytes: bool) -> Result<Option<ValueStream>, Error> {
+        let f = match &self.stdin {
+            Some(f) => f.try_clone()?,
+            None => return Ok(None),
+        };
+
+        Ok(Some(match bytes {
+            true => ValueStream::new(BufReader::new(f).bytes().map(|b| b.map(Value::U1).map_err(Error::from))),
+            false => ValueStream::new(BufReader::new(f).lines().map(|l| l.map(Value::Str).map_err(Error::from))),
+        }))
+    }
+
+}
+
+
+
+/// Separates the redirections from the other arguments of a command : the
+/// `stdin`, `stdout` and `stderr` options, the `stderr` choice, and the
+/// `/append` flag if an output is redirected into a file. The switches whose
+/// name is declared by the command, as told by `declared`, are left to it, as
+/// are the arguments following a `--` word (see `ExternalCommand`).
+pub fn split_redirections(args: Vec<AstArgument>, declared: impl Fn(&str) -> bool) -> (Vec<AstArgument>, Vec<AstSwitch>) {
+    let is_redirection = |s: &AstSwitch| match s {
+        AstSwitch::Option(n, _) | AstSwitch::Choice(n, _) => is_stream(n) && !declared(n),
+        _ => false,
+    };
+    let end = args.iter().position(is_end_of_flags).unwrap_or(args.len());
+    let writes = args[..end].iter().any(|a| matches!(a,
+        AstArgument::Switch(s @ AstSwitch::Option(n, _)) if (n == "stdout" || n == "stderr") && is_redirection(s)
+    ));
+    let append = writes && !declared("append");
+    let mut redirections = Vec::new();
+    let mut others = Vec::with_capacity(args.len());
+
+    for (i, a) in args.into_iter().enumerate() {
+        match a {
+            a if i >= end => others.push(a),
+            AstArgument::Switch(s) if is_redirection(&s) => redirections.push(s),
+            AstArgument::Switch(AstSwitch::On(n)) if append && n == "append" => redirections.push(AstSwitch::On(n)),
+            a => others.push(a),
+        }
+    }
+
+    (others, redirections)
+}
+
+/// Checks if an argument is the `--` word ending the redirections.
+fn is_end_of_flags(arg: &AstArgument) -> bool {
+    matches!(arg, AstArgument::Name(AstName::Name(n)) if n == END_OF_FLAGS)
+}
+
+/// Checks if a switch name is the name of a standard stream.
+fn is_stream(name: &str) -> bool {
+    name == "stdin" || name == "stdout" || name == "stderr"
+}
+
+/// Opens a file written as output.
+fn open_output(path: &Path, append: bool) -> Result<File, Error> {
+    open_file(path, OpenOptions::new().create(true).write(true).append(append).truncate(!append))
+}
+
+/// Opens a file with the given options.
+fn open_file(path: &Path, options: &OpenOptions) -> Result<File, Error> {
+    options.open(path).map_err(|e| RedirectionError::new_open(&path.to_string_lossy(), &e))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neoshell::builtins::tests::{output, run, run_in};
+    use crate::neoshell::vm::*;
+
+    #[test]
+    fn outputs_are_written_into_files() {
+        assert_eq!(output("puts \"a\" stdout = $out;").unwrap(), "a\n");
+        assert_eq!(output("echo b stdout = $out;").unwrap(), "b\n");
+        assert_eq!(output("puts \"a\" stdout = $out; echo b stdout = $out /append;").unwrap(), "a\nb\n");
+    }
+
+    #[test]
+    fn error_outputs_are_written_into_files_or_merged() {
+        assert_eq!(output("sh \"-c\" \"echo e >&2\" stderr = $out;").unwrap(), "e\n");
+        assert_eq!(output("sh \"-c\" \"echo o; echo e >&2\" stdout = $out stderr -> merge;").unwrap(), "o\ne\n");
+    }
+
+    #[test]
+    fn inputs_are_read_from_files() {
+        let mut scp = Scope::new();
+        let path = std::env::temp_dir().join(format!("neoshell-input-{}", std::process::id()));
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        scp.declare("input", Variable::Value(Value::Str(path.to_string_lossy().into_owned())));
+
+        let count = run_in(&mut scp, "count stdin = $input;").map(|v| v.to_string());
+        let piped = run_in(&mut scp, "cat stdin = $input |> count;").map(|v| v.to_string());
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(count.unwrap(), "3");
+        assert_eq!(piped.unwrap(), "3");
+    }
+
+    #[test]
+    fn invalid_redirections_are_rejected() {
+        let e = run("puts \"a\" stderr -> nowhere;").err().unwrap();
+        assert!(matches!(e.downcast_ref::<RedirectionError>(), Some(RedirectionError::InvalidRedirection { .. })));
+
+        let e = run("puts \"a\" stdout = \"/neoshell-missing-directory/out\";").err().unwrap();
+        assert!(matches!(e.downcast_ref::<RedirectionError>(), Some(RedirectionError::Open { .. })));
+    }
+
+    #[test]
+    fn declared_switches_are_left_to_the_command() {
+        let args = ns_parser::file("f stdout = a stdin = b /append -- stderr = c;").unwrap().remove(0).arguments;
+        let (others, redirections) = split_redirections(args, |n| n == "stdin");
+
+        assert_eq!(redirections.iter().map(|s| s.to_string()).collect::<Vec<_>>(), ["stdout = a", "/append"]);
+        assert_eq!(others.len(), 3);
+    }
+}
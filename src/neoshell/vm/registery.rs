@@ -1,19 +1,112 @@
+#![deny(missing_docs)]
+//! Registery of the commands and resolution of their overloads.
 
+use std::fmt;
 
 use std::collections::HashMap;
 
+use failure::*;
+
+use crate::neoshell::parser::*;
+use crate::neoshell::tools::*;
+
 use super::command::*;
+use super::scope::*;
 
 
 /// Registery of commands. All commands are registered in global scope.
 ///
+/// A command name can be registered several times with different descriptors
+/// in any of the compile time, macro and runtime tables. The implementation
+/// used for a given call is chosen by `resolve`, from the arguments given to
+/// the command.
 pub struct CommandRegistery {
-    ct_commands: HashMap<String, Box<dyn CompileTimeCommand>>,
-    macros: HashMap<String, Box<dyn MacroCommand>>,
-    commands: HashMap<String, Box<dyn RuntimeCommand>>,
+    ct_commands: HashMap<String, Vec<Overload<dyn CompileTimeCommand>>>,
+    macros: HashMap<String, Vec<Overload<dyn MacroCommand>>>,
+    commands: HashMap<String, Vec<Overload<dyn RuntimeCommand>>>,
+}
+
+/// Implementation of a command with the descriptor of its arguments.
+pub struct Overload<T: ?Sized> {
+    /// Descriptor of the arguments. Implementations without descriptor accept
+    /// any argument, and are chosen only when no described implementation
+    /// matches.
+    pub descriptor: Option<AstDescriptor>,
+    command: Option<Box<T>>,
+}
+
+/// Overload chosen for a command call.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolution {
+    /// Table of the overload.
+    pub time: AstTime,
+    /// Index of the overload in the table.
+    pub index: usize,
+}
+
+/// Errors related to the registration and the resolution of commands.
+#[derive(Debug)]
+pub enum RegisteryError {
+    /// No command with the given name exists.
+    NotFound {
+        /// Name of the command.
+        name: String
+    },
+    /// No overload matches the given arguments.
+    NoMatch {
+        /// Name of the command.
+        name: String,
+        /// Candidates, with the reason they don't match.
+        candidates: String,
+    },
+    /// Several overloads match the given arguments with the same precision.
+    Ambiguous {
+        /// Name of the command.
+        name: String,
+        /// Equally matching candidates.
+        candidates: String,
+    },
+    /// An overload with the same descriptor is already registered.
+    AlreadyRegistered {
+        /// Descriptor of the command.
+        name: String
+    },
+    /// A descriptor couldn't be parsed.
+    InvalidDescriptor {
+        /// Source of the descriptor.
+        descriptor: String,
+        /// Parsing error.
+        msg: String,
+    },
 }
 
 
+
+impl RegisteryError {
+    /// Creates a new `NotFound` error.
+    pub fn new_not_found(name: &str) -> Error {
+        Error::from(RegisteryError::NotFound {
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for RegisteryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisteryError::NotFound { name } => write!(f, "Command '{}' not found.", name),
+            RegisteryError::NoMatch { name, candidates } => write!(f, "No overload of '{}' matches the given arguments. Candidates are :{}", name, candidates),
+            RegisteryError::Ambiguous { name, candidates } => write!(f, "Call to '{}' is ambiguous. Candidates are :{}", name, candidates),
+            RegisteryError::AlreadyRegistered { name } => write!(f, "Command `{}` is already registered.", name),
+            RegisteryError::InvalidDescriptor { descriptor, msg } => write!(f, "Invalid descriptor `{}` : {}.", descriptor, msg),
+        }
+    }
+}
+
+impl Fail for RegisteryError {}
+
+
+
 impl CommandRegistery {
 
     /// Constructs a new registery with no commands.
@@ -26,69 +119,399 @@ impl CommandRegistery {
     }
 
 
-    /// Registers a compile time command.
-    pub fn register_ct(&mut self, name: &String, cmd: impl CompileTimeCommand + 'static) {
-        self.ct_commands.insert(name.clone(), Box::new(cmd));
+    /// Registers a compile time command accepting any argument.
+    pub fn register_ct(&mut self, name: &str, cmd: impl CompileTimeCommand + 'static) {
+        self.register_ct_boxed(name, Box::new(cmd));
+    }
+    /// Registers a macro command accepting any argument.
+    pub fn register_macro(&mut self, name: &str, cmd: impl MacroCommand + 'static) {
+        self.register_macro_boxed(name, Box::new(cmd));
+    }
+    /// Registers a runtime command accepting any argument.
+    pub fn register(&mut self, name: &str, cmd: impl RuntimeCommand + 'static) {
+        self.register_boxed(name, Box::new(cmd));
+    }
+    /// Registers a compile time command accepting any argument.
+    pub fn register_ct_boxed(&mut self, name: &str, cmd: Box<dyn CompileTimeCommand>) {
+        replace_undescribed(&mut self.ct_commands, name, cmd);
+    }
+    /// Registers a macro command accepting any argument.
+    pub fn register_macro_boxed(&mut self, name: &str, cmd: Box<dyn MacroCommand>) {
+        replace_undescribed(&mut self.macros, name, cmd);
+    }
+    /// Registers a runtime command accepting any argument.
+    pub fn register_boxed(&mut self, name: &str, cmd: Box<dyn RuntimeCommand>) {
+        replace_undescribed(&mut self.commands, name, cmd);
+    }
+
+
+    /// Registers a compile time command with the given descriptor source
+    /// (`'name <arg type> ...;`).
+    pub fn register_ct_described(&mut self, desc: &str, cmd: impl CompileTimeCommand + 'static) -> Result<(), Error> {
+        self.register_ct_with(parse_descriptor(desc)?, Box::new(cmd))
+    }
+    /// Registers a macro command with the given descriptor source.
+    pub fn register_macro_described(&mut self, desc: &str, cmd: impl MacroCommand + 'static) -> Result<(), Error> {
+        self.register_macro_with(parse_descriptor(desc)?, Box::new(cmd))
+    }
+    /// Registers a runtime command with the given descriptor source.
+    pub fn register_described(&mut self, desc: &str, cmd: impl RuntimeCommand + 'static) -> Result<(), Error> {
+        self.register_with(parse_descriptor(desc)?, Box::new(cmd))
+    }
+    /// Registers a compile time command with the given descriptor.
+    pub fn register_ct_with(&mut self, desc: AstDescriptor, cmd: Box<dyn CompileTimeCommand>) -> Result<(), Error> {
+        insert_described(&mut self.ct_commands, desc, cmd)
+    }
+    /// Registers a macro command with the given descriptor.
+    pub fn register_macro_with(&mut self, desc: AstDescriptor, cmd: Box<dyn MacroCommand>) -> Result<(), Error> {
+        insert_described(&mut self.macros, desc, cmd)
+    }
+    /// Registers a runtime command with the given descriptor.
+    pub fn register_with(&mut self, desc: AstDescriptor, cmd: Box<dyn RuntimeCommand>) -> Result<(), Error> {
+        insert_described(&mut self.commands, desc, cmd)
+    }
+
+
+    /// Checks if a compile time command with the given name exists.
+    pub fn has_ct_command(&self, name: &String) -> bool {
+        self.ct_commands.contains_key(name)
+    }
+    /// Checks if a macro command with the given name exists.
+    pub fn has_macro(&self, name: &String) -> bool {
+        self.macros.contains_key(name)
+    }
+    /// Checks if a runtime command with the given name exists.
+    pub fn has_command(&self, name: &String) -> bool {
+        self.commands.contains_key(name)
+    }
+
+
+    /// Gets the overloads of the given compile time command.
+    pub fn get_ct_overloads(&self, name: &String) -> &[Overload<dyn CompileTimeCommand>] {
+        self.ct_commands.get(name).map_or(&[], |v| v.as_slice())
     }
-    /// Registers a macro command.
-    pub fn register_macro(&mut self, name: &String, cmd: impl MacroCommand + 'static) {
-        self.macros.insert(name.clone(), Box::new(cmd));
+    /// Gets the overloads of the given macro command.
+    pub fn get_macro_overloads(&self, name: &String) -> &[Overload<dyn MacroCommand>] {
+        self.macros.get(name).map_or(&[], |v| v.as_slice())
     }
-    /// Registers a runtime command.
-    pub fn register(&mut self, name: &String, cmd: impl RuntimeCommand + 'static) {
-        self.commands.insert(name.clone(), Box::new(cmd));
+    /// Gets the overloads of the given runtime command.
+    pub fn get_overloads(&self, name: &String) -> &[Overload<dyn RuntimeCommand>] {
+        self.commands.get(name).map_or(&[], |v| v.as_slice())
     }
-    /// Registers a compile time command.
-    pub fn register_ct_boxed(&mut self, name: &String, cmd: Box<dyn CompileTimeCommand>) {
-        self.ct_commands.insert(name.clone(), cmd);
+
+    /// Checks if a descriptor of any overload of the given command declares
+    /// an argument with the given name.
+    pub fn declares_argument(&self, name: &String, arg: &str) -> bool {
+        let ct = self.get_ct_overloads(name).iter().map(|o| &o.descriptor);
+        let macros = self.get_macro_overloads(name).iter().map(|o| &o.descriptor);
+        let commands = self.get_overloads(name).iter().map(|o| &o.descriptor);
+
+        ct.chain(macros).chain(commands)
+            .filter_map(|d| d.as_ref())
+            .flat_map(|d| d.arguments.iter())
+            .any(|i| match i {
+                AstDescriptorItem::Static(_) => false,
+                AstDescriptorItem::Mandatory(d) => d.name == arg,
+                AstDescriptorItem::Optional(v) => v.iter().any(|d| d.name == arg),
+            })
     }
-    /// Registers a macro command.
-    pub fn register_macro_boxed(&mut self, name: &String, cmd: Box<dyn MacroCommand>) {
-        self.macros.insert(name.clone(), cmd);
+
+
+    /// Gets the given macro command overload.
+    pub fn get_macro(&self, name: &String, index: usize) -> Option<&dyn MacroCommand> {
+        self.get_macro_overloads(name).get(index).and_then(|o| o.get_command())
     }
-    /// Registers a runtime command.
-    pub fn register_boxed(&mut self, name: &String, cmd: Box<dyn RuntimeCommand>) {
-        self.commands.insert(name.clone(), cmd);
+    /// Gets the given runtime command overload.
+    pub fn get_command(&self, name: &String, index: usize) -> Option<&dyn RuntimeCommand> {
+        self.get_overloads(name).get(index).and_then(|o| o.get_command())
     }
 
 
-    /// Gets the given compile time command.
-    pub fn get_ct_command_mut(&mut self, name: &String) -> Option<&mut Box<dyn CompileTimeCommand>> {
+    /// Takes the given compile time command overload out of the registery, so
+    /// it can be executed with a mutable access to the VM. It has to be put
+    /// back with `restore_ct_command`.
+    pub fn take_ct_command(&mut self, name: &String, index: usize) -> Option<Box<dyn CompileTimeCommand>> {
         self.ct_commands.get_mut(name)
+            .and_then(|v| v.get_mut(index))
+            .and_then(|o| o.command.take())
     }
-    /// Gets the given macro command.
-    pub fn get_macro_mut(&mut self, name: &String) -> Option<&mut Box<dyn MacroCommand>> {
-        self.macros.get_mut(name)
+    /// Puts back a compile time command taken with `take_ct_command`.
+    pub fn restore_ct_command(&mut self, name: &String, index: usize, cmd: Box<dyn CompileTimeCommand>) {
+        if let Some(o) = self.ct_commands.get_mut(name).and_then(|v| v.get_mut(index)) {
+            o.command = Some(cmd);
+        }
     }
-    /// Gets the given runtime command.
-    pub fn get_command_mut(&mut self, name: &String) -> Option<&mut Box<dyn RuntimeCommand>> {
-        self.commands.get_mut(name)
+
+
+
+    /// Resolves the overload of a command to call with the given arguments,
+    /// among the given tables.
+    ///
+    /// The overload whose descriptor matches the arguments with the best
+    /// precision is chosen. Overloads without descriptor are used only if no
+    /// described overload matches. An error listing the candidates is
+    /// returned if no overload matches, or if several match equally.
+    pub fn resolve(&self, scp: &Scope, name: &String, times: &[AstTime], args: &[AstArgument]) -> Result<Resolution, Error> {
+        let mut best = self.best_matches(scp, name, times, args)?;
+
+        if best.len() == 1 {
+            return Ok(best.pop().unwrap());
+        }
+
+        let candidates: Vec<String> = best.iter()
+            .map(|r| format!("\n    ({}) {}", r.time, describe(name, self.get_descriptor(name, r))))
+            .collect();
+
+        Err(Error::from(RegisteryError::Ambiguous {
+            name: name.clone(),
+            candidates: candidates.concat(),
+        }))
     }
-    /// Gets the given compile time command.
-    pub fn get_ct_command(&self, name: &String) -> Option<&Box<dyn CompileTimeCommand>> {
-        self.ct_commands.get(name)
+    /// Gets the overloads matching the given arguments with the best precision,
+    /// among the given tables. An error listing the candidates is returned if
+    /// no overload matches.
+    pub fn best_matches(&self, scp: &Scope, name: &String, times: &[AstTime], args: &[AstArgument]) -> Result<Vec<Resolution>, Error> {
+        let mut best = Vec::new();
+        let mut best_precision = Precision::lowest();
+        let mut rejected: Vec<String> = Vec::new();
+        let mut found = false;
+
+        for time in times {
+            let descriptors: Vec<&Option<AstDescriptor>> = match time {
+                AstTime::CompileTime => self.get_ct_overloads(name).iter().map(|o| &o.descriptor).collect(),
+                AstTime::Macro => self.get_macro_overloads(name).iter().map(|o| &o.descriptor).collect(),
+                AstTime::Runtime => self.get_overloads(name).iter().map(|o| &o.descriptor).collect(),
+            };
+
+            for (index, desc) in descriptors.into_iter().enumerate() {
+                found = true;
+
+                let precision = match desc {
+                    None => Precision::lowest(),
+                    Some(d) => match DescriptorMatcher::new(d).matches(scp, args) {
+                        Ok(m) => m.precision,
+                        Err(e) => {
+                            rejected.push(format!("\n    ({}) {} : {}", time, d, e));
+                            continue;
+                        },
+                    },
+                };
+
+                let res = Resolution {
+                    time: time.clone(),
+                    index,
+                };
+
+                if best.is_empty() || precision > best_precision {
+                    best = vec![res];
+                    best_precision = precision;
+                } else if precision == best_precision {
+                    best.push(res);
+                }
+            }
+        }
+
+        if !found {
+            return Err(RegisteryError::new_not_found(name));
+        }
+        if best.is_empty() {
+            return Err(Error::from(RegisteryError::NoMatch {
+                name: name.clone(),
+                candidates: rejected.concat(),
+            }));
+        }
+
+        Ok(best)
     }
-    /// Gets the given macro command.
-    pub fn get_macro(&self, name: &String) -> Option<&Box<dyn MacroCommand>> {
-        self.macros.get(name)
+
+
+    /// Gets the descriptor of a resolved overload.
+    pub fn get_descriptor(&self, name: &String, res: &Resolution) -> Option<&AstDescriptor> {
+        let desc = match res.time {
+            AstTime::CompileTime => self.get_ct_overloads(name).get(res.index).map(|o| &o.descriptor),
+            AstTime::Macro => self.get_macro_overloads(name).get(res.index).map(|o| &o.descriptor),
+            AstTime::Runtime => self.get_overloads(name).get(res.index).map(|o| &o.descriptor),
+        };
+
+        desc.and_then(|d| d.as_ref())
     }
-    /// Gets the given runtime command.
-    pub fn get_command(&self, name: &String) -> Option<&Box<dyn RuntimeCommand>> {
-        self.commands.get(name)
+
+}
+
+impl Default for CommandRegistery {
+    fn default() -> CommandRegistery {
+        CommandRegistery::new()
     }
+}
+
 
+impl<T: ?Sized> Overload<T> {
 
-    /// Gets the given compile time command.
-    pub fn get_ct_command_move(&mut self, name: &String) -> Option<Box<dyn CompileTimeCommand>> {
-        self.ct_commands.remove(name)
+    /// Gets the implementation of the overload. It is missing while a compile
+    /// time command is being executed.
+    pub fn get_command(&self) -> Option<&T> {
+        self.command.as_deref()
     }
-    /// Gets the given macro command.
-    pub fn get_macro_move(&mut self, name: &String) -> Option<Box<dyn MacroCommand>> {
-        self.macros.remove(name)
+
+}
+
+
+
+/// Parses a descriptor source.
+fn parse_descriptor(desc: &str) -> Result<AstDescriptor, Error> {
+    ns_parser::descriptor(desc).map_err(|e| Error::from(RegisteryError::InvalidDescriptor {
+        descriptor: desc.to_owned(),
+        msg: e.to_string(),
+    }))
+}
+
+/// Registers an overload without descriptor, replacing the previous one.
+fn replace_undescribed<T: ?Sized>(table: &mut HashMap<String, Vec<Overload<T>>>, name: &str, cmd: Box<T>) {
+    let overloads = table.entry(name.to_owned()).or_default();
+    let overload = Overload {
+        descriptor: None,
+        command: Some(cmd),
+    };
+
+    match overloads.iter().position(|o| o.descriptor.is_none()) {
+        Some(i) => overloads[i] = overload,
+        None => overloads.push(overload),
     }
-    /// Gets the given runtime command.
-    pub fn get_command_move(&mut self, name: &String) -> Option<Box<dyn RuntimeCommand>> {
-        self.commands.remove(name)
+}
+
+/// Registers an overload with a descriptor, failing if the same descriptor is
+/// already registered.
+fn insert_described<T: ?Sized>(table: &mut HashMap<String, Vec<Overload<T>>>, desc: AstDescriptor, cmd: Box<T>) -> Result<(), Error> {
+    let desc = merge_choices(&desc)?;
+    check_positional_lists(&desc)?;
+
+    let overloads = table.entry(desc.name.clone()).or_default();
+
+    if overloads.iter().any(|o| o.descriptor.as_ref() == Some(&desc)) {
+        return Err(Error::from(RegisteryError::AlreadyRegistered {
+            name: desc.to_string(),
+        }));
     }
 
+    overloads.push(Overload {
+        descriptor: Some(desc),
+        command: Some(cmd),
+    });
+    Ok(())
+}
+
+/// Checks that a descriptor has at most one positional list, as the
+/// positional arguments couldn't be split between several lists.
+fn check_positional_lists(desc: &AstDescriptor) -> Result<(), Error> {
+    let lists = desc.arguments.iter()
+        .flat_map(|i| match i {
+            AstDescriptorItem::Static(_) => Vec::new(),
+            AstDescriptorItem::Mandatory(d) => vec![d],
+            AstDescriptorItem::Optional(v) => v.iter().collect(),
+        })
+        .filter(|d| matches!(d.kind, AstArgumentKind::PositionalList(_, _)))
+        .count();
+
+    if lists > 1 {
+        return Err(Error::from(RegisteryError::InvalidDescriptor {
+            descriptor: desc.to_string(),
+            msg: "only one positional list is allowed".to_owned(),
+        }));
+    }
+
+    Ok(())
+}
+
+/// Describes an overload, for candidate listings.
+fn describe(name: &String, desc: Option<&AstDescriptor>) -> String {
+    match desc {
+        Some(d) => d.to_string(),
+        None => format!("'{} ...;", name),
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neoshell::vm::*;
+
+    struct NopCommand { }
+
+    impl RuntimeCommand for NopCommand {
+        fn execute(&self, _vm: &VM, _scp: &mut Scope, _cmd: &AstCommand) -> Result<Value, Error> {
+            Ok(Value::Void)
+        }
+    }
+
+    #[test]
+    fn several_positional_lists_are_rejected() {
+        let mut reg = CommandRegistery::new();
+
+        let e = reg.register_described("'f <a... str> <b... str>;", NopCommand{}).err().unwrap();
+
+        assert!(matches!(e.downcast_ref::<RegisteryError>(), Some(RegisteryError::InvalidDescriptor { .. })));
+        assert!(!reg.has_command(&"f".to_owned()));
+    }
+
+    fn arguments(src: &str) -> Vec<AstArgument> {
+        ns_parser::file(src).unwrap().remove(0).arguments
+    }
+
+    fn resolve(reg: &CommandRegistery, src: &str) -> Result<Resolution, Error> {
+        reg.resolve(&Scope::new(), &"f".to_owned(), &[AstTime::Runtime], &arguments(src))
+    }
+
+    #[test]
+    fn most_precise_overload_is_chosen() {
+        let mut reg = CommandRegistery::new();
+        reg.register_described("'f <a i8>;", NopCommand{}).unwrap();
+        reg.register_described("'f <a i4>;", NopCommand{}).unwrap();
+        reg.register_described("'f <a i4> [/b];", NopCommand{}).unwrap();
+
+        assert_eq!(resolve(&reg, "f 1;").unwrap().index, 1);
+        assert_eq!(resolve(&reg, "f 1 /b;").unwrap().index, 2);
+    }
+
+    #[test]
+    fn equally_precise_overloads_are_ambiguous() {
+        let mut reg = CommandRegistery::new();
+        reg.register_described("'f <a i8>;", NopCommand{}).unwrap();
+        reg.register_described("'f <a i2>;", NopCommand{}).unwrap();
+
+        let e = resolve(&reg, "f 1;").err().unwrap();
+
+        assert!(matches!(e.downcast_ref::<RegisteryError>(), Some(RegisteryError::Ambiguous { .. })));
+    }
+
+    #[test]
+    fn undescribed_overloads_are_chosen_last() {
+        let mut reg = CommandRegistery::new();
+        reg.register("f", NopCommand{});
+        reg.register_described("'f <a i4>;", NopCommand{}).unwrap();
+
+        assert_eq!(resolve(&reg, "f 1;").unwrap().index, 1);
+        assert_eq!(resolve(&reg, "f 1 2;").unwrap().index, 0);
+    }
+
+    #[test]
+    fn unmatched_arguments_are_errors() {
+        let mut reg = CommandRegistery::new();
+        reg.register_described("'f <a i4>;", NopCommand{}).unwrap();
+
+        let e = resolve(&reg, "f 1 2;").err().unwrap();
+
+        assert!(matches!(e.downcast_ref::<RegisteryError>(), Some(RegisteryError::NoMatch { .. })));
+    }
+
+    #[test]
+    fn declared_arguments_are_found_in_every_overload() {
+        let mut reg = CommandRegistery::new();
+        reg.register_described("'f <a i4>;", NopCommand{}).unwrap();
+        reg.register_described("'f [stdout = str];", NopCommand{}).unwrap();
+
+        assert!(reg.declares_argument(&"f".to_owned(), "stdout"));
+        assert!(!reg.declares_argument(&"f".to_owned(), "stderr"));
+    }
 }
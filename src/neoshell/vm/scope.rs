@@ -1,45 +1,524 @@
 #![deny(missing_docs)]
+//! Variable scopes.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::rc::{Rc, Weak};
 
+use failure::*;
+
+use super::value::*;
 use super::variable::*;
 
 
 
 /// Script's scope.
-pub struct Scope<'p> {
-    parent: Option<&'p mut Scope<'p>>,
-    variables: HashMap<String, Variable>,
+///
+/// A scope is a shared handle : cloning it gives another handle to the same
+/// variables. This is how closures capture the scope they are created in.
+/// A closure stored in the scope it captures holds it weakly instead, so the
+/// scope and the closure don't keep each other alive. The closure holds it
+/// strongly again once read out of the scope.
+///
+/// Under the root scope lies the environment of the process, whose variables
+/// are readable as `str` variables and assignable, unless they are shadowed
+/// by script variables. The environment of the programs run by the script is
+/// this environment, overridden by the exported script variables visible from
+/// the scope running them : the nearest exported variable wins, and a script
+/// variable which isn't exported doesn't hide an environment variable from
+/// the programs.
+#[derive(Clone)]
+pub struct Scope {
+    inner: Rc<RefCell<ScopeData>>,
+}
+
+/// Scope held weakly, see `Scope`.
+#[derive(Clone)]
+pub struct WeakScope {
+    inner: Weak<RefCell<ScopeData>>,
+}
+
+/// Content of a scope.
+struct ScopeData {
+    parent: Option<Scope>,
+    variables: HashMap<String, Binding>,
+    environment: HashMap<String, String>,
+}
+
+/// Variable declared in a scope, with its declaration informations.
+struct Binding {
+    variable: Variable,
+    ty: Type,
+    constant: bool,
+    exported: bool,
+}
+
+/// Errors related to scopes.
+#[derive(Debug)]
+pub enum ScopeError {
+    /// The variable is not declared in any of the scopes.
+    UndeclaredVariable {
+        /// Name of the variable.
+        name: String
+    },
+    /// The variable is a constant and can't be assigned.
+    ConstantAssignment {
+        /// Name of the variable.
+        name: String
+    },
+    /// The variable is a constant already declared in the same scope.
+    ConstantRedeclaration {
+        /// Name of the variable.
+        name: String
+    },
+    /// The scope captured by a block doesn't exist anymore.
+    ReleasedScope,
 }
 
 
 
-impl<'p> Scope<'p> {
+impl ScopeError {
+    /// Creates a new `UndeclaredVariable` error.
+    pub fn new_undeclared_variable(name: &str) -> Error {
+        Error::from(ScopeError::UndeclaredVariable {
+            name: name.to_owned(),
+        })
+    }
+    /// Creates a new `ConstantAssignment` error.
+    pub fn new_constant_assignment(name: &str) -> Error {
+        Error::from(ScopeError::ConstantAssignment {
+            name: name.to_owned(),
+        })
+    }
+    /// Creates a new `ConstantRedeclaration` error.
+    pub fn new_constant_redeclaration(name: &str) -> Error {
+        Error::from(ScopeError::ConstantRedeclaration {
+            name: name.to_owned(),
+        })
+    }
+    /// Creates a new `ReleasedScope` error.
+    pub fn new_released_scope() -> Error {
+        Error::from(ScopeError::ReleasedScope)
+    }
+}
+
+impl fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScopeError::UndeclaredVariable { name } => write!(f, "Variable '${}' is not declared.", name),
+            ScopeError::ConstantAssignment { name } => write!(f, "Variable '${}' is a constant and can't be assigned.", name),
+            ScopeError::ConstantRedeclaration { name } => write!(f, "Constant '${}' is already declared in this scope.", name),
+            ScopeError::ReleasedScope => write!(f, "The scope captured by the block doesn't exist anymore."),
+        }
+    }
+}
 
-    /// Creates a new scope
-    pub fn new() -> Scope<'p> {
+impl Fail for ScopeError {}
+
+
+
+impl Scope {
+
+    /// Creates a new root scope, above the environment of the process.
+    /// Environment variables which aren't valid UTF-8 are ignored.
+    pub fn new() -> Scope {
+        let env = env::vars_os()
+            .filter_map(|(n, v)| Some((n.into_string().ok()?, v.into_string().ok()?)))
+            .collect();
+
+        Scope::with_environment(env)
+    }
+    /// Creates a new root scope, above the given environment.
+    pub fn with_environment(env: HashMap<String, String>) -> Scope {
         Scope {
-            parent: None,
-            variables: HashMap::new(),
+            inner: Rc::new(RefCell::new(ScopeData {
+                parent: None,
+                variables: HashMap::new(),
+                environment: env,
+            })),
         }
     }
     /// Creates a new scope with a parent scope.
-    pub fn extends(p: &'p mut Scope<'p>) -> Scope<'p> {
+    pub fn extends(p: &Scope) -> Scope {
         Scope {
-            parent: Some(p),
-            variables: HashMap::new(),
+            inner: Rc::new(RefCell::new(ScopeData {
+                parent: Some(p.clone()),
+                variables: HashMap::new(),
+                environment: HashMap::new(),
+            })),
+        }
+    }
+
+
+    /// Gets the parent scope.
+    pub fn get_parent(&self) -> Option<Scope> {
+        self.inner.borrow().parent.clone()
+    }
+    /// Gets a weak handle to this scope.
+    pub fn downgrade(&self) -> WeakScope {
+        WeakScope {
+            inner: Rc::downgrade(&self.inner),
+        }
+    }
+    /// Checks if both handles give the same scope.
+    pub fn ptr_eq(&self, other: &Scope) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+
+
+
+    /// Declares a mutable variable in this scope, whose type is inferred from
+    /// its value. If a variable with the same name is already declared in this
+    /// scope or in a parent scope, it is shadowed.
+    pub fn declare(&self, name: &str, var: Variable) {
+        let ty = var.get_type();
+
+        self.inner.borrow_mut().variables.insert(name.to_owned(), Binding {
+            variable: self.detach(var),
+            ty,
+            constant: false,
+            exported: false,
+        });
+    }
+    /// Declares a variable in this scope. If `ty` is given, the value is
+    /// converted to that type, otherwise the type is inferred from the value.
+    /// Constant variables can't be assigned nor redeclared in the same scope
+    /// afterward.
+    pub fn declare_with(&self, name: &str, var: Variable, ty: Option<&Type>, constant: bool) -> Result<(), Error> {
+        if let Some(Binding { constant: true, .. }) = self.inner.borrow().variables.get(name) {
+            return Err(ScopeError::new_constant_redeclaration(name));
+        }
+
+        let var = match ty {
+            Some(t) => var.cast(t)?,
+            None => var,
+        };
+        let ty = match ty {
+            Some(t) => t.clone(),
+            None => var.get_type(),
+        };
+
+        self.inner.borrow_mut().variables.insert(name.to_owned(), Binding {
+            variable: self.detach(var),
+            ty,
+            constant,
+            exported: false,
+        });
+
+        Ok(())
+    }
+    /// Declares a variable of the given type without giving it a value. Used
+    /// by compile time checks.
+    pub fn declare_type(&self, name: &str, ty: Type) {
+        self.inner.borrow_mut().variables.insert(name.to_owned(), Binding {
+            variable: Variable::Value(Value::Void),
+            ty,
+            constant: false,
+            exported: false,
+        });
+    }
+    /// Assigns a new value to an already declared variable. The variable is
+    /// modified in the nearest scope declaring it, and the value is converted
+    /// to the variable's type.
+    pub fn assign(&self, name: &String, var: Variable) -> Result<(), Error> {
+        let mut data = self.inner.borrow_mut();
+
+        if let Some(b) = data.variables.get_mut(name) {
+            if b.constant {
+                return Err(ScopeError::new_constant_assignment(name));
+            }
+
+            b.variable = self.detach(var.cast(&b.ty)?);
+            return Ok(());
+        }
+
+        match &data.parent {
+            Some(p) => p.assign(name, var),
+            None if data.environment.contains_key(name) => {
+                let v = var.cast(&Type::Str)?.to_value().to_string();
+
+                data.environment.insert(name.clone(), v);
+                Ok(())
+            },
+            None => Err(ScopeError::new_undeclared_variable(name)),
+        }
+    }
+    /// Exports the given variable, giving it to the programs run in this
+    /// scope or in its child scopes. The variable is exported in the nearest
+    /// scope declaring it. Environment variables are always exported.
+    pub fn export(&self, name: &String) -> Result<(), Error> {
+        let mut data = self.inner.borrow_mut();
+
+        if let Some(b) = data.variables.get_mut(name) {
+            b.exported = true;
+            return Ok(());
+        }
+
+        match &data.parent {
+            Some(p) => p.export(name),
+            None if data.environment.contains_key(name) => Ok(()),
+            None => Err(ScopeError::new_undeclared_variable(name)),
+        }
+    }
+
+
+    /// Checks if the given variable is declared in this scope or in a parent
+    /// scope.
+    pub fn is_declared(&self, name: &String) -> bool {
+        let data = self.inner.borrow();
+
+        if data.variables.contains_key(name) {
+            return true;
+        }
+
+        match &data.parent {
+            Some(p) => p.is_declared(name),
+            None => data.environment.contains_key(name),
+        }
+    }
+    /// Checks if the given variable is declared in this scope, ignoring the
+    /// parent scopes.
+    pub fn is_declared_locally(&self, name: &String) -> bool {
+        self.inner.borrow().variables.contains_key(name)
+    }
+
+
+    /// Gets the given variable, looking up in the parent scopes if needed.
+    pub fn get_variable(&self, name: &String) -> Option<Variable> {
+        let data = self.inner.borrow();
+
+        if let Some(b) = data.variables.get(name) {
+            return Some(b.variable.clone().map_closures(&|c| c.attached()));
+        }
+
+        match &data.parent {
+            Some(p) => p.get_variable(name),
+            None => data.environment.get(name).map(|v| Variable::Value(Value::Str(v.clone()))),
+        }
+    }
+    /// Gets the declared type of the given variable.
+    pub fn get_variable_type(&self, name: &String) -> Option<Type> {
+        let data = self.inner.borrow();
+
+        if let Some(b) = data.variables.get(name) {
+            return Some(b.ty.clone());
+        }
+
+        match &data.parent {
+            Some(p) => p.get_variable_type(name),
+            None => data.environment.get(name).map(|_| Type::Str),
+        }
+    }
+    /// Checks if the given variable is a constant.
+    pub fn is_constant(&self, name: &String) -> Option<bool> {
+        let data = self.inner.borrow();
+
+        if let Some(b) = data.variables.get(name) {
+            return Some(b.constant);
+        }
+
+        match &data.parent {
+            Some(p) => p.is_constant(name),
+            None => data.environment.get(name).map(|_| false),
         }
     }
 
 
+    /// Gets the value given to the programs for the given environment
+    /// variable.
+    pub fn get_environment_variable(&self, name: &String) -> Option<String> {
+        let data = self.inner.borrow();
 
-    /// Gets the given variable.
-    pub fn get_variable(&self, name: &String) -> Option<&Variable> {
-        self.variables.get(name)
+        if let Some(Binding { variable: Variable::Value(v), exported: true, .. }) = data.variables.get(name) {
+            return Some(v.to_string());
+        }
+
+        match &data.parent {
+            Some(p) => p.get_environment_variable(name),
+            None => data.environment.get(name).cloned(),
+        }
     }
-    /// Gets the given variable.
-    pub fn get_variable_mut(&mut self, name: &String) -> Option<&mut Variable> {
-        self.variables.get_mut(name)
+    /// Gets the environment given to the programs.
+    pub fn get_environment(&self) -> HashMap<String, String> {
+        let data = self.inner.borrow();
+        let mut env = match &data.parent {
+            Some(p) => p.get_environment(),
+            None => data.environment.clone(),
+        };
+
+        for (n, b) in data.variables.iter().filter(|(_, b)| b.exported) {
+            if let Variable::Value(v) = &b.variable {
+                env.insert(n.clone(), v.to_string());
+            }
+        }
+
+        env
+    }
+
+
+    /// Makes the closures of a variable stored in this scope hold it weakly
+    /// if they capture it.
+    fn detach(&self, var: Variable) -> Variable {
+        var.map_closures(&|c| c.detached_from(self))
+    }
+
+}
+
+impl WeakScope {
+
+    /// Gets the scope, if it still exists.
+    pub fn upgrade(&self) -> Option<Scope> {
+        self.inner.upgrade().map(|inner| Scope { inner })
+    }
+
+}
+
+impl Default for Scope {
+    fn default() -> Scope {
+        Scope::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closure(scp: &Scope) -> Variable {
+        Variable::Value(Value::Closure(Closure::new(Vec::new(), scp)))
+    }
+
+    fn value_of(scp: &Scope, name: &str) -> Option<String> {
+        scp.get_variable(&name.to_owned()).map(|v| v.to_value().to_string())
     }
 
+    #[test]
+    fn variables_are_found_through_parent_scopes() {
+        let root = Scope::with_environment(HashMap::new());
+        let sub = Scope::extends(&root);
+        root.declare("a", Variable::Value(Value::I4(1)));
+        sub.declare("b", Variable::Value(Value::I4(2)));
+
+        assert_eq!(value_of(&sub, "a"), Some("1".to_owned()));
+        assert_eq!(value_of(&root, "b"), None);
+        assert!(sub.is_declared(&"a".to_owned()) && !sub.is_declared_locally(&"a".to_owned()));
+    }
+
+    #[test]
+    fn assignments_change_the_declaring_scope() {
+        let root = Scope::with_environment(HashMap::new());
+        let sub = Scope::extends(&root);
+        root.declare("a", Variable::Value(Value::I4(1)));
+
+        sub.assign(&"a".to_owned(), Variable::Value(Value::I4(2))).unwrap();
+        assert_eq!(value_of(&root, "a"), Some("2".to_owned()));
+        assert!(!sub.is_declared_locally(&"a".to_owned()));
+        assert!(sub.assign(&"b".to_owned(), Variable::Value(Value::I4(2))).is_err());
+    }
+
+    #[test]
+    fn declarations_shadow_parent_variables() {
+        let root = Scope::with_environment(HashMap::new());
+        let sub = Scope::extends(&root);
+        root.declare("a", Variable::Value(Value::I4(1)));
+        sub.declare("a", Variable::Value(Value::Str("s".to_owned())));
+
+        assert_eq!(value_of(&sub, "a"), Some("s".to_owned()));
+        assert_eq!(value_of(&root, "a"), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn assignments_keep_the_declared_type() {
+        let root = Scope::with_environment(HashMap::new());
+        root.declare_with("a", Variable::Value(Value::I4(1)), Some(&Type::I8), false).unwrap();
+
+        assert_eq!(root.get_variable_type(&"a".to_owned()), Some(Type::I8));
+        assert!(root.assign(&"a".to_owned(), Variable::Value(Value::Str("s".to_owned()))).is_err());
+        root.assign(&"a".to_owned(), Variable::Value(Value::I4(3))).unwrap();
+        assert_eq!(root.get_variable(&"a".to_owned()).map(|v| v.get_type()), Some(Type::I8));
+    }
+
+    #[test]
+    fn constants_cannot_be_assigned() {
+        let root = Scope::with_environment(HashMap::new());
+        root.declare_with("c", Variable::Value(Value::I4(1)), None, true).unwrap();
+
+        assert!(Scope::extends(&root).assign(&"c".to_owned(), Variable::Value(Value::I4(2))).is_err());
+        assert_eq!(root.is_constant(&"c".to_owned()), Some(true));
+    }
+
+    #[test]
+    fn constants_cannot_be_redeclared_in_the_same_scope() {
+        let root = Scope::with_environment(HashMap::new());
+        let name = "c".to_owned();
+        root.declare_with(&name, Variable::Value(Value::I4(1)), None, true).unwrap();
+
+        assert!(root.declare_with(&name, Variable::Value(Value::I4(2)), None, false).is_err());
+        assert!(Scope::extends(&root).declare_with(&name, Variable::Value(Value::I4(2)), None, false).is_ok());
+        assert_eq!(root.get_variable(&name).map(|v| v.to_value().to_string()), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn closures_stored_in_their_scope_do_not_keep_it_alive() {
+        let root = Scope::with_environment(HashMap::new());
+        let sub = Scope::extends(&root);
+        let name = "f".to_owned();
+        sub.declare(&name, closure(&sub));
+        sub.declare_with("g", Variable::Value(Value::Array(Type::Block, vec![closure(&sub).to_value()])), None, false).unwrap();
+
+        let read = match sub.get_variable(&name) {
+            Some(Variable::Value(Value::Closure(c))) => c,
+            _ => unreachable!(),
+        };
+        assert!(read.get_scope().map(|s| s.ptr_eq(&sub)) == Some(true));
+
+        let weak = sub.downgrade();
+        drop(sub);
+        assert!(weak.upgrade().is_some());
+
+        drop(read);
+        assert!(weak.upgrade().is_none());
+    }
+
+    fn environment() -> Scope {
+        let mut env = HashMap::new();
+        env.insert("HOME".to_owned(), "/home/me".to_owned());
+        Scope::with_environment(env)
+    }
+
+    #[test]
+    fn environment_variables_are_readable_and_assignable() {
+        let root = environment();
+        let home = "HOME".to_owned();
+
+        assert_eq!(value_of(&root, "HOME").as_deref(), Some("/home/me"));
+        assert!(root.get_variable_type(&home) == Some(Type::Str));
+
+        root.assign(&home, Variable::Value(Value::Str("/root".to_owned()))).unwrap();
+        assert_eq!(root.get_environment_variable(&home).as_deref(), Some("/root"));
+    }
+
+    #[test]
+    fn only_exported_variables_reach_the_programs() {
+        let root = environment();
+        let sub = Scope::extends(&root);
+        let home = "HOME".to_owned();
+
+        sub.declare("HOME", Variable::Value(Value::Str("/tmp".to_owned())));
+        assert_eq!(value_of(&sub, "HOME").as_deref(), Some("/tmp"));
+        assert_eq!(sub.get_environment_variable(&home).as_deref(), Some("/home/me"));
+
+        sub.export(&home).unwrap();
+        assert_eq!(sub.get_environment_variable(&home).as_deref(), Some("/tmp"));
+        assert_eq!(sub.get_environment().get("HOME").map(|v| v.as_str()), Some("/tmp"));
+        assert_eq!(root.get_environment().get("HOME").map(|v| v.as_str()), Some("/home/me"));
+    }
+
+    #[test]
+    fn undeclared_variables_cannot_be_exported() {
+        assert!(environment().export(&"missing".to_owned()).is_err());
+    }
 }
@@ -0,0 +1,185 @@
+#![deny(missing_docs)]
+//! Signals received by the shell, forwarded to the programs it runs.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+use failure::*;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+use super::machine::VMError;
+use super::value::*;
+
+
+/// Signal state of a VM : the processes receiving the signals forwarded by
+/// the shell, and the last signal received. Clones share the same state, so
+/// the thread receiving the signals can forward them to the VM.
+#[derive(Clone)]
+pub struct SignalForwarder {
+    inner: Arc<SignalState>,
+}
+
+/// State shared by the clones of a `SignalForwarder`.
+struct SignalState {
+    /// The negated process group ids of the pipelines running in the
+    /// foreground or in the background.
+    targets: Mutex<Vec<i32>>,
+    /// Last signal forwarded and not taken yet, `0` if none.
+    received: AtomicI32,
+}
+
+
+
+impl SignalForwarder {
+
+    /// Creates a new signal state, without processes nor signals.
+    pub fn new() -> SignalForwarder {
+        SignalForwarder {
+            inner: Arc::new(SignalState {
+                targets: Mutex::new(Vec::new()),
+                received: AtomicI32::new(0),
+            }),
+        }
+    }
+
+
+    /// Installs the signal policy of the shell : `SIGINT` and `SIGTERM` don't
+    /// terminate the shell anymore, but are forwarded to the programs running
+    /// in the foreground and to the process groups of the background jobs,
+    /// and interrupt the running script before its next command or its next
+    /// stream item.
+    ///
+    /// Applications embedding the VM and handling the signals themselves don't
+    /// need to install it, and can call `forward` instead.
+    pub fn install(&self) -> Result<(), Error> {
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let forwarder = self.clone();
+
+        thread::spawn(move || {
+            for s in signals.forever() {
+                forwarder.forward(s);
+            }
+        });
+
+        Ok(())
+    }
+    /// Forwards a signal to the running programs, and interrupts the running
+    /// script.
+    pub fn forward(&self, sig: i32) {
+        self.inner.received.store(sig, Ordering::SeqCst);
+
+        for t in self.targets().iter() {
+            unsafe {
+                libc::kill(*t, sig);
+            }
+        }
+    }
+
+
+    /// Takes the signal interrupting the running script, if any.
+    pub fn take(&self) -> Option<i32> {
+        match self.inner.received.swap(0, Ordering::SeqCst) {
+            0 => None,
+            s => Some(s),
+        }
+    }
+    /// Fails with an `Interrupted` error if a signal interrupts the running
+    /// script, taking the signal. Long running commands and streams call it
+    /// regularly, so the scripts stay interruptible.
+    pub fn check(&self) -> Result<(), Error> {
+        match self.take() {
+            Some(s) => Err(VMError::new_interrupted(s)),
+            None => Ok(()),
+        }
+    }
+    /// Creates a stream generating the items of the given iterator, which
+    /// fails once a signal interrupts the running script.
+    pub fn interruptible(&self, iter: impl Iterator<Item = Result<Value, Error>> + 'static) -> ValueStream {
+        let forwarder = self.clone();
+        let mut iter = iter;
+
+        ValueStream::new(std::iter::from_fn(move || match forwarder.check() {
+            Ok(()) => iter.next(),
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+
+    /// Registers a process receiving the forwarded signals : a process id, or
+    /// a negated process group id.
+    pub fn register(&self, target: i32) {
+        self.targets().push(target);
+    }
+    /// Unregisters a process registered with `register`.
+    pub fn unregister(&self, target: i32) {
+        let mut targets = self.targets();
+
+        if let Some(i) = targets.iter().position(|t| *t == target) {
+            targets.remove(i);
+        }
+    }
+
+    /// Locks the registered processes.
+    fn targets(&self) -> MutexGuard<'_, Vec<i32>> {
+        self.inner.targets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+}
+
+impl Default for SignalForwarder {
+    fn default() -> SignalForwarder {
+        SignalForwarder::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neoshell::builtins::*;
+    use crate::neoshell::parser::*;
+    use crate::neoshell::vm::*;
+
+    fn run(vm: &mut VM, src: &str) -> Result<Value, Error> {
+        let mut scp = Scope::new();
+        let prog = vm.compile(&mut scp, ns_parser::file(src)?)?;
+
+        vm.run(&mut scp, &prog)
+    }
+
+    fn new_vm() -> VM {
+        let mut vm = VM::new();
+        register_builtins(vm.get_registery_mut()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn signals_interrupt_only_their_vm() {
+        let mut interrupted = new_vm();
+        let mut other = new_vm();
+
+        interrupted.get_signals().forward(libc::SIGTERM);
+
+        assert!(run(&mut other, "value 1;").is_ok());
+        match run(&mut interrupted, "value 1;").err().map(|e| e.downcast::<VMError>()) {
+            Some(Ok(VMError::Interrupted { signal })) => assert_eq!(signal, libc::SIGTERM),
+            _ => panic!("Expected an interruption"),
+        }
+        assert!(run(&mut interrupted, "value 1;").is_ok());
+    }
+
+    #[test]
+    fn signals_interrupt_streams() {
+        let vm = new_vm();
+        let signals = vm.get_signals();
+        let mut s = signals.interruptible(ValueStream::from_values(vec![Value::I4(1), Value::I4(2)]));
+
+        assert!(matches!(s.next(), Some(Ok(_))));
+        signals.forward(libc::SIGINT);
+        assert!(matches!(s.next(), Some(Err(_))));
+        assert!(matches!(s.next(), Some(Ok(_))));
+    }
+}
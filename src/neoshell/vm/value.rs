@@ -0,0 +1,812 @@
+#![deny(missing_docs)]
+//! Runtime values and their types.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use failure::*;
+
+use crate::neoshell::parser::ast::*;
+
+use super::scope::{Scope, WeakScope};
+
+
+
+/// Type of a runtime value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    /// Signed 1 byte-long integer.
+    I1,
+    /// Signed 2 byte-long integer.
+    I2,
+    /// Signed 4 byte-long integer.
+    I4,
+    /// Signed 8 byte-long integer.
+    I8,
+    /// Signed integer large enough to contain a memory address.
+    IL,
+    /// Unsigned 1 byte-long integer.
+    U1,
+    /// Unsigned 2 byte-long integer.
+    U2,
+    /// Unsigned 4 byte-long integer.
+    U4,
+    /// Unsigned 8 byte-long integer.
+    U8,
+    /// Unsigned integer large enough to contain a memory address.
+    UL,
+    /// 4 byte-long float.
+    F4,
+    /// 8 byte-long float.
+    F8,
+    /// Boolean.
+    Bool,
+    /// String.
+    Str,
+    /// Path of a file.
+    Path,
+    /// No value.
+    Void,
+    /// Any type. Only usable by compile time and macro commands.
+    Generic,
+    /// Block of commands.
+    Block,
+    /// Command descriptor. Only usable by compile time and macro commands.
+    Descriptor,
+    /// Instantiated command.
+    Command,
+    /// Piece of AST. Only usable by compile time and macro commands.
+    Ast,
+    /// Array of values of the given type.
+    Array(Box<Type>),
+    /// Stream of values of the given type.
+    Stream(Box<Type>),
+}
+
+/// Runtime value.
+#[derive(Clone)]
+pub enum Value {
+    /// No value.
+    Void,
+    /// Signed 1 byte-long integer.
+    I1(i8),
+    /// Signed 2 byte-long integer.
+    I2(i16),
+    /// Signed 4 byte-long integer.
+    I4(i32),
+    /// Signed 8 byte-long integer.
+    I8(i64),
+    /// Signed integer large enough to contain a memory address.
+    IL(isize),
+    /// Unsigned 1 byte-long integer.
+    U1(u8),
+    /// Unsigned 2 byte-long integer.
+    U2(u16),
+    /// Unsigned 4 byte-long integer.
+    U4(u32),
+    /// Unsigned 8 byte-long integer.
+    U8(u64),
+    /// Unsigned integer large enough to contain a memory address.
+    UL(usize),
+    /// 4 byte-long float.
+    F4(f32),
+    /// 8 byte-long float.
+    F8(f64),
+    /// Boolean.
+    Bool(bool),
+    /// String.
+    Str(String),
+    /// Path of a file.
+    Path(PathBuf),
+    /// Array of values, with the type of its items.
+    Array(Type, Vec<Value>),
+    /// Stream of values, with the type of its items.
+    Stream(Type, ValueStream),
+    /// Block of commands.
+    Closure(Closure),
+    /// Instantiated command.
+    Command(Rc<InstancedCommand>),
+    /// Piece of AST, generated by macros.
+    Ast(Rc<AstValue>),
+}
+
+/// Piece of AST used as a value.
+#[derive(Clone)]
+pub enum AstValue {
+    /// A command.
+    Command(AstCommand),
+    /// An argument.
+    Argument(AstArgument),
+}
+
+/// Lazy stream of values. Cloning a stream shares it, meaning that the items
+/// are consumed only once.
+#[derive(Clone)]
+pub struct ValueStream {
+    iter: Rc<RefCell<StreamIterator>>,
+}
+
+/// Iterator generating the items of a stream.
+type StreamIterator = Box<dyn Iterator<Item = Result<Value, Error>>>;
+
+/// Block of commands used as a value, with the scope it was created in.
+#[derive(Clone)]
+pub struct Closure {
+    /// Commands of the block.
+    pub body: Rc<Vec<AstCommand>>,
+    scope: CapturedScope,
+}
+
+/// Scope captured by a closure. A closure stored in the scope it captures
+/// holds it weakly, see `Scope`.
+#[derive(Clone)]
+enum CapturedScope {
+    Strong(Scope),
+    Weak(WeakScope),
+}
+
+/// Command stored as a value, with partially applied arguments.
+pub struct InstancedCommand {
+    /// Command invoked.
+    pub target: CommandTarget,
+    /// Arguments bound to the command, passed before the arguments given at
+    /// invocation.
+    pub arguments: Vec<InstanceArgument>,
+}
+
+/// Argument bound to an instantiated command.
+#[derive(Clone)]
+pub enum InstanceArgument {
+    /// An argument kept as-is, e.g. a literal or a word.
+    Ast(AstArgument),
+    /// An evaluated argument, of any type.
+    Value(Value),
+}
+
+/// Command invoked by an instantiated command.
+#[derive(Clone)]
+pub enum CommandTarget {
+    /// A command registered in the VM.
+    Registered(String),
+    /// A block of commands, which receives its arguments through `$args`.
+    Closure(Closure),
+}
+
+/// Errors related to values.
+#[derive(Debug)]
+pub enum ValueError {
+    /// The AST node can't be converted to a value.
+    NotAValue {
+        /// Description of the AST node.
+        what: String
+    },
+    /// The value doesn't have the expected type and can't be converted to it.
+    TypeMismatch {
+        /// Expected type.
+        expected: Type,
+        /// Type of the value.
+        found: Type,
+    },
+    /// The value can't be converted to an AST literal.
+    NotALiteral {
+        /// Type of the value.
+        ty: Type
+    },
+}
+
+
+
+
+
+impl ValueError {
+    /// Creates a new `NotAValue` error.
+    pub fn new_not_a_value(what: &'static str) -> Error {
+        Error::from(ValueError::NotAValue {
+            what: what.to_owned(),
+        })
+    }
+    /// Creates a new `TypeMismatch` error.
+    pub fn new_type_mismatch(expected: &Type, found: Type) -> Error {
+        Error::from(ValueError::TypeMismatch {
+            expected: expected.clone(),
+            found,
+        })
+    }
+    /// Creates a new `NotALiteral` error.
+    pub fn new_not_a_literal(ty: Type) -> Error {
+        Error::from(ValueError::NotALiteral {
+            ty,
+        })
+    }
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueError::NotAValue { what } => write!(f, "Can't convert {} to a value.", what),
+            ValueError::TypeMismatch { expected, found } => write!(f, "Expected a value of type '{}', found '{}'.", expected, found),
+            ValueError::NotALiteral { ty } => write!(f, "Can't convert a value of type '{}' to an AST literal.", ty),
+        }
+    }
+}
+
+impl Fail for ValueError {}
+
+
+
+impl Type {
+
+    /// Gets the type from its name (`i4`, `str`, `[u1]`, `<str>`...).
+    pub fn from_name(name: &str) -> Option<Type> {
+        let name = name.trim();
+
+        if name.starts_with('[') && name.ends_with(']') {
+            return Type::from_name(&name[1..name.len()-1]).map(|t| Type::Array(Box::new(t)));
+        }
+        if name.starts_with('<') && name.ends_with('>') {
+            return Type::from_name(&name[1..name.len()-1]).map(|t| Type::Stream(Box::new(t)));
+        }
+
+        match name {
+            "i1" => Some(Type::I1),
+            "i2" => Some(Type::I2),
+            "i4" => Some(Type::I4),
+            "i8" => Some(Type::I8),
+            "iL" => Some(Type::IL),
+            "u1" => Some(Type::U1),
+            "u2" => Some(Type::U2),
+            "u4" => Some(Type::U4),
+            "u8" => Some(Type::U8),
+            "uL" => Some(Type::UL),
+            "f4" => Some(Type::F4),
+            "f8" => Some(Type::F8),
+            "bool" => Some(Type::Bool),
+            "str" => Some(Type::Str),
+            "path" => Some(Type::Path),
+            "void" => Some(Type::Void),
+            "generic" => Some(Type::Generic),
+            "block" => Some(Type::Block),
+            "descriptor" => Some(Type::Descriptor),
+            "command" => Some(Type::Command),
+            "ast" => Some(Type::Ast),
+            _ => None,
+        }
+    }
+
+
+    /// Checks if the type is an integer type.
+    pub fn is_integer(&self) -> bool {
+        matches!(self,
+            Type::I1 | Type::I2 | Type::I4 | Type::I8 | Type::IL |
+            Type::U1 | Type::U2 | Type::U4 | Type::U8 | Type::UL
+        )
+    }
+    /// Checks if the type is a float type.
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::F4 | Type::F8)
+    }
+    /// Checks if values of this type can be cast to the given type, see
+    /// `Value::cast`. Integers may still not fit the integer type.
+    pub fn is_convertible_to(&self, other: &Type) -> bool {
+        match (self, other) {
+            (a, b) if a == b => true,
+            (_, Type::Generic) | (Type::Generic, _) => true,
+            (Type::Array(a), Type::Array(b)) => a.is_convertible_to(b),
+            (Type::Str, Type::Path) | (Type::Path, Type::Str) => true,
+            (a, b) if a.is_integer() => b.is_integer() || b.is_float(),
+            (a, b) => a.is_float() && b.is_float(),
+        }
+    }
+
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::I1 => write!(f, "i1"),
+            Type::I2 => write!(f, "i2"),
+            Type::I4 => write!(f, "i4"),
+            Type::I8 => write!(f, "i8"),
+            Type::IL => write!(f, "iL"),
+            Type::U1 => write!(f, "u1"),
+            Type::U2 => write!(f, "u2"),
+            Type::U4 => write!(f, "u4"),
+            Type::U8 => write!(f, "u8"),
+            Type::UL => write!(f, "uL"),
+            Type::F4 => write!(f, "f4"),
+            Type::F8 => write!(f, "f8"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
+            Type::Path => write!(f, "path"),
+            Type::Void => write!(f, "void"),
+            Type::Generic => write!(f, "generic"),
+            Type::Block => write!(f, "block"),
+            Type::Descriptor => write!(f, "descriptor"),
+            Type::Command => write!(f, "command"),
+            Type::Ast => write!(f, "ast"),
+            Type::Array(t) => write!(f, "[{}]", t),
+            Type::Stream(t) => write!(f, "<{}>", t),
+        }
+    }
+}
+
+
+
+impl Value {
+
+    /// Converts an AST literal into a value.
+    ///
+    /// Words are converted to strings, except for `true` and `false` which are
+    /// converted to booleans. Variables, blocks and switches needs to be
+    /// evaluated by the VM and thus can't be converted.
+    pub fn from_ast(arg: &AstArgument) -> Result<Value, Error> {
+        match arg {
+            AstArgument::None       => Ok(Value::Void),
+            AstArgument::Integer(i) => Ok(Value::I4(*i)),
+            AstArgument::Float(f)   => Ok(Value::F4(*f)),
+            AstArgument::String(s)  => Ok(Value::Str(s.clone())),
+            AstArgument::Name(n)    => match n {
+                AstName::Name(w) if w == "true"  => Ok(Value::Bool(true)),
+                AstName::Name(w) if w == "false" => Ok(Value::Bool(false)),
+                AstName::Name(w)     => Ok(Value::Str(w.clone())),
+                AstName::Variable(_) => Err(ValueError::new_not_a_value("a variable")),
+                AstName::Placeholder => Err(ValueError::new_not_a_value("a placeholder")),
+                AstName::Block(_)    => Err(ValueError::new_not_a_value("a block")),
+            },
+            AstArgument::Switch(_)  => Err(ValueError::new_not_a_value("a switch")),
+            AstArgument::Block(_)   => Err(ValueError::new_not_a_value("a block")),
+            AstArgument::Descriptor(_) => Err(ValueError::new_not_a_value("a descriptor")),
+        }
+    }
+    /// Converts the value into an AST literal.
+    ///
+    /// Integers must fit in an `i4` literal. Booleans are converted to the
+    /// `true` and `false` words, and closures to argument blocks. Commands
+    /// built as AST are converted to evaluated blocks.
+    pub fn to_ast(&self) -> Result<AstArgument, Error> {
+        let int = |v: Option<i32>| match v {
+            Some(i) => Ok(AstArgument::Integer(i)),
+            None => Err(ValueError::new_not_a_literal(self.get_type())),
+        };
+
+        match self {
+            Value::Void     => Ok(AstArgument::None),
+            Value::I1(i)    => int(Some(*i as i32)),
+            Value::I2(i)    => int(Some(*i as i32)),
+            Value::I4(i)    => int(Some(*i)),
+            Value::I8(i)    => int(i32::try_from(*i).ok()),
+            Value::IL(i)    => int(i32::try_from(*i).ok()),
+            Value::U1(i)    => int(Some(*i as i32)),
+            Value::U2(i)    => int(Some(*i as i32)),
+            Value::U4(i)    => int(i32::try_from(*i).ok()),
+            Value::U8(i)    => int(i32::try_from(*i).ok()),
+            Value::UL(i)    => int(i32::try_from(*i).ok()),
+            Value::F4(f)    => Ok(AstArgument::Float(*f)),
+            Value::F8(f)    => Ok(AstArgument::Float(*f as f32)),
+            Value::Bool(b)  => Ok(AstArgument::Name(AstName::Name(b.to_string()))),
+            Value::Str(s)   => Ok(AstArgument::String(s.clone())),
+            Value::Path(p)  => Ok(AstArgument::String(p.to_string_lossy().into_owned())),
+            Value::Closure(c) => Ok(AstArgument::Block(AstBlock::Argument(c.body.as_ref().clone()))),
+            Value::Ast(a)   => match a.as_ref() {
+                AstValue::Command(c)  => Ok(AstArgument::Block(AstBlock::Evaluated(vec![c.clone()]))),
+                AstValue::Argument(a) => Ok(a.clone()),
+            },
+            _ => Err(ValueError::new_not_a_literal(self.get_type())),
+        }
+    }
+
+
+    /// Converts the value to the given type. Integers can be converted to any
+    /// integer type able to hold their value, or to floats. Floats can be
+    /// converted between widths, and strings to paths and back. Arrays are
+    /// converted item by item. The allowed conversions are the ones of
+    /// `Type::is_convertible_to`.
+    pub fn cast(&self, ty: &Type) -> Result<Value, Error> {
+        let mismatch = || ValueError::new_type_mismatch(ty, self.get_type());
+
+        if *ty == Type::Generic || *ty == self.get_type() {
+            return Ok(self.clone());
+        }
+        if !self.get_type().is_convertible_to(ty) {
+            return Err(mismatch());
+        }
+
+        if let Some(i) = self.get_wide_integer() {
+            return match ty {
+                Type::I1 => i8::try_from(i).map(Value::I1).map_err(|_| mismatch()),
+                Type::I2 => i16::try_from(i).map(Value::I2).map_err(|_| mismatch()),
+                Type::I4 => i32::try_from(i).map(Value::I4).map_err(|_| mismatch()),
+                Type::I8 => i64::try_from(i).map(Value::I8).map_err(|_| mismatch()),
+                Type::IL => isize::try_from(i).map(Value::IL).map_err(|_| mismatch()),
+                Type::U1 => u8::try_from(i).map(Value::U1).map_err(|_| mismatch()),
+                Type::U2 => u16::try_from(i).map(Value::U2).map_err(|_| mismatch()),
+                Type::U4 => u32::try_from(i).map(Value::U4).map_err(|_| mismatch()),
+                Type::U8 => u64::try_from(i).map(Value::U8).map_err(|_| mismatch()),
+                Type::UL => usize::try_from(i).map(Value::UL).map_err(|_| mismatch()),
+                Type::F4 => Ok(Value::F4(i as f32)),
+                Type::F8 => Ok(Value::F8(i as f64)),
+                _ => Err(mismatch()),
+            };
+        }
+
+        match (self, ty) {
+            (Value::F4(f), Type::F8) => Ok(Value::F8(*f as f64)),
+            (Value::F8(f), Type::F4) => Ok(Value::F4(*f as f32)),
+            (Value::Str(s), Type::Path) => Ok(Value::Path(PathBuf::from(s))),
+            (Value::Path(p), Type::Str) => Ok(Value::Str(p.to_string_lossy().into_owned())),
+            (Value::Array(_, a), Type::Array(t)) => {
+                let mut items = Vec::with_capacity(a.len());
+                for v in a {
+                    items.push(v.cast(t)?);
+                }
+                Ok(Value::Array(t.as_ref().clone(), items))
+            },
+            _ => Err(mismatch()),
+        }
+    }
+
+
+    /// Gets the default value of the given type : zero for numbers, `false`
+    /// for booleans, an empty string or an empty array. Other types have no
+    /// default value.
+    pub fn default_of(ty: &Type) -> Option<Value> {
+        match ty {
+            Type::I1 => Some(Value::I1(0)),
+            Type::I2 => Some(Value::I2(0)),
+            Type::I4 => Some(Value::I4(0)),
+            Type::I8 => Some(Value::I8(0)),
+            Type::IL => Some(Value::IL(0)),
+            Type::U1 => Some(Value::U1(0)),
+            Type::U2 => Some(Value::U2(0)),
+            Type::U4 => Some(Value::U4(0)),
+            Type::U8 => Some(Value::U8(0)),
+            Type::UL => Some(Value::UL(0)),
+            Type::F4 => Some(Value::F4(0.0)),
+            Type::F8 => Some(Value::F8(0.0)),
+            Type::Bool => Some(Value::Bool(false)),
+            Type::Str => Some(Value::Str(String::new())),
+            Type::Array(t) => Some(Value::Array(t.as_ref().clone(), Vec::new())),
+            _ => None,
+        }
+    }
+
+
+    /// Replaces the closures of the value, including the ones in arrays and
+    /// instantiated commands.
+    pub fn map_closures(self, f: &dyn Fn(&Closure) -> Closure) -> Value {
+        match self {
+            Value::Closure(c) => Value::Closure(f(&c)),
+            Value::Array(t, v) => Value::Array(t, v.into_iter().map(|v| v.map_closures(f)).collect()),
+            Value::Command(c) => Value::Command(Rc::new(c.map_closures(f))),
+            v => v,
+        }
+    }
+
+
+    /// Converts the value into a stream, with the type of its items. Arrays
+    /// are streamed item by item, and other values can't be streamed.
+    pub fn into_stream(self) -> Option<(Type, ValueStream)> {
+        match self {
+            Value::Stream(t, s) => Some((t, s)),
+            Value::Array(t, v) => Some((t, ValueStream::from_values(v))),
+            _ => None,
+        }
+    }
+
+
+    /// Gets the type of the value.
+    pub fn get_type(&self) -> Type {
+        match self {
+            Value::Void       => Type::Void,
+            Value::I1(_)      => Type::I1,
+            Value::I2(_)      => Type::I2,
+            Value::I4(_)      => Type::I4,
+            Value::I8(_)      => Type::I8,
+            Value::IL(_)      => Type::IL,
+            Value::U1(_)      => Type::U1,
+            Value::U2(_)      => Type::U2,
+            Value::U4(_)      => Type::U4,
+            Value::U8(_)      => Type::U8,
+            Value::UL(_)      => Type::UL,
+            Value::F4(_)      => Type::F4,
+            Value::F8(_)      => Type::F8,
+            Value::Bool(_)    => Type::Bool,
+            Value::Str(_)     => Type::Str,
+            Value::Path(_)    => Type::Path,
+            Value::Array(t, _)  => Type::Array(Box::new(t.clone())),
+            Value::Stream(t, _) => Type::Stream(Box::new(t.clone())),
+            Value::Closure(_) => Type::Block,
+            Value::Command(_) => Type::Command,
+            Value::Ast(_)     => Type::Ast,
+        }
+    }
+
+
+    /// Gets the value as a signed integer, whatever its width.
+    pub fn get_integer(&self) -> Option<i64> {
+        match self {
+            Value::I1(i) => Some(*i as i64),
+            Value::I2(i) => Some(*i as i64),
+            Value::I4(i) => Some(*i as i64),
+            Value::I8(i) => Some(*i),
+            Value::IL(i) => Some(*i as i64),
+            Value::U1(i) => Some(*i as i64),
+            Value::U2(i) => Some(*i as i64),
+            Value::U4(i) => Some(*i as i64),
+            Value::U8(i) => i64::try_from(*i).ok(),
+            Value::UL(i) => i64::try_from(*i).ok(),
+            _ => None,
+        }
+    }
+    /// Gets the value as an integer large enough for all the integer types.
+    fn get_wide_integer(&self) -> Option<i128> {
+        match self {
+            Value::U8(i) => Some(*i as i128),
+            Value::UL(i) => Some(*i as i128),
+            _ => self.get_integer().map(|i| i as i128),
+        }
+    }
+    /// Gets the value as a float, whatever its width.
+    pub fn get_float(&self) -> Option<f64> {
+        match self {
+            Value::F4(f) => Some(*f as f64),
+            Value::F8(f) => Some(*f),
+            _ => None,
+        }
+    }
+    /// Gets the value as a boolean.
+    pub fn get_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+    /// Gets the value as a string.
+    pub fn get_string(&self) -> Option<&String> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+    /// Gets the value as a path.
+    pub fn get_path(&self) -> Option<&PathBuf> {
+        match self {
+            Value::Path(p) => Some(p),
+            _ => None,
+        }
+    }
+    /// Gets the value as an array.
+    pub fn get_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(_, a) => Some(a),
+            _ => None,
+        }
+    }
+    /// Gets the value as a closure.
+    pub fn get_closure(&self) -> Option<&Closure> {
+        match self {
+            Value::Closure(c) => Some(c),
+            _ => None,
+        }
+    }
+    /// Gets the value as a piece of AST.
+    pub fn get_ast(&self) -> Option<&AstValue> {
+        match self {
+            Value::Ast(a) => Some(a),
+            _ => None,
+        }
+    }
+
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Void     => Ok(()),
+            Value::I1(i)    => write!(f, "{}", i),
+            Value::I2(i)    => write!(f, "{}", i),
+            Value::I4(i)    => write!(f, "{}", i),
+            Value::I8(i)    => write!(f, "{}", i),
+            Value::IL(i)    => write!(f, "{}", i),
+            Value::U1(i)    => write!(f, "{}", i),
+            Value::U2(i)    => write!(f, "{}", i),
+            Value::U4(i)    => write!(f, "{}", i),
+            Value::U8(i)    => write!(f, "{}", i),
+            Value::UL(i)    => write!(f, "{}", i),
+            Value::F4(v)    => write!(f, "{}", v),
+            Value::F8(v)    => write!(f, "{}", v),
+            Value::Bool(b)  => write!(f, "{}", b),
+            Value::Str(s)   => write!(f, "{}", s),
+            Value::Path(p)  => write!(f, "{}", p.display()),
+            Value::Array(_, a) => {
+                write!(f, "[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            },
+            Value::Stream(t, _) => write!(f, "<stream of {}>", t),
+            Value::Closure(_)   => write!(f, "<block>"),
+            Value::Command(c)   => match &c.target {
+                CommandTarget::Registered(n) => write!(f, "<command {}>", n),
+                CommandTarget::Closure(_)    => write!(f, "<command block>"),
+            },
+            Value::Ast(a)       => match a.as_ref() {
+                AstValue::Command(c)  => write!(f, "<ast {}>", c),
+                AstValue::Argument(a) => write!(f, "<ast {}>", a),
+            },
+        }
+    }
+}
+
+
+
+impl ValueStream {
+
+    /// Creates a new stream from the given iterator.
+    pub fn new(iter: impl Iterator<Item = Result<Value, Error>> + 'static) -> ValueStream {
+        ValueStream {
+            iter: Rc::new(RefCell::new(Box::new(iter))),
+        }
+    }
+    /// Creates a new stream generating the given values.
+    pub fn from_values(values: Vec<Value>) -> ValueStream {
+        ValueStream::new(values.into_iter().map(Ok))
+    }
+
+}
+
+impl Iterator for ValueStream {
+    type Item = Result<Value, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.borrow_mut().next()
+    }
+}
+
+
+
+impl Closure {
+
+    /// Creates a new closure from the given commands, capturing the given
+    /// scope.
+    pub fn new(body: Vec<AstCommand>, scope: &Scope) -> Closure {
+        Closure {
+            body: Rc::new(body),
+            scope: CapturedScope::Strong(scope.clone()),
+        }
+    }
+
+
+    /// Gets the scope captured by the closure, if it still exists.
+    pub fn get_scope(&self) -> Option<Scope> {
+        match &self.scope {
+            CapturedScope::Strong(s) => Some(s.clone()),
+            CapturedScope::Weak(w) => w.upgrade(),
+        }
+    }
+    /// Gets the closure holding the given scope weakly, if it captures it.
+    pub fn detached_from(&self, scp: &Scope) -> Closure {
+        let scope = match &self.scope {
+            CapturedScope::Strong(s) if s.ptr_eq(scp) => CapturedScope::Weak(s.downgrade()),
+            s => s.clone(),
+        };
+
+        Closure {
+            body: self.body.clone(),
+            scope,
+        }
+    }
+    /// Gets the closure holding its scope strongly again.
+    pub fn attached(&self) -> Closure {
+        let scope = match &self.scope {
+            CapturedScope::Weak(w) => w.upgrade().map(CapturedScope::Strong).unwrap_or_else(|| self.scope.clone()),
+            s => s.clone(),
+        };
+
+        Closure {
+            body: self.body.clone(),
+            scope,
+        }
+    }
+
+}
+
+
+
+impl InstancedCommand {
+
+    /// Creates a new instantiated command, without bound arguments.
+    pub fn new(target: CommandTarget) -> InstancedCommand {
+        InstancedCommand {
+            target,
+            arguments: Vec::new(),
+        }
+    }
+
+
+    /// Replaces the closures of the command, see `Value::map_closures`.
+    pub fn map_closures(&self, f: &dyn Fn(&Closure) -> Closure) -> InstancedCommand {
+        InstancedCommand {
+            target: match &self.target {
+                CommandTarget::Closure(c) => CommandTarget::Closure(f(c)),
+                t => t.clone(),
+            },
+            arguments: self.arguments.iter().map(|a| match a {
+                InstanceArgument::Value(v) => InstanceArgument::Value(v.clone().map_closures(f)),
+                a => a.clone(),
+            }).collect(),
+        }
+    }
+
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn casts_follow_the_conversion_table() {
+        let values = vec![
+            Value::I4(1), Value::U1(1), Value::F4(1.0), Value::F8(1.0), Value::Bool(true),
+            Value::Str("a".to_owned()), Value::Path(PathBuf::from("a")),
+        ];
+
+        for v in &values {
+            for t in values.iter().map(|v| v.get_type()) {
+                assert_eq!(v.cast(&t).is_ok(), v.get_type().is_convertible_to(&t), "{} to {}", v.get_type(), t);
+            }
+        }
+    }
+
+    #[test]
+    fn floats_are_not_convertible_to_integers() {
+        assert!(!Type::F8.is_convertible_to(&Type::I4));
+        assert!(Value::F8(1.0).cast(&Type::I4).is_err());
+        assert!(Value::I4(1).cast(&Type::F8).is_ok());
+    }
+
+    #[test]
+    fn integer_casts_check_the_range() {
+        assert_eq!(Value::I4(200).cast(&Type::U1).ok().map(|v| v.to_string()), Some("200".to_owned()));
+        assert!(Value::I4(300).cast(&Type::U1).is_err());
+        assert!(Value::I4(-1).cast(&Type::U8).is_err());
+        assert!(Value::Array(Type::I4, vec![Value::I4(1), Value::I4(-1)]).cast(&Type::Array(Box::new(Type::U1))).is_err());
+    }
+
+    #[test]
+    fn literals_convert_to_values_and_back() {
+        let literals = vec![
+            AstArgument::Integer(3), AstArgument::Float(1.5), AstArgument::String("a b".to_owned()),
+            AstArgument::Name(AstName::Name("true".to_owned())), AstArgument::None,
+        ];
+
+        for l in literals {
+            assert!(Value::from_ast(&l).unwrap().to_ast().unwrap() == l, "{}", l);
+        }
+        assert_eq!(Value::from_ast(&AstArgument::Name(AstName::Name("w".to_owned()))).unwrap().get_type(), Type::Str);
+        assert!(Value::from_ast(&AstArgument::Name(AstName::Variable("v".to_owned()))).is_err());
+    }
+
+    #[test]
+    fn wide_values_are_not_literals() {
+        assert!(Value::I8(i64::MAX).to_ast().is_err());
+        assert!(Value::Array(Type::I4, Vec::new()).to_ast().is_err());
+        assert!(Value::I8(5).to_ast().unwrap() == AstArgument::Integer(5));
+    }
+
+    #[test]
+    fn values_have_default_values_by_type() {
+        assert_eq!(Value::default_of(&Type::U2).map(|v| v.get_type()), Some(Type::U2));
+        assert_eq!(Value::default_of(&Type::Str).map(|v| v.to_string()), Some(String::new()));
+        assert!(Value::default_of(&Type::Block).is_none());
+    }
+}
@@ -1,77 +1,120 @@
 #![deny(missing_docs)]
+//! Variables stored in scopes.
 
 
-use crate::neoshell::parser::ast::*;
+use std::rc::Rc;
+
+use failure::Error;
+
+use super::value::*;
 
 
 /// A variable.
+#[derive(Clone)]
 pub enum Variable {
     /// The variable is a value.
-    Value(AstArgument),
+    Value(Value),
     /// The variable is an instanced command.
-    Command()
+    Command(Rc<InstancedCommand>),
 }
 
 
 
 impl Variable {
 
-    /// Gets the variable as a name.
-    pub fn get_name(&self) -> Option<&AstName> {
+    /// Creates a variable from a value. Instantiated commands are stored as
+    /// command variables.
+    pub fn from_value(v: Value) -> Variable {
+        match v {
+            Value::Command(c) => Variable::Command(c),
+            _ => Variable::Value(v),
+        }
+    }
+    /// Replaces the closures of the variable, see `Value::map_closures`.
+    pub fn map_closures(self, f: &dyn Fn(&Closure) -> Closure) -> Variable {
+        match self {
+            Variable::Value(v) => Variable::Value(v.map_closures(f)),
+            Variable::Command(c) => Variable::Command(Rc::new(c.map_closures(f))),
+        }
+    }
+    /// Converts the variable into a value.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Variable::Value(v) => v.clone(),
+            Variable::Command(c) => Value::Command(c.clone()),
+        }
+    }
+
+
+    /// Gets the type of the variable.
+    pub fn get_type(&self) -> Type {
+        match self {
+            Variable::Value(v) => v.get_type(),
+            Variable::Command(_) => Type::Command,
+        }
+    }
+    /// Converts the variable to the given type.
+    pub fn cast(&self, ty: &Type) -> Result<Variable, Error> {
+        match self {
+            Variable::Value(v) => Ok(Variable::Value(v.cast(ty)?)),
+            Variable::Command(c) if *ty == Type::Command || *ty == Type::Generic => Ok(Variable::Command(c.clone())),
+            Variable::Command(_) => Err(ValueError::new_type_mismatch(ty, Type::Command)),
+        }
+    }
+
+
+    /// Gets the variable as a value.
+    pub fn get_value(&self) -> Option<&Value> {
         if let Variable::Value(v) = self {
-            if let AstArgument::Name(n) = v {
-                return Some(n);
-            }
+            return Some(v);
+        }
+
+        None
+    }
+    /// Gets the variable as an instanced command.
+    pub fn get_command(&self) -> Option<&Rc<InstancedCommand>> {
+        if let Variable::Command(c) = self {
+            return Some(c);
         }
 
         None
     }
     /// Gets the variable as an integer.
     pub fn get_integer(&self) -> Option<&i32> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Integer(i) = v {
-                return Some(i);
-            }
+        if let Variable::Value(Value::I4(i)) = self {
+            return Some(i);
         }
 
         None
     }
     /// Gets the variable as a float.
     pub fn get_float(&self) -> Option<&f32> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Float(f) = v {
-                return Some(f);
-            }
+        if let Variable::Value(Value::F4(f)) = self {
+            return Some(f);
         }
 
         None
     }
-    /// Gets the variable as a string.
-    pub fn get_string(&self) -> Option<&String> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::String(s) = v {
-                return Some(s);
-            }
+    /// Gets the variable as a boolean.
+    pub fn get_bool(&self) -> Option<&bool> {
+        if let Variable::Value(Value::Bool(b)) = self {
+            return Some(b);
         }
 
         None
     }
-    /// Gets the variable as a switch.
-    pub fn get_switch(&self) -> Option<&AstSwitch> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Switch(s) = v {
-                return Some(s);
-            }
+    /// Gets the variable as a string.
+    pub fn get_string(&self) -> Option<&String> {
+        if let Variable::Value(Value::Str(s)) = self {
+            return Some(s);
         }
 
         None
     }
-    /// Gets the variable as a block.
-    pub fn get_block(&self) -> Option<&AstBlock> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Block(b) = v {
-                return Some(b);
-            }
+    /// Gets the variable as a closure.
+    pub fn get_closure(&self) -> Option<&Closure> {
+        if let Variable::Value(Value::Closure(c)) = self {
+            return Some(c);
         }
 
         None
@@ -79,62 +122,50 @@ impl Variable {
 
 
 
-    /// Gets the variable as a name.
-    pub fn get_name_mut(&mut self) -> Option<&mut AstName> {
+    /// Gets the variable as a value.
+    pub fn get_value_mut(&mut self) -> Option<&mut Value> {
         if let Variable::Value(v) = self {
-            if let AstArgument::Name(n) = v {
-                return Some(n);
-            }
+            return Some(v);
         }
 
         None
     }
     /// Gets the variable as an integer.
     pub fn get_integer_mut(&mut self) -> Option<&mut i32> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Integer(i) = v {
-                return Some(i);
-            }
+        if let Variable::Value(Value::I4(i)) = self {
+            return Some(i);
         }
 
         None
     }
     /// Gets the variable as a float.
     pub fn get_float_mut(&mut self) -> Option<&mut f32> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Float(f) = v {
-                return Some(f);
-            }
+        if let Variable::Value(Value::F4(f)) = self {
+            return Some(f);
         }
 
         None
     }
-    /// Gets the variable as a string.
-    pub fn get_string_mut(&mut self) -> Option<&mut String> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::String(s) = v {
-                return Some(s);
-            }
+    /// Gets the variable as a boolean.
+    pub fn get_bool_mut(&mut self) -> Option<&mut bool> {
+        if let Variable::Value(Value::Bool(b)) = self {
+            return Some(b);
         }
 
         None
     }
-    /// Gets the variable as a switch.
-    pub fn get_switch_mut(&mut self) -> Option<&mut AstSwitch> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Switch(s) = v {
-                return Some(s);
-            }
+    /// Gets the variable as a string.
+    pub fn get_string_mut(&mut self) -> Option<&mut String> {
+        if let Variable::Value(Value::Str(s)) = self {
+            return Some(s);
         }
 
         None
     }
-    /// Gets the variable as a block.
-    pub fn get_block_mut(&mut self) -> Option<&mut AstBlock> {
-        if let Variable::Value(v) = self {
-            if let AstArgument::Block(b) = v {
-                return Some(b);
-            }
+    /// Gets the variable as a closure.
+    pub fn get_closure_mut(&mut self) -> Option<&mut Closure> {
+        if let Variable::Value(Value::Closure(c)) = self {
+            return Some(c);
         }
 
         None